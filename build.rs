@@ -1,9 +1,14 @@
 use fs_extra::copy_items;
 use fs_extra::dir::CopyOptions;
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn main() -> anyhow::Result<()> {
+    // Nothing to copy when libcudaimg has not been built, the CPU implementation is used then
+    if !Path::new("data").exists() {
+        return Ok(());
+    }
+
     // Prepare what to copy and how
     let mut copy_options = CopyOptions::new();
     copy_options.overwrite = true;
@@ -1,691 +1,1762 @@
-use crate::cudaimg::ImageProcessingFunction;
-use crate::{ImageModifiers, ImageProcessingTask, ShowResizedTexture, TextureMap, ToColorImage};
-use image::DynamicImage;
-use libloading::Library;
+use crate::backend::ImageBackend;
+use crate::batch::{self, BatchFileResult};
+use crate::cpuimg::CpuBackend;
+use crate::encoding::{
+    self, EncoderOptions, JpegSubsampling, PngCompression, PngFilter, TiffCompression,
+};
+use crate::formats;
+use crate::histogram::{self, ChannelHistograms, HistogramChannel};
+use crate::history::{History, HistoryEntry};
+use crate::metadata::{self, ImageMetadata};
+use crate::operations::{self, ParamSpec, OPERATIONS};
+use crate::pipeline::Pipeline;
+use crate::recipe::Recipe;
+use crate::{
+    BatchFolder, HistogramSource, ImageModifiers, ImageProcessingTask, OperationGuard,
+    ShowResizedTexture, TextureMap, ToColorImage,
+};
+use image::{DynamicImage, ImageFormat};
+use log::{error, warn};
 use rfd::FileDialog;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
 use tokio::sync::mpsc;
-use tokio::sync::Mutex as TokioMutex;
 
 #[allow(unused)]
 pub struct MyApp {
-    libcudaimg: Arc<TokioMutex<Library>>,
-    image: Option<DynamicImage>,
+    backend: Arc<dyn ImageBackend>,
+    image: Option<Arc<DynamicImage>>,
     modified_image: Option<DynamicImage>,
+    histograms: Option<ChannelHistograms>,
+    histogram_channels: [bool; HistogramChannel::ALL.len()],
+    histogram_beside: bool,
+    live_histogram: LiveHistogramPanel,
+    pipeline: Pipeline,
+    pipeline_dirty: bool,
+    pipeline_generation: u64,
+    history: History,
+    show_history: bool,
+    batch: BatchWindow,
+    save: SaveWindow,
     image_path_info: Option<PathBuf>,
+    image_metadata: ImageMetadata,
     texture_map: TextureMap,
     image_modifiers: ImageModifiers,
     last_operation_duration: Option<std::time::Duration>,
     op_in_progress: Arc<Mutex<bool>>,
+    errors: Vec<String>,
     tx: mpsc::Sender<ImageProcessingTask>,
     rx: mpsc::Receiver<ImageProcessingTask>,
 }
 
 impl MyApp {
-    pub fn new(libcudaimg: Library) -> Self {
+    pub fn new(backend: Arc<dyn ImageBackend>) -> Self {
         let (tx, rx) = mpsc::channel(32);
 
         Self {
-            libcudaimg: Arc::new(TokioMutex::new(libcudaimg)),
+            backend,
             image: None,
             modified_image: None,
+            histograms: None,
+            histogram_channels: [true; HistogramChannel::ALL.len()],
+            histogram_beside: false,
+            live_histogram: LiveHistogramPanel::default(),
+            pipeline: Pipeline::default(),
+            pipeline_dirty: false,
+            pipeline_generation: 0,
+            history: History::default(),
+            show_history: true,
+            batch: BatchWindow::default(),
+            save: SaveWindow::default(),
             image_path_info: None,
+            image_metadata: ImageMetadata::default(),
             texture_map: TextureMap::default(),
             image_modifiers: ImageModifiers::default(),
             last_operation_duration: None,
             op_in_progress: Arc::new(Mutex::new(false)),
+            errors: Vec::new(),
             tx,
             rx,
         }
     }
 }
 
-impl MyApp {
-    fn draw_top_panel(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
-            // Menu bar
-            egui::menu::bar(ui, |ui| {
-                // File menu
-                ui.menu_button("File", |ui| {
-                    // Open image button
-                    if ui.button("Open Image").clicked() {
-                        self.image = None;
-                        self.modified_image = None;
-                        self.image_path_info = None;
-                        self.texture_map = TextureMap::default();
+/// Shortcut to undo the last change.
+const UNDO_SHORTCUT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
+
+/// Shortcut to redo the last undone change.
+const REDO_SHORTCUT: egui::KeyboardShortcut = egui::KeyboardShortcut::new(
+    egui::Modifiers::COMMAND.plus(egui::Modifiers::SHIFT),
+    egui::Key::Z,
+);
+
+/// Send the result of an async task to the GUI.
+///
+/// # Arguments
+///
+/// * `tx` - The sender of the GUI's task channel.
+/// * `task` - The result to send.
+async fn send_task(tx: &mpsc::Sender<ImageProcessingTask>, task: ImageProcessingTask) {
+    if tx.send(task).await.is_err() {
+        // The receiver is only dropped when the app is closing
+        warn!("Failed to send the task result, the app is closing");
+    }
+}
 
-                        let tx = self.tx.clone();
-                        let op_in_progress = Arc::clone(&self.op_in_progress);
+/// Add a slider for a parameter of an operation.
+///
+/// # Arguments
+///
+/// * `ui` - The ui to add the slider to.
+/// * `param` - The description of the parameter.
+/// * `value` - The value of the parameter.
+fn param_slider(ui: &mut egui::Ui, param: &ParamSpec, value: &mut f32) -> egui::Response {
+    let slider = egui::Slider::new(value, param.range.clone()).text(param.name);
+    ui.add(if param.integer {
+        slider.integer()
+    } else {
+        slider
+    })
+}
 
-                        tokio::spawn(async move {
-                            // Wait for the previous operation to finish
-                            while *op_in_progress.lock().unwrap() {
-                                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                            }
+/// The settings and progress of the batch processing window.
+///
+/// # Fields
+///
+/// * `open` - Whether the window is shown.
+/// * `input` - The folder or glob pattern of the images to process.
+/// * `output_dir` - The folder the results are written to.
+/// * `name_template` - The template of the output file names.
+/// * `jobs` - The number of images processed at a time.
+/// * `strip_metadata` - Whether to drop the EXIF, XMP and ICC metadata of the images.
+/// * `running` - Whether a batch is running.
+/// * `total` - The number of images in the running or last batch.
+/// * `results` - The results of the images that finished, in the order they finished.
+/// * `duration` - How long the last batch took, once it finished.
+struct BatchWindow {
+    open: bool,
+    input: String,
+    output_dir: String,
+    name_template: String,
+    jobs: usize,
+    strip_metadata: bool,
+    running: bool,
+    total: usize,
+    results: Vec<BatchFileResult>,
+    duration: Option<std::time::Duration>,
+}
 
-                            *op_in_progress.lock().unwrap() = true;
+impl Default for BatchWindow {
+    fn default() -> Self {
+        Self {
+            open: false,
+            input: String::new(),
+            output_dir: String::new(),
+            name_template: batch::DEFAULT_NAME_TEMPLATE.to_string(),
+            jobs: batch::default_jobs(),
+            strip_metadata: false,
+            running: false,
+            total: 0,
+            results: Vec::new(),
+            duration: None,
+        }
+    }
+}
 
-                            if let Some(path) = FileDialog::new()
-                                .add_filter("Image Files", &["jpg", "jpeg", "png"])
-                                .pick_file()
-                            {
-                                let image = image::open(&path).expect("Failed to open image");
-                                tx.send(ImageProcessingTask::OpenImage { image, path })
-                                    .await
-                                    .unwrap();
-                            }
+/// The settings of the save image window.
+///
+/// # Fields
+///
+/// * `open` - Whether the window is shown.
+/// * `format` - The format the image is saved in.
+/// * `options` - The options of the encoders.
+/// * `keep_metadata` - Whether the EXIF, XMP and ICC metadata of the source are written.
+/// * `estimate` - The size of the encoded image, or why encoding failed.
+/// * `estimated` - The settings and image generation the estimate was made for.
+/// * `estimating` - Whether an estimate is being computed.
+/// * `image_generation` - Incremented whenever the modified image changes.
+struct SaveWindow {
+    open: bool,
+    format: ImageFormat,
+    options: EncoderOptions,
+    keep_metadata: bool,
+    estimate: Option<Result<usize, String>>,
+    estimated: Option<(ImageFormat, EncoderOptions, bool, u64)>,
+    estimating: bool,
+    image_generation: u64,
+}
 
-                            *op_in_progress.lock().unwrap() = false;
-                        });
+impl Default for SaveWindow {
+    fn default() -> Self {
+        Self {
+            open: false,
+            format: ImageFormat::Png,
+            options: EncoderOptions::default(),
+            keep_metadata: true,
+            estimate: None,
+            estimated: None,
+            estimating: false,
+            image_generation: 0,
+        }
+    }
+}
 
-                        ui.close_menu();
-                    }
+impl SaveWindow {
+    /// Get the settings the estimate has to be made for.
+    fn estimate_key(&self) -> (ImageFormat, EncoderOptions, bool, u64) {
+        (
+            self.format,
+            self.options,
+            self.keep_metadata,
+            self.image_generation,
+        )
+    }
+}
 
-                    // Save image button
-                    if ui.button("Save image").clicked() {
-                        if self.modified_image.is_some() {
-                            let op_in_progress = Arc::clone(&self.op_in_progress);
+/// The sides of the window the live histogram panel can be docked to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HistogramDock {
+    Right,
+    Bottom,
+}
 
-                            let modified_image = self.modified_image.clone(); // TODO: avoid clone
-                            let image_path_info = self.image_path_info.clone(); // TODO: avoid clone
+/// The state of the live histogram panel.
+///
+/// # Fields
+///
+/// * `open` - Whether the panel is shown.
+/// * `dock` - The side of the window the panel is docked to.
+/// * `original` - The histograms of the original image.
+/// * `modified` - The histograms of the modified image.
+/// * `original_source` - The original image the histograms were last requested for.
+/// * `modified_generation` - The generation of the modified image they were last requested for.
+struct LiveHistogramPanel {
+    open: bool,
+    dock: HistogramDock,
+    original: Option<ChannelHistograms>,
+    modified: Option<ChannelHistograms>,
+    original_source: Option<Arc<DynamicImage>>,
+    modified_generation: Option<u64>,
+}
 
-                            tokio::spawn(async move {
-                                // Wait for the previous operation to finish
-                                while *op_in_progress.lock().unwrap() {
-                                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                                }
+impl Default for LiveHistogramPanel {
+    fn default() -> Self {
+        Self {
+            open: true,
+            dock: HistogramDock::Bottom,
+            original: None,
+            modified: None,
+            original_source: None,
+            modified_generation: None,
+        }
+    }
+}
 
-                                *op_in_progress.lock().unwrap() = true;
-
-                                if let Some(image) = modified_image {
-                                    let exts = if let Some(impath) = &image_path_info {
-                                        vec![impath
-                                            .extension()
-                                            .unwrap()
-                                            .to_str()
-                                            .unwrap()
-                                            .to_string()]
-                                    } else {
-                                        vec![
-                                            "jpg".to_string(),
-                                            "jpeg".to_string(),
-                                            "png".to_string(),
-                                        ]
-                                    };
-
-                                    if let Some(path) = FileDialog::new()
-                                        .add_filter("Image Files", exts.as_slice())
-                                        .save_file()
-                                    {
-                                        image.save(&path).expect("Failed to save image");
-                                    }
-                                }
+/// Format a file size for display, e.g. `1.5 MiB`.
+fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
 
-                                *op_in_progress.lock().unwrap() = false;
-                            });
-                        }
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
 
-                        ui.close_menu();
-                    }
-                });
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
 
-                // Tools menu
-                ui.menu_button("Tools", |ui| {
-                    // Invert image
-                    if ui.button("Invert image").clicked() {
-                        self.texture_map.modified_image = None;
+/// Format a histogram statistic for display, whole numbers without decimals.
+fn format_stat(value: f64) -> String {
+    if value.fract() == 0.0 {
+        format!("{}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
 
-                        let tx = self.tx.clone();
-                        let op_in_progress = Arc::clone(&self.op_in_progress);
+/// Draw the histograms of channels over each other as a bar chart.
+/// Hovering the chart shows the value of the bin under the pointer and its counts.
+///
+/// # Arguments
+///
+/// * `ui` - The ui to draw the chart in.
+/// * `histograms` - The histograms to draw.
+/// * `channels` - The channels to draw, each in its own color.
+/// * `size` - The size of the chart.
+fn draw_histogram_chart(
+    ui: &mut egui::Ui,
+    histograms: &ChannelHistograms,
+    channels: &[HistogramChannel],
+    size: egui::Vec2,
+) -> egui::Response {
+    let (rect, response) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+
+    let bins = histograms.luma.data.len();
+    let bin_width = rect.width() / bins as f32;
+    let max_count = channels
+        .iter()
+        .flat_map(|&channel| histograms.get(channel).data.iter().copied())
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    for &channel in channels {
+        let [r, g, b] = channel.color();
+        let color = egui::Color32::from_rgba_unmultiplied(r, g, b, 110);
+
+        for (i, &count) in histograms.get(channel).data.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
 
-                        let image = self.image.clone(); // TODO: avoid clone
-                        let library = Arc::clone(&self.libcudaimg);
+            let x = rect.left() + i as f32 * bin_width;
+            let top = rect.bottom() - rect.height() * count as f32 / max_count as f32;
+            painter.rect_filled(
+                egui::Rect::from_min_max(
+                    egui::pos2(x, top),
+                    egui::pos2(x + bin_width, rect.bottom()),
+                ),
+                0.0,
+                color,
+            );
+        }
+    }
 
-                        tokio::spawn(async move {
-                            // Wait for the previous operation to finish
-                            while *op_in_progress.lock().unwrap() {
-                                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                            }
+    painter.text(
+        rect.left_top() + egui::vec2(4.0, 2.0),
+        egui::Align2::LEFT_TOP,
+        max_count.to_string(),
+        egui::FontId::proportional(11.0),
+        ui.visuals().weak_text_color(),
+    );
+
+    let Some(pos) = response.hover_pos() else {
+        return response;
+    };
+
+    // Highlight the bin under the pointer and show its counts
+    let bin = (((pos.x - rect.left()) / bin_width).max(0.0) as usize).min(bins - 1);
+    painter.vline(
+        rect.left() + (bin as f32 + 0.5) * bin_width,
+        rect.y_range(),
+        ui.visuals().widgets.hovered.fg_stroke,
+    );
+
+    response.on_hover_ui_at_pointer(|ui| {
+        ui.label(format!("Value: {}", bin));
+
+        for &channel in channels {
+            ui.label(format!(
+                "{}: {}",
+                channel.label(),
+                histograms.get(channel).data[bin]
+            ));
+        }
+    })
+}
 
-                            *op_in_progress.lock().unwrap() = true;
+/// Add a file dialog filter for every format, preceded by one matching all of them.
+///
+/// # Arguments
+///
+/// * `dialog` - The file dialog to add the filters to.
+/// * `formats` - The formats to add filters for.
+fn add_format_filters(mut dialog: FileDialog, formats: &[ImageFormat]) -> FileDialog {
+    dialog = dialog.add_filter("All Images", &formats::extensions(formats));
+
+    for &format in formats {
+        dialog = dialog.add_filter(formats::format_name(format), format.extensions_str());
+    }
 
-                            if let Some(image) = image {
-                                let library = library.lock().await;
+    dialog
+}
 
-                                let start = std::time::Instant::now();
+/// An edit of the pipeline's structure requested from the pipeline panel.
+enum StepAction {
+    MoveUp(usize),
+    MoveDown(usize),
+    Remove(usize),
+}
 
-                                let modified_image = crate::cudaimg::process_image(
-                                    &library,
-                                    &image,
-                                    ImageProcessingFunction::Invert,
-                                )
-                                .expect("Failed to invert image");
-
-                                let duration = start.elapsed();
-                                tx.send(ImageProcessingTask::OperationFinished {
-                                    image: modified_image,
-                                    duration,
-                                })
-                                .await
-                                .unwrap();
-                            }
+impl MyApp {
+    /// Check whether an operation is running in an async task.
+    fn is_busy(&self) -> bool {
+        *self
+            .op_in_progress
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
 
-                            *op_in_progress.lock().unwrap() = false;
-                        });
+    /// Get the channels whose histograms are shown.
+    fn visible_histogram_channels(&self) -> Vec<HistogramChannel> {
+        HistogramChannel::ALL
+            .into_iter()
+            .zip(self.histogram_channels)
+            .filter_map(|(channel, visible)| visible.then_some(channel))
+            .collect()
+    }
 
-                        ui.close_menu();
-                    }
+    /// Compute the histograms of the original image in an async task.
+    /// The chart is shown instead of or beside the modified image once the task finishes.
+    fn compute_histograms(&mut self) {
+        let tx = self.tx.clone();
+        let op_in_progress = Arc::clone(&self.op_in_progress);
 
-                    // Gamma transformation
-                    ui.menu_button("Gamma transformation", |ui| {
-                        if ui.button("Run").clicked() {
-                            self.texture_map.modified_image = None;
+        let image = self.image.clone();
+        let backend = Arc::clone(&self.backend);
 
-                            let tx = self.tx.clone();
-                            let op_in_progress = Arc::clone(&self.op_in_progress);
+        tokio::spawn(async move {
+            // Wait for the previous operation to finish
+            let _guard = OperationGuard::acquire(op_in_progress).await;
 
-                            let image = self.image.clone(); // TODO: avoid clone
-                            let library = Arc::clone(&self.libcudaimg);
-                            let gamma = self.image_modifiers.gamma;
+            if let Some(image) = image {
+                let start = std::time::Instant::now();
 
-                            tokio::spawn(async move {
-                                // Wait for the previous operation to finish
-                                while *op_in_progress.lock().unwrap() {
-                                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                                }
+                let result = tokio::task::spawn_blocking(move || {
+                    histogram::image_histograms(backend.as_ref(), &image)
+                })
+                .await
+                .unwrap_or_else(|e| {
+                    Err(anyhow::anyhow!("Computing the histograms panicked: {}", e))
+                });
 
-                                *op_in_progress.lock().unwrap() = true;
+                let task = match result {
+                    Ok(histograms) => ImageProcessingTask::HistogramComputed {
+                        histograms,
+                        duration: start.elapsed(),
+                    },
+                    Err(e) => ImageProcessingTask::failed("Failed to generate the histogram", e),
+                };
 
-                                if let Some(image) = image {
-                                    let library = library.lock().await;
+                send_task(&tx, task).await;
+            }
+        });
+    }
 
-                                    let start = std::time::Instant::now();
+    /// Compute the histograms of an image for the live histogram panel in an async task.
+    /// They are computed on the CPU, so they do not wait for the running operation.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The image the histograms are computed for, to drop outdated results.
+    /// * `image` - The image to compute the histograms of.
+    fn compute_live_histograms(&self, source: HistogramSource, image: Arc<DynamicImage>) {
+        let tx = self.tx.clone();
+
+        tokio::spawn(async move {
+            let histograms = tokio::task::spawn_blocking(move || {
+                histogram::image_histograms(&CpuBackend, &image).map_err(|e| format!("{:#}", e))
+            })
+            .await
+            .unwrap_or_else(|e| Err(format!("Computing the histograms panicked: {}", e)));
+
+            send_task(
+                &tx,
+                ImageProcessingTask::LiveHistogramComputed { source, histograms },
+            )
+            .await;
+        });
+    }
 
-                                    let modified_image = crate::cudaimg::process_image(
-                                        &library,
-                                        &image,
-                                        ImageProcessingFunction::GammaTransform(gamma),
-                                    )
-                                    .expect("Failed to use gamma transformation on image");
+    /// Recalculate the histograms of the live histogram panel for images that changed.
+    fn update_live_histograms(&mut self) {
+        if !self.live_histogram.open {
+            return;
+        }
 
-                                    let duration = start.elapsed();
-                                    tx.send(ImageProcessingTask::OperationFinished {
-                                        image: modified_image,
-                                        duration,
-                                    })
-                                    .await
-                                    .unwrap();
-                                }
+        match &self.image {
+            Some(image)
+                if !self
+                    .live_histogram
+                    .original_source
+                    .as_ref()
+                    .is_some_and(|source| Arc::ptr_eq(source, image)) =>
+            {
+                let image = Arc::clone(image);
+                self.live_histogram.original_source = Some(Arc::clone(&image));
+                self.compute_live_histograms(HistogramSource::Original(Arc::clone(&image)), image);
+            }
+            Some(_) => {}
+            None => {
+                self.live_histogram.original = None;
+                self.live_histogram.original_source = None;
+            }
+        }
 
-                                *op_in_progress.lock().unwrap() = false;
-                            });
+        let generation = self.save.image_generation;
+        match &self.modified_image {
+            Some(image) if self.live_histogram.modified_generation != Some(generation) => {
+                let image = Arc::new(image.clone());
+                self.live_histogram.modified_generation = Some(generation);
+                self.compute_live_histograms(HistogramSource::Modified(generation), image);
+            }
+            Some(_) => {}
+            None => {
+                self.live_histogram.modified = None;
+                self.live_histogram.modified_generation = None;
+            }
+        }
+    }
 
-                            ui.close_menu();
-                        }
+    /// Export the bins and statistics of the histograms with a file dialog in an async task.
+    fn export_histograms(&self) {
+        let Some(histograms) = self.histograms.clone() else {
+            return;
+        };
+
+        let tx = self.tx.clone();
+
+        tokio::spawn(async move {
+            if let Some(path) = FileDialog::new()
+                .add_filter("CSV", &["csv"])
+                .add_filter("JSON", &["json"])
+                .set_file_name("histogram.csv")
+                .save_file()
+            {
+                if let Err(e) = histograms.export(&path) {
+                    let task = ImageProcessingTask::failed("Failed to export the histogram", e);
+                    send_task(&tx, task).await;
+                }
+            }
+        });
+    }
 
-                        // Gamma slider
-                        ui.label("Gamma");
-                        ui.add(egui::Slider::new(
-                            &mut self.image_modifiers.gamma,
-                            0.1..=5.0,
-                        ));
-                    });
+    /// Draw the chart of the histograms with its channel toggles and statistics.
+    fn draw_histogram_section(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.strong("Histogram");
 
-                    // Logarithmic transformation
-                    ui.menu_button("Logarithmic transformation", |ui| {
-                        if ui.button("Run").clicked() {
-                            self.texture_map.modified_image = None;
+            for (channel, visible) in HistogramChannel::ALL
+                .iter()
+                .zip(self.histogram_channels.iter_mut())
+            {
+                ui.checkbox(visible, channel.label());
+            }
 
-                            let tx = self.tx.clone();
-                            let op_in_progress = Arc::clone(&self.op_in_progress);
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui.button("Close").clicked() {
+                    self.histograms = None;
+                }
 
-                            let image = self.image.clone(); // TODO: avoid clone
-                            let library = Arc::clone(&self.libcudaimg);
-                            let log_base = self.image_modifiers.log_base;
+                ui.checkbox(&mut self.histogram_beside, "Beside the image")
+                    .on_hover_text("Show the modified image below the histogram");
+            });
+        });
 
-                            tokio::spawn(async move {
-                                // Wait for the previous operation to finish
-                                while *op_in_progress.lock().unwrap() {
-                                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                                }
+        let channels = self.visible_histogram_channels();
+        let Some(histograms) = &self.histograms else {
+            return;
+        };
+
+        let width = ui.available_width();
+        let height = (width * 0.5).min(ui.available_height() * 0.5);
+        draw_histogram_chart(ui, histograms, &channels, egui::vec2(width, height));
+
+        ui.collapsing("Statistics", |ui| {
+            self.draw_histogram_stats(ui);
 
-                                *op_in_progress.lock().unwrap() = true;
+            if ui
+                .button("Export...")
+                .on_hover_text("Save the bins and statistics as CSV or JSON")
+                .clicked()
+            {
+                self.export_histograms();
+            }
+        });
+    }
 
-                                if let Some(image) = image {
-                                    let library = library.lock().await;
+    /// Draw the statistics of the histograms, a column per channel.
+    fn draw_histogram_stats(&self, ui: &mut egui::Ui) {
+        let Some(histograms) = &self.histograms else {
+            return;
+        };
+
+        let stats: Vec<_> = HistogramChannel::ALL
+            .iter()
+            .map(|&channel| histograms.stats(channel).rows())
+            .collect();
+
+        egui::Grid::new("histogram_stats")
+            .striped(true)
+            .num_columns(HistogramChannel::ALL.len() + 1)
+            .show(ui, |ui| {
+                ui.label("");
+                for channel in HistogramChannel::ALL {
+                    ui.strong(channel.label());
+                }
+                ui.end_row();
 
-                                    let start = std::time::Instant::now();
+                for (i, (label, _)) in stats[0].iter().enumerate() {
+                    ui.label(label);
+                    for rows in &stats {
+                        ui.label(format_stat(rows[i].1));
+                    }
+                    ui.end_row();
+                }
+            });
+    }
 
-                                    let modified_image = crate::cudaimg::process_image(
-                                        &library,
-                                        &image,
-                                        ImageProcessingFunction::LogarithmicTransform(log_base),
-                                    )
-                                    .expect("Failed to use Logarithmic transformation on image");
+    /// Run the pipeline on the original image in an async task.
+    /// Results of earlier runs that finish later are dropped using the generation counter.
+    fn run_pipeline(&mut self) {
+        self.pipeline_dirty = false;
+        self.pipeline_generation += 1;
 
-                                    let duration = start.elapsed();
-                                    tx.send(ImageProcessingTask::OperationFinished {
-                                        image: modified_image,
-                                        duration,
-                                    })
-                                    .await
-                                    .unwrap();
-                                }
+        let Some(image) = self.image.clone() else {
+            return;
+        };
 
-                                *op_in_progress.lock().unwrap() = false;
-                            });
+        if !self.pipeline.has_enabled_steps() {
+            self.modified_image = None;
+            self.texture_map.modified_image = None;
+            return;
+        }
 
-                            ui.close_menu();
-                        }
+        let tx = self.tx.clone();
+        let op_in_progress = Arc::clone(&self.op_in_progress);
 
-                        // Logarithmic base slider
-                        ui.label("Base");
-                        ui.add(egui::Slider::new(
-                            &mut self.image_modifiers.log_base,
-                            0.1f32..=10f32,
-                        ));
-                    });
+        let backend = Arc::clone(&self.backend);
+        let pipeline = self.pipeline.clone();
+        let generation = self.pipeline_generation;
 
-                    // Grayscale conversion
-                    if ui.button("Grayscale conversion").clicked() {
-                        self.texture_map.modified_image = None;
+        tokio::spawn(async move {
+            // Wait for the previous operation to finish
+            let _guard = OperationGuard::acquire(op_in_progress).await;
 
-                        let tx = self.tx.clone();
-                        let op_in_progress = Arc::clone(&self.op_in_progress);
+            let start = std::time::Instant::now();
 
-                        let image = self.image.clone(); // TODO: avoid clone
-                        let library = Arc::clone(&self.libcudaimg);
+            let result =
+                tokio::task::spawn_blocking(move || pipeline.run(backend.as_ref(), &image))
+                    .await
+                    .unwrap_or_else(|e| Err(anyhow::anyhow!("The pipeline panicked: {}", e)));
 
-                        tokio::spawn(async move {
-                            // Wait for the previous operation to finish
-                            while *op_in_progress.lock().unwrap() {
-                                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                            }
+            let task = match result {
+                Ok(image) => ImageProcessingTask::PipelineFinished {
+                    image,
+                    duration: start.elapsed(),
+                    generation,
+                },
+                Err(e) => ImageProcessingTask::failed("Failed to run the pipeline", e),
+            };
 
-                            *op_in_progress.lock().unwrap() = true;
+            send_task(&tx, task).await;
+        });
+    }
 
-                            if let Some(image) = image {
-                                let library = library.lock().await;
+    /// Record the current image and pipeline in the history.
+    ///
+    /// # Arguments
+    ///
+    /// * `label` - A description of the change that led to the current state.
+    /// * `merge_key` - Consecutive changes with the same key are recorded as one.
+    fn record_history(&mut self, label: String, merge_key: Option<String>) {
+        let Some(image) = &self.image else {
+            return;
+        };
+
+        let entry = HistoryEntry {
+            label,
+            source: Arc::clone(image),
+            pipeline: self.pipeline.clone(),
+            duration: None,
+        };
+
+        self.history.record(entry, merge_key);
+    }
 
-                                let start = std::time::Instant::now();
+    /// Restore the image and pipeline of a state from the history.
+    fn restore_history_entry(&mut self, entry: HistoryEntry) {
+        let same_source = self
+            .image
+            .as_ref()
+            .is_some_and(|image| Arc::ptr_eq(image, &entry.source));
 
-                                let modified_image = crate::cudaimg::process_image(
-                                    &library,
-                                    &image,
-                                    ImageProcessingFunction::Grayscale,
-                                )
-                                .expect("Failed to convert to grayscale");
-
-                                let duration = start.elapsed();
-                                tx.send(ImageProcessingTask::OperationFinished {
-                                    image: modified_image,
-                                    duration,
-                                })
-                                .await
-                                .unwrap();
-                            }
+        if !same_source {
+            self.image = Some(entry.source);
+            self.texture_map.original_image = None;
+        }
 
-                            *op_in_progress.lock().unwrap() = false;
-                        });
+        self.pipeline = entry.pipeline;
+        self.pipeline_dirty = true;
+    }
 
-                        ui.close_menu();
-                    }
+    /// Go back to the previous state of the history.
+    fn undo(&mut self) {
+        if let Some(entry) = self.history.undo().cloned() {
+            self.restore_history_entry(entry);
+        }
+    }
 
-                    // Generate histogram
-                    if ui.button("Generate histogram").clicked() {
-                        self.texture_map.modified_image = None;
+    /// Go forward to the next undone state of the history.
+    fn redo(&mut self) {
+        if let Some(entry) = self.history.redo().cloned() {
+            self.restore_history_entry(entry);
+        }
+    }
+
+    /// Run the pipeline on every image of the batch window's input in an async task.
+    /// The progress is reported to the batch window as the images finish.
+    fn start_batch(&mut self) {
+        self.batch.running = true;
+        self.batch.total = 0;
+        self.batch.results.clear();
+        self.batch.duration = None;
+
+        let tx = self.tx.clone();
+        let backend = Arc::clone(&self.backend);
+        let pipeline = self.pipeline.clone();
+        let input = self.batch.input.clone();
+        let output_dir = PathBuf::from(&self.batch.output_dir);
+        let name_template = self.batch.name_template.clone();
+        let jobs = self.batch.jobs;
+        let strip_metadata = self.batch.strip_metadata;
+
+        tokio::spawn(async move {
+            let files = match batch::collect_inputs(&input)
+                .and_then(|inputs| batch::plan_outputs(&inputs, &output_dir, &name_template))
+            {
+                Ok(files) => files,
+                Err(e) => {
+                    let report = Err(format!("Failed to start the batch: {:#}", e));
+                    send_task(&tx, ImageProcessingTask::BatchFinished { report }).await;
+                    return;
+                }
+            };
+
+            send_task(
+                &tx,
+                ImageProcessingTask::BatchStarted { total: files.len() },
+            )
+            .await;
+
+            // Forward the results as they finish, the batch reports them synchronously
+            let (progress_tx, mut progress_rx) = mpsc::unbounded_channel();
+            let forward_tx = tx.clone();
+            let forward = tokio::spawn(async move {
+                while let Some(result) = progress_rx.recv().await {
+                    send_task(
+                        &forward_tx,
+                        ImageProcessingTask::BatchFileFinished { result },
+                    )
+                    .await;
+                }
+            });
+
+            let report = batch::run_batch(
+                backend,
+                pipeline,
+                files,
+                jobs,
+                strip_metadata,
+                move |result| {
+                    let _ = progress_tx.send(result.clone());
+                },
+            )
+            .await;
+
+            // The sender was dropped with the callback, so the forwarding ends
+            let _ = forward.await;
+
+            let report = Ok(report);
+            send_task(&tx, ImageProcessingTask::BatchFinished { report }).await;
+        });
+    }
+
+    /// Get the metadata written when saving, unless the save window strips it.
+    fn save_metadata(&self) -> ImageMetadata {
+        if self.save.keep_metadata {
+            self.image_metadata.clone()
+        } else {
+            ImageMetadata::default()
+        }
+    }
+
+    /// Encode the modified image with the settings of the save window in an async task,
+    /// to show the size of the file before it is written.
+    fn estimate_save_size(&mut self) {
+        let Some(image) = self.modified_image.clone() else {
+            return;
+        };
+
+        let tx = self.tx.clone();
+        let metadata = self.save_metadata();
+        let (format, options, keep_metadata, generation) = self.save.estimate_key();
+
+        self.save.estimating = true;
+        self.save.estimated = Some((format, options, keep_metadata, generation));
+
+        tokio::spawn(async move {
+            let size = tokio::task::spawn_blocking(move || {
+                encoding::encode(&image, format, &options, &metadata)
+                    .map(|bytes| bytes.len())
+                    .map_err(|e| format!("{:#}", e))
+            })
+            .await
+            .unwrap_or_else(|e| Err(format!("Encoding panicked: {}", e)));
+
+            send_task(
+                &tx,
+                ImageProcessingTask::SizeEstimated {
+                    format,
+                    options,
+                    keep_metadata,
+                    generation,
+                    size,
+                },
+            )
+            .await;
+        });
+    }
+
+    /// Ask for a file name and save the modified image in a format in an async task.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format to save the image in, the extension of the file is set to match.
+    /// * `options` - The options of the encoder.
+    /// * `metadata` - The metadata written into the file, if the format supports it.
+    fn save_image(&self, format: ImageFormat, options: EncoderOptions, metadata: ImageMetadata) {
+        let Some(image) = self.modified_image.clone() else {
+            return;
+        };
+
+        let tx = self.tx.clone();
+        let op_in_progress = Arc::clone(&self.op_in_progress);
+
+        let file_name = self
+            .image_path_info
+            .as_ref()
+            .and_then(|path| path.file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "image".to_string());
+        let file_name = formats::with_format_extension(Path::new(&file_name), format);
+
+        tokio::spawn(async move {
+            // Wait for the previous operation to finish
+            let _guard = OperationGuard::acquire(op_in_progress).await;
+
+            if let Some(path) = FileDialog::new()
+                .add_filter(formats::format_name(format), format.extensions_str())
+                .set_file_name(file_name.to_string_lossy())
+                .save_file()
+            {
+                let path = formats::with_format_extension(&path, format);
+
+                let result = tokio::task::spawn_blocking(move || {
+                    encoding::save(&image, &path, format, &options, &metadata)
+                })
+                .await
+                .unwrap_or_else(|e| Err(anyhow::anyhow!("Saving panicked: {}", e)));
+
+                if let Err(e) = result {
+                    send_task(
+                        &tx,
+                        ImageProcessingTask::failed("Failed to save the image", e),
+                    )
+                    .await;
+                }
+            }
+        });
+    }
+
+    /// Pick a folder of the batch window with a dialog in an async task.
+    fn pick_batch_folder(&self, folder: BatchFolder) {
+        let tx = self.tx.clone();
+
+        tokio::spawn(async move {
+            if let Some(path) = FileDialog::new().pick_folder() {
+                send_task(&tx, ImageProcessingTask::BatchFolderPicked { folder, path }).await;
+            }
+        });
+    }
+
+    /// Handle the undo and redo keyboard shortcuts.
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        // A focused text field undoes its own typing
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+
+        // Redo is checked first, as the undo shortcut also matches with shift held
+        if ctx.input_mut(|i| i.consume_shortcut(&REDO_SHORTCUT)) {
+            self.redo();
+        } else if ctx.input_mut(|i| i.consume_shortcut(&UNDO_SHORTCUT)) {
+            self.undo();
+        }
+    }
+
+    fn draw_top_panel(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
+            // Menu bar
+            egui::menu::bar(ui, |ui| {
+                // File menu
+                ui.menu_button("File", |ui| {
+                    // Open image button
+                    if ui.button("Open Image").clicked() {
+                        self.image = None;
+                        self.modified_image = None;
+                        self.image_path_info = None;
+                        self.image_metadata = ImageMetadata::default();
+                        self.texture_map = TextureMap::default();
 
                         let tx = self.tx.clone();
                         let op_in_progress = Arc::clone(&self.op_in_progress);
 
-                        let image = self.image.clone(); // TODO: avoid clone
-                        let library = Arc::clone(&self.libcudaimg);
-
                         tokio::spawn(async move {
                             // Wait for the previous operation to finish
-                            while *op_in_progress.lock().unwrap() {
-                                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                            }
+                            let _guard = OperationGuard::acquire(op_in_progress).await;
 
-                            *op_in_progress.lock().unwrap() = true;
+                            if let Some(path) =
+                                add_format_filters(FileDialog::new(), &formats::readable_formats())
+                                    .pick_file()
+                            {
+                                let opened = {
+                                    let path = path.clone();
+                                    tokio::task::spawn_blocking(move || metadata::open(&path))
+                                        .await
+                                        .unwrap_or_else(|e| {
+                                            Err(anyhow::anyhow!("Opening panicked: {}", e))
+                                        })
+                                };
+
+                                let task = match opened {
+                                    Ok((image, metadata)) => ImageProcessingTask::OpenImage {
+                                        image,
+                                        metadata,
+                                        path,
+                                    },
+                                    Err(e) => ImageProcessingTask::failed(
+                                        &format!("Failed to open {}", path.display()),
+                                        e,
+                                    ),
+                                };
+
+                                send_task(&tx, task).await;
+                            }
+                        });
 
-                            if let Some(image) = image {
-                                let library = library.lock().await;
+                        ui.close_menu();
+                    }
 
-                                let start = std::time::Instant::now();
+                    // Save image button
+                    if ui
+                        .add_enabled(
+                            self.modified_image.is_some(),
+                            egui::Button::new("Save image"),
+                        )
+                        .clicked()
+                    {
+                        // Keep the format of the source when it can be written
+                        if let Some(format) = self
+                            .image_path_info
+                            .as_ref()
+                            .and_then(|path| ImageFormat::from_path(path).ok())
+                            .filter(|format| formats::is_writable(*format))
+                        {
+                            self.save.format = format;
+                        }
 
-                                let histogram = crate::cudaimg::process_image(
-                                    &library,
-                                    &image,
-                                    ImageProcessingFunction::ComputeHistogram,
-                                )
-                                .expect("Failed to generate histogram");
-
-                                let duration = start.elapsed();
-                                tx.send(ImageProcessingTask::OperationFinished {
-                                    image: histogram,
-                                    duration,
-                                })
-                                .await
-                                .unwrap();
-                            }
+                        self.save.open = true;
+                        ui.close_menu();
+                    }
 
-                            *op_in_progress.lock().unwrap() = false;
-                        });
+                    ui.separator();
 
+                    // Batch processing button
+                    if ui
+                        .button("Batch Process...")
+                        .on_hover_text("Apply the pipeline to every image of a folder")
+                        .clicked()
+                    {
+                        self.batch.open = true;
                         ui.close_menu();
                     }
 
-                    // Balance histogram
-                    if ui.button("Balance histogram").clicked() {
-                        self.texture_map.modified_image = None;
+                    ui.separator();
 
+                    // Save recipe button
+                    if ui
+                        .button("Save Recipe")
+                        .on_hover_text("Save the pipeline so it can be applied to other images")
+                        .clicked()
+                    {
                         let tx = self.tx.clone();
-                        let op_in_progress = Arc::clone(&self.op_in_progress);
-
-                        let image = self.image.clone(); // TODO: avoid clone
-                        let library = Arc::clone(&self.libcudaimg);
+                        let recipe = Recipe::from_pipeline(&self.pipeline);
 
                         tokio::spawn(async move {
-                            // Wait for the previous operation to finish
-                            while *op_in_progress.lock().unwrap() {
-                                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                            if let Some(path) = FileDialog::new()
+                                .add_filter("Recipe Files", &["json", "toml"])
+                                .set_file_name("recipe.json")
+                                .save_file()
+                            {
+                                if let Err(e) = recipe.save(&path) {
+                                    let task =
+                                        ImageProcessingTask::failed("Failed to save the recipe", e);
+                                    send_task(&tx, task).await;
+                                }
                             }
+                        });
 
-                            *op_in_progress.lock().unwrap() = true;
+                        ui.close_menu();
+                    }
 
-                            if let Some(image) = image {
-                                let library = library.lock().await;
+                    // Load recipe button
+                    if ui
+                        .button("Load Recipe")
+                        .on_hover_text("Replace the pipeline with the steps of a recipe")
+                        .clicked()
+                    {
+                        let tx = self.tx.clone();
 
-                                let start = std::time::Instant::now();
+                        tokio::spawn(async move {
+                            if let Some(path) = FileDialog::new()
+                                .add_filter("Recipe Files", &["json", "toml"])
+                                .pick_file()
+                            {
+                                let task = match Recipe::load(&path) {
+                                    Ok(recipe) => ImageProcessingTask::RecipeLoaded {
+                                        pipeline: recipe.to_pipeline(),
+                                        path,
+                                    },
+                                    Err(e) => {
+                                        ImageProcessingTask::failed("Failed to load the recipe", e)
+                                    }
+                                };
 
-                                let modified_image = crate::cudaimg::process_image(
-                                    &library,
-                                    &image,
-                                    ImageProcessingFunction::BalanceHistogram,
-                                )
-                                .expect("Failed to balance histogram");
-
-                                let duration = start.elapsed();
-                                tx.send(ImageProcessingTask::OperationFinished {
-                                    image: modified_image,
-                                    duration,
-                                })
-                                .await
-                                .unwrap();
+                                send_task(&tx, task).await;
                             }
-
-                            *op_in_progress.lock().unwrap() = false;
                         });
 
                         ui.close_menu();
                     }
+                });
 
-                    // Box filter
-                    ui.menu_button("Box filter", |ui| {
-                        if ui.button("Run").clicked() {
-                            self.texture_map.modified_image = None;
+                // Edit menu
+                ui.menu_button("Edit", |ui| {
+                    if ui
+                        .add_enabled(
+                            self.history.can_undo(),
+                            egui::Button::new("Undo")
+                                .shortcut_text(ctx.format_shortcut(&UNDO_SHORTCUT)),
+                        )
+                        .clicked()
+                    {
+                        self.undo();
+                        ui.close_menu();
+                    }
 
-                            let tx = self.tx.clone();
-                            let op_in_progress = Arc::clone(&self.op_in_progress);
+                    if ui
+                        .add_enabled(
+                            self.history.can_redo(),
+                            egui::Button::new("Redo")
+                                .shortcut_text(ctx.format_shortcut(&REDO_SHORTCUT)),
+                        )
+                        .clicked()
+                    {
+                        self.redo();
+                        ui.close_menu();
+                    }
+
+                    ui.separator();
+                    ui.checkbox(&mut self.show_history, "Show history");
+                    ui.checkbox(&mut self.live_histogram.open, "Show histograms");
+                });
 
-                            let image = self.image.clone(); // TODO: avoid clone
-                            let library = Arc::clone(&self.libcudaimg);
-                            let filter_size = self.image_modifiers.box_filter_size;
+                // Tools menu, generated from the operation registry
+                let mut requested = None;
 
-                            tokio::spawn(async move {
-                                // Wait for the previous operation to finish
-                                while *op_in_progress.lock().unwrap() {
-                                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                ui.menu_button("Tools", |ui| {
+                    for (spec, values) in OPERATIONS.iter().zip(&mut self.image_modifiers.values) {
+                        let supported = self.backend.supports(spec.operation);
+
+                        ui.add_enabled_ui(supported, |ui| {
+                            if spec.params.is_empty() {
+                                if ui
+                                    .button(spec.name)
+                                    .on_hover_text(spec.description)
+                                    .clicked()
+                                {
+                                    requested = Some((spec, (spec.build)(values)));
+                                    ui.close_menu();
                                 }
 
-                                *op_in_progress.lock().unwrap() = true;
+                                return;
+                            }
 
-                                if let Some(image) = image {
-                                    let library = library.lock().await;
+                            ui.menu_button(spec.name, |ui| {
+                                if ui.button("Run").on_hover_text(spec.description).clicked() {
+                                    requested = Some((spec, (spec.build)(values)));
+                                    ui.close_menu();
+                                }
 
-                                    let start = std::time::Instant::now();
+                                // Parameter sliders
+                                for (param, value) in spec.params.iter().zip(values.iter_mut()) {
+                                    param_slider(ui, param, value);
+                                }
+                            });
+                        });
+                    }
+                });
 
-                                    let modified_image = crate::cudaimg::process_image(
-                                        &library,
-                                        &image,
-                                        ImageProcessingFunction::BoxFilter(filter_size),
-                                    )
-                                    .expect("Failed to use Box filter on image");
+                // Operations that modify the image are added to the pipeline,
+                // the histogram, the only other one, is computed from the original image
+                if let Some((spec, function)) = requested {
+                    if spec.operation.modifies_image() {
+                        self.pipeline.push(function);
+                        self.pipeline_dirty = true;
+                        self.record_history(
+                            format!("Add {}", operations::describe(&function)),
+                            None,
+                        );
+                    } else {
+                        self.compute_histograms();
+                    }
+                }
 
-                                    let duration = start.elapsed();
-                                    tx.send(ImageProcessingTask::OperationFinished {
-                                        image: modified_image,
-                                        duration,
-                                    })
-                                    .await
-                                    .unwrap();
-                                }
+                // Display the duration of the last operation
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    // Apply modification and replace the image with the modified one
+                    if ui
+                        .button("Apply current")
+                        .on_hover_text(
+                            "Replace the original with the result and clear the pipeline",
+                        )
+                        .clicked()
+                    {
+                        if let Some(modified_image) = self.modified_image.take() {
+                            self.histograms = None;
+                            self.image = Some(Arc::new(modified_image));
+                            self.pipeline = Pipeline::default();
+                            self.pipeline_generation += 1;
+                            self.texture_map = TextureMap::default();
+                            self.record_history("Apply current".to_string(), None);
+                        }
+                    }
 
-                                *op_in_progress.lock().unwrap() = false;
-                            });
+                    // Remove the current modification
+                    if ui
+                        .button("Remove current")
+                        .on_hover_text("Clear the pipeline")
+                        .clicked()
+                    {
+                        let had_steps = !self.pipeline.steps.is_empty();
+
+                        let _ = self.modified_image.take();
+                        self.histograms = None;
+                        self.pipeline = Pipeline::default();
+                        self.pipeline_generation += 1;
+                        self.texture_map = TextureMap::default();
 
-                            ui.close_menu();
+                        if had_steps {
+                            self.record_history("Remove current".to_string(), None);
                         }
+                    }
+                });
+            });
+        });
+    }
 
-                        // Box filter size slider
-                        ui.label("Filter size");
-                        ui.add(egui::Slider::new(
-                            &mut self.image_modifiers.box_filter_size,
-                            0u32..=80u32,
-                        ));
-                    });
+    fn draw_pipeline_panel(&mut self, ctx: &egui::Context) {
+        egui::SidePanel::left("pipeline_panel")
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.heading("Pipeline");
+                ui.separator();
 
-                    // Gaussian blur
-                    ui.menu_button("Gaussian blur", |ui| {
-                        if ui.button("Run").clicked() {
-                            self.texture_map.modified_image = None;
+                if self.pipeline.steps.is_empty() {
+                    ui.label("Add operations from the Tools menu.");
+                    return;
+                }
 
-                            let tx = self.tx.clone();
-                            let op_in_progress = Arc::clone(&self.op_in_progress);
+                let step_count = self.pipeline.steps.len();
+                let mut change = None;
+                let mut action = None;
 
-                            let image = self.image.clone(); // TODO: avoid clone
-                            let library = Arc::clone(&self.libcudaimg);
-                            let sigma = self.image_modifiers.gauss_sigma;
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for (i, step) in self.pipeline.steps.iter_mut().enumerate() {
+                        let spec = operations::spec(step.function.operation());
 
-                            tokio::spawn(async move {
-                                // Wait for the previous operation to finish
-                                while *op_in_progress.lock().unwrap() {
-                                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                                }
+                        ui.horizontal(|ui| {
+                            if ui
+                                .checkbox(&mut step.enabled, format!("{}. {}", i + 1, spec.name))
+                                .on_hover_text("Enable or disable the step")
+                                .changed()
+                            {
+                                let verb = if step.enabled { "Enable" } else { "Disable" };
+                                let label = format!(
+                                    "{} step {}: {}",
+                                    verb,
+                                    i + 1,
+                                    operations::describe(&step.function)
+                                );
+                                change = Some((label, None));
+                            }
 
-                                *op_in_progress.lock().unwrap() = true;
+                            ui.with_layout(
+                                egui::Layout::right_to_left(egui::Align::Center),
+                                |ui| {
+                                    if ui.small_button("🗑").on_hover_text("Delete").clicked() {
+                                        action = Some(StepAction::Remove(i));
+                                    }
 
-                                if let Some(image) = image {
-                                    let library = library.lock().await;
+                                    if ui
+                                        .add_enabled(
+                                            i + 1 < step_count,
+                                            egui::Button::new("⬇").small(),
+                                        )
+                                        .on_hover_text("Move down")
+                                        .clicked()
+                                    {
+                                        action = Some(StepAction::MoveDown(i));
+                                    }
 
-                                    let start = std::time::Instant::now();
+                                    if ui
+                                        .add_enabled(i > 0, egui::Button::new("⬆").small())
+                                        .on_hover_text("Move up")
+                                        .clicked()
+                                    {
+                                        action = Some(StepAction::MoveUp(i));
+                                    }
+                                },
+                            );
+                        });
 
-                                    let modified_image = crate::cudaimg::process_image(
-                                        &library,
-                                        &image,
-                                        ImageProcessingFunction::GaussianBlur(sigma),
-                                    )
-                                    .expect("Failed to use Gaussian blur on image");
+                        // Parameter sliders of the step
+                        if !spec.params.is_empty() {
+                            let mut values = step.function.params();
+                            let mut edited = false;
 
-                                    let duration = start.elapsed();
-                                    tx.send(ImageProcessingTask::OperationFinished {
-                                        image: modified_image,
-                                        duration,
-                                    })
-                                    .await
-                                    .unwrap();
+                            ui.indent(i, |ui| {
+                                for (param, value) in spec.params.iter().zip(values.iter_mut()) {
+                                    edited |= param_slider(ui, param, value).changed();
                                 }
-
-                                *op_in_progress.lock().unwrap() = false;
                             });
 
-                            ui.close_menu();
-                        }
+                            if edited {
+                                step.function = (spec.build)(&values);
 
-                        // Gauss sigma slider
-                        ui.label("Sigma");
-                        ui.add(egui::Slider::new(
-                            &mut self.image_modifiers.gauss_sigma,
-                            0.1..=5.0,
-                        ));
-                    });
+                                // Dragging a slider is recorded as a single change
+                                let label = format!(
+                                    "Edit step {}: {}",
+                                    i + 1,
+                                    operations::describe(&step.function)
+                                );
+                                change = Some((label, Some(format!("edit step {}", i))));
+                            }
+                        }
 
-                    // Sobel edge detection
-                    if ui.button("Sobel edge detection").clicked() {
-                        self.texture_map.modified_image = None;
+                        ui.separator();
+                    }
+                });
 
-                        let tx = self.tx.clone();
-                        let op_in_progress = Arc::clone(&self.op_in_progress);
+                if let Some(action) = action {
+                    let (label, index) = match action {
+                        StepAction::MoveUp(i) => (format!("Move step {} up", i + 1), i),
+                        StepAction::MoveDown(i) => (format!("Move step {} down", i + 1), i),
+                        StepAction::Remove(i) => (format!("Delete step {}", i + 1), i),
+                    };
+                    let label = format!(
+                        "{}: {}",
+                        label,
+                        operations::describe(&self.pipeline.steps[index].function)
+                    );
+
+                    match action {
+                        StepAction::MoveUp(i) => self.pipeline.move_up(i),
+                        StepAction::MoveDown(i) => self.pipeline.move_down(i),
+                        StepAction::Remove(i) => self.pipeline.remove(i),
+                    }
 
-                        let image = self.image.clone(); // TODO: avoid clone
-                        let library = Arc::clone(&self.libcudaimg);
+                    change = Some((label, None));
+                }
 
-                        tokio::spawn(async move {
-                            // Wait for the previous operation to finish
-                            while *op_in_progress.lock().unwrap() {
-                                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                            }
+                if let Some((label, merge_key)) = change {
+                    self.pipeline_dirty = true;
+                    self.record_history(label, merge_key);
+                }
+            });
+    }
 
-                            *op_in_progress.lock().unwrap() = true;
+    fn draw_live_histogram_panel(&mut self, ctx: &egui::Context) {
+        if !self.live_histogram.open {
+            return;
+        }
 
-                            if let Some(image) = image {
-                                let library = library.lock().await;
+        let channels = self.visible_histogram_channels();
 
-                                let start = std::time::Instant::now();
-                                let modified_image = crate::cudaimg::process_image(
-                                    &library,
-                                    &image,
-                                    ImageProcessingFunction::SobelEdgeDetection,
-                                )
-                                .expect("Failed to use Sobel edge detection on image");
+        let header = |ui: &mut egui::Ui, app: &mut Self| {
+            ui.horizontal(|ui| {
+                ui.heading("Histograms");
 
-                                // TODO do not panic on fail but show a message and set the op_in_progress to false on tokio tasks
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    ui.selectable_value(
+                        &mut app.live_histogram.dock,
+                        HistogramDock::Bottom,
+                        "Bottom",
+                    );
+                    ui.selectable_value(
+                        &mut app.live_histogram.dock,
+                        HistogramDock::Right,
+                        "Right",
+                    );
+                    ui.label("Dock:");
+                });
+            });
 
-                                let duration = start.elapsed();
-                                tx.send(ImageProcessingTask::OperationFinished {
-                                    image: modified_image,
-                                    duration,
-                                })
-                                .await
-                                .unwrap();
-                            }
+            ui.horizontal_wrapped(|ui| {
+                for (channel, visible) in HistogramChannel::ALL
+                    .iter()
+                    .zip(app.histogram_channels.iter_mut())
+                {
+                    ui.checkbox(visible, channel.label());
+                }
+            });
 
-                            *op_in_progress.lock().unwrap() = false;
-                        });
+            ui.separator();
+        };
 
-                        ui.close_menu();
-                    }
+        // Draw the histograms of an image under its label, filling the width of the ui
+        let chart = |ui: &mut egui::Ui,
+                     label: &str,
+                     histograms: Option<&ChannelHistograms>,
+                     height: f32| {
+            ui.label(label);
 
-                    // Laplace edge detection
-                    if ui.button("Laplace edge detection").clicked() {
-                        self.texture_map.modified_image = None;
+            match histograms {
+                Some(histograms) => {
+                    let size = egui::vec2(ui.available_width(), height.max(40.0));
+                    draw_histogram_chart(ui, histograms, &channels, size);
+                }
+                None => {
+                    ui.weak("No image");
+                }
+            }
+        };
+
+        match self.live_histogram.dock {
+            HistogramDock::Right => {
+                egui::SidePanel::right("live_histogram_panel")
+                    .resizable(true)
+                    .default_width(280.0)
+                    .show(ctx, |ui| {
+                        header(ui, self);
+
+                        let height = ui.available_width() * 0.5;
+                        chart(
+                            ui,
+                            "Original",
+                            self.live_histogram.original.as_ref(),
+                            height,
+                        );
+                        ui.add_space(ui.spacing().item_spacing.y);
+                        chart(
+                            ui,
+                            "Modified",
+                            self.live_histogram.modified.as_ref(),
+                            height,
+                        );
+                    });
+            }
+            HistogramDock::Bottom => {
+                egui::TopBottomPanel::bottom("live_histogram_panel")
+                    .resizable(true)
+                    .default_height(200.0)
+                    .show(ctx, |ui| {
+                        header(ui, self);
+
+                        let height = ui.available_height() - 2.0 * ui.spacing().interact_size.y;
+                        ui.columns(2, |columns| {
+                            chart(
+                                &mut columns[0],
+                                "Original",
+                                self.live_histogram.original.as_ref(),
+                                height,
+                            );
+                            chart(
+                                &mut columns[1],
+                                "Modified",
+                                self.live_histogram.modified.as_ref(),
+                                height,
+                            );
+                        });
+                    });
+            }
+        }
+    }
 
-                        let tx = self.tx.clone();
-                        let op_in_progress = Arc::clone(&self.op_in_progress);
+    fn draw_history_panel(&mut self, ctx: &egui::Context) {
+        if !self.show_history {
+            return;
+        }
 
-                        let image = self.image.clone(); // TODO: avoid clone
-                        let library = Arc::clone(&self.libcudaimg);
+        egui::SidePanel::right("history_panel")
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.heading("History");
+                ui.separator();
 
-                        tokio::spawn(async move {
-                            // Wait for the previous operation to finish
-                            while *op_in_progress.lock().unwrap() {
-                                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                            }
+                if self.history.entries().is_empty() {
+                    ui.label("Open an image to start recording changes.");
+                    return;
+                }
 
-                            *op_in_progress.lock().unwrap() = true;
+                let current = self.history.current_index();
+                let mut jump = None;
 
-                            if let Some(image) = image {
-                                let library = library.lock().await;
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for (i, entry) in self.history.entries().iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            // Undone states are dimmed until they are discarded
+                            let label = if i > current {
+                                egui::RichText::new(&entry.label).weak()
+                            } else {
+                                egui::RichText::new(&entry.label)
+                            };
 
-                                let start = std::time::Instant::now();
-                                let modified_image = crate::cudaimg::process_image(
-                                    &library,
-                                    &image,
-                                    ImageProcessingFunction::LaplaceEdgeDetection,
-                                )
-                                .expect("Failed to use Laplace edge detection on image");
-
-                                let duration = start.elapsed();
-                                tx.send(ImageProcessingTask::OperationFinished {
-                                    image: modified_image,
-                                    duration,
-                                })
-                                .await
-                                .unwrap();
+                            if ui.selectable_label(i == current, label).clicked() {
+                                jump = Some(i);
                             }
 
-                            *op_in_progress.lock().unwrap() = false;
+                            if let Some(duration) = entry.duration {
+                                ui.weak(format!("{:?}", duration));
+                            }
                         });
+                    }
+                });
 
-                        ui.close_menu();
+                if let Some(i) = jump {
+                    if let Some(entry) = self.history.jump_to(i).cloned() {
+                        self.restore_history_entry(entry);
                     }
+                }
 
-                    // Harris corner detection
-                    if ui.button("Harris corner detection").clicked() {
-                        self.texture_map.modified_image = None;
+                ui.separator();
+                ui.label(format!(
+                    "Snapshots: {:.1} MiB of {:.0} MiB",
+                    self.history.memory_usage() as f64 / (1024.0 * 1024.0),
+                    self.history.memory_limit() as f64 / (1024.0 * 1024.0)
+                ));
+            });
+    }
 
-                        let tx = self.tx.clone();
-                        let op_in_progress = Arc::clone(&self.op_in_progress);
+    fn draw_batch_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.batch.open;
+        let mut start = false;
+        let mut pick = None;
+
+        egui::Window::new("Batch processing")
+            .open(&mut open)
+            .resizable(true)
+            .show(ctx, |ui| {
+                let step_count = self.pipeline.steps.iter().filter(|s| s.enabled).count();
+                ui.label(format!(
+                    "Apply the {} enabled pipeline steps to every image.",
+                    step_count
+                ));
+                ui.separator();
+
+                egui::Grid::new("batch_settings")
+                    .num_columns(3)
+                    .show(ui, |ui| {
+                        ui.label("Input");
+                        ui.text_edit_singleline(&mut self.batch.input)
+                            .on_hover_text("A folder, or a glob pattern such as scans/*.png");
+                        if ui.button("Browse").clicked() {
+                            pick = Some(BatchFolder::Input);
+                        }
+                        ui.end_row();
 
-                        let image = self.image.clone(); // TODO: avoid clone
-                        let library = Arc::clone(&self.libcudaimg);
+                        ui.label("Output folder");
+                        ui.text_edit_singleline(&mut self.batch.output_dir);
+                        if ui.button("Browse").clicked() {
+                            pick = Some(BatchFolder::Output);
+                        }
+                        ui.end_row();
+
+                        ui.label("File names");
+                        ui.text_edit_singleline(&mut self.batch.name_template)
+                            .on_hover_text(
+                                "{name} and {ext} are replaced with the name and extension \
+                                 of the input, {index} with its position in the batch. \
+                                 The extension picks the output format.",
+                            );
+                        ui.end_row();
+
+                        ui.label("Jobs");
+                        ui.add(egui::Slider::new(
+                            &mut self.batch.jobs,
+                            1..=2 * batch::default_jobs(),
+                        ))
+                        .on_hover_text("The number of images processed at a time");
+                        ui.end_row();
+
+                        ui.label("Metadata");
+                        ui.checkbox(&mut self.batch.strip_metadata, "Strip")
+                            .on_hover_text("Drop the EXIF, XMP and ICC metadata of the images");
+                        ui.end_row();
+                    });
 
-                        tokio::spawn(async move {
-                            // Wait for the previous operation to finish
-                            while *op_in_progress.lock().unwrap() {
-                                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                            }
+                let ready = !self.batch.running
+                    && step_count > 0
+                    && !self.batch.input.trim().is_empty()
+                    && !self.batch.output_dir.trim().is_empty();
 
-                            // Runs Grayscale conversion, Gaussian blur (sigma=1) and then does Harris corner detection.
-                            *op_in_progress.lock().unwrap() = true;
+                if ui.add_enabled(ready, egui::Button::new("Start")).clicked() {
+                    start = true;
+                }
 
-                            if let Some(image) = image {
-                                let library = library.lock().await;
+                if self.batch.total == 0 {
+                    return;
+                }
 
-                                let start = std::time::Instant::now();
-                                let modified_image = crate::cudaimg::process_image(
-                                    &library,
-                                    &image,
-                                    ImageProcessingFunction::HarrisCornerDetection,
-                                )
-                                .expect("Failed to use Harris corner detection on image");
-
-                                let duration = start.elapsed();
-                                tx.send(ImageProcessingTask::OperationFinished {
-                                    image: modified_image,
-                                    duration,
-                                })
-                                .await
-                                .unwrap();
+                ui.separator();
+
+                let completed = self.batch.results.len();
+                ui.add(
+                    egui::ProgressBar::new(completed as f32 / self.batch.total as f32)
+                        .text(format!("{}/{}", completed, self.batch.total)),
+                );
+
+                let failed = self
+                    .batch
+                    .results
+                    .iter()
+                    .filter(|r| r.outcome.is_err())
+                    .count();
+                if let Some(duration) = self.batch.duration {
+                    ui.label(format!(
+                        "{} succeeded, {} failed in {:?}",
+                        completed - failed,
+                        failed,
+                        duration
+                    ));
+                }
+
+                // Report of every image
+                egui::ScrollArea::vertical()
+                    .max_height(240.0)
+                    .show(ui, |ui| {
+                        for result in &self.batch.results {
+                            match &result.outcome {
+                                Ok(duration) => {
+                                    ui.label(format!(
+                                        "{} -> {} ({:?})",
+                                        result.input.display(),
+                                        result.output.display(),
+                                        duration
+                                    ));
+                                }
+                                Err(e) => {
+                                    ui.colored_label(
+                                        ui.visuals().error_fg_color,
+                                        format!("{}: {}", result.input.display(), e),
+                                    );
+                                }
                             }
+                        }
+                    });
+            });
 
-                            *op_in_progress.lock().unwrap() = false;
-                        });
+        self.batch.open = open;
 
-                        ui.close_menu();
-                    }
-                });
+        if let Some(folder) = pick {
+            self.pick_batch_folder(folder);
+        }
 
-                // Display the duration of the last operation
-                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
-                    // Apply modification and replace the image with the modified one
-                    if ui.button("Apply current").clicked() {
-                        if let Some(modified_image) = self.modified_image.take() {
-                            self.image = Some(modified_image);
-                            self.texture_map = TextureMap::default();
-                        }
-                    }
+        if start {
+            self.start_batch();
+        }
+    }
 
-                    // Remove the current modification
-                    if ui.button("Remove current").clicked() {
-                        let _ = self.modified_image.take();
-                        self.texture_map = TextureMap::default();
+    fn draw_save_window(&mut self, ctx: &egui::Context) {
+        if !self.save.open {
+            return;
+        }
+
+        let mut open = self.save.open;
+        let mut save = false;
+
+        egui::Window::new("Save image")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("save_settings")
+                    .num_columns(2)
+                    .show(ui, |ui| {
+                        ui.label("Format");
+                        egui::ComboBox::from_id_salt("save_format")
+                            .selected_text(formats::format_name(self.save.format))
+                            .show_ui(ui, |ui| {
+                                for format in formats::writable_formats() {
+                                    ui.selectable_value(
+                                        &mut self.save.format,
+                                        format,
+                                        formats::format_name(format),
+                                    );
+                                }
+                            });
+                        ui.end_row();
+
+                        Self::draw_encoder_options(ui, self.save.format, &mut self.save.options);
+
+                        ui.label("Metadata");
+                        ui.add_enabled(
+                            metadata::is_supported(self.save.format)
+                                && !self.image_metadata.is_empty(),
+                            egui::Checkbox::new(&mut self.save.keep_metadata, "Keep"),
+                        )
+                        .on_hover_text(
+                            "Write the EXIF, XMP and ICC metadata of the opened image. \
+                             Uncheck to strip it, e.g. to remove the location of photos.",
+                        )
+                        .on_disabled_hover_text(
+                            "The image has no metadata, or the format can not store it",
+                        );
+                        ui.end_row();
+                    });
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.label("Estimated size:");
+
+                    match &self.save.estimate {
+                        _ if self.save.estimating => {
+                            ui.spinner();
+                        }
+                        Some(Ok(size)) => {
+                            ui.label(format_size(*size));
+                        }
+                        Some(Err(message)) => {
+                            ui.colored_label(ui.visuals().error_fg_color, message);
+                        }
+                        None => {
+                            ui.label("-");
+                        }
                     }
                 });
+
+                ui.separator();
+
+                if ui
+                    .add_enabled(self.modified_image.is_some(), egui::Button::new("Save..."))
+                    .clicked()
+                {
+                    save = true;
+                }
             });
-        });
+
+        self.save.open = open && !save;
+
+        if save {
+            self.save_image(self.save.format, self.save.options, self.save_metadata());
+        }
+
+        // Estimate the size again once the settings or the image changed,
+        // waiting for the running estimate so dragging a slider does not queue up encodes
+        if self.save.open
+            && !self.save.estimating
+            && self.save.estimated != Some(self.save.estimate_key())
+        {
+            self.save.estimate = None;
+            self.estimate_save_size();
+        }
+    }
+
+    /// Draw the options of the encoder of a format as rows of a grid.
+    ///
+    /// # Arguments
+    ///
+    /// * `ui` - The grid to add the rows to.
+    /// * `format` - The format the options are shown for, formats without options add no rows.
+    /// * `options` - The options to edit.
+    fn draw_encoder_options(ui: &mut egui::Ui, format: ImageFormat, options: &mut EncoderOptions) {
+        match format {
+            ImageFormat::Jpeg => {
+                ui.label("Quality");
+                ui.add(egui::Slider::new(&mut options.jpeg.quality, 1..=100));
+                ui.end_row();
+
+                ui.label("Chroma subsampling");
+                egui::ComboBox::from_id_salt("jpeg_subsampling")
+                    .selected_text(options.jpeg.subsampling.label())
+                    .show_ui(ui, |ui| {
+                        for subsampling in JpegSubsampling::ALL {
+                            ui.selectable_value(
+                                &mut options.jpeg.subsampling,
+                                subsampling,
+                                subsampling.label(),
+                            );
+                        }
+                    });
+                ui.end_row();
+            }
+            ImageFormat::Png => {
+                ui.label("Compression");
+                egui::ComboBox::from_id_salt("png_compression")
+                    .selected_text(options.png.compression.label())
+                    .show_ui(ui, |ui| {
+                        for compression in PngCompression::ALL {
+                            ui.selectable_value(
+                                &mut options.png.compression,
+                                compression,
+                                compression.label(),
+                            );
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Filter");
+                egui::ComboBox::from_id_salt("png_filter")
+                    .selected_text(options.png.filter.label())
+                    .show_ui(ui, |ui| {
+                        for filter in PngFilter::ALL {
+                            ui.selectable_value(&mut options.png.filter, filter, filter.label());
+                        }
+                    });
+                ui.end_row();
+            }
+            ImageFormat::WebP => {
+                ui.label("Mode");
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut options.webp.lossless, true, "Lossless");
+                    ui.radio_value(&mut options.webp.lossless, false, "Lossy");
+                });
+                ui.end_row();
+
+                ui.label("Quality");
+                ui.add_enabled(
+                    !options.webp.lossless,
+                    egui::Slider::new(&mut options.webp.quality, 0..=100),
+                );
+                ui.end_row();
+            }
+            ImageFormat::Tiff => {
+                ui.label("Compression");
+                egui::ComboBox::from_id_salt("tiff_compression")
+                    .selected_text(options.tiff.label())
+                    .show_ui(ui, |ui| {
+                        for compression in TiffCompression::ALL {
+                            ui.selectable_value(
+                                &mut options.tiff,
+                                compression,
+                                compression.label(),
+                            );
+                        }
+                    });
+                ui.end_row();
+            }
+            _ => {}
+        }
     }
 
     fn draw_central_panel(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
@@ -725,7 +1796,16 @@ impl MyApp {
                 ui.vertical(|ui| {
                     ui.set_width(half_width - ui.spacing().window_margin.right);
 
-                    if let Some(modified_image) = &self.modified_image {
+                    // The histogram replaces the modified image unless shown beside it
+                    if self.histograms.is_some() {
+                        self.draw_histogram_section(ui);
+                    }
+
+                    let show_image = self.histograms.is_none() || self.histogram_beside;
+
+                    if let Some(modified_image) =
+                        self.modified_image.as_ref().filter(|_| show_image)
+                    {
                         let texture: &egui::TextureHandle =
                             self.texture_map.modified_image.get_or_insert_with(|| {
                                 // Load the texture only once.
@@ -743,7 +1823,7 @@ impl MyApp {
                 egui::TopBottomPanel::bottom("bottom_panel").show(ctx, |ui| {
                     // Image selection and other information
                     ui.horizontal(|ui| {
-                        if *self.op_in_progress.lock().unwrap() {
+                        if self.is_busy() {
                             ui.label("Operation in progress...");
                         } else if let Some(path) = &self.image_path_info {
                             ui.label(format!("Image: {}", path.display()));
@@ -756,6 +1836,9 @@ impl MyApp {
                             } else {
                                 ui.label("No operation performed yet");
                             }
+
+                            ui.separator();
+                            ui.label(format!("Backend: {}", self.backend.name()));
                         });
                     });
                 });
@@ -763,32 +1846,222 @@ impl MyApp {
         });
     }
 
+    fn draw_errors(&mut self, ctx: &egui::Context) {
+        if self.errors.is_empty() {
+            return;
+        }
+
+        // Show the errors until they are dismissed
+        egui::Window::new("Errors")
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::Vec2::new(-10.0, -40.0))
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let mut dismissed = None;
+
+                for (i, message) in self.errors.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.colored_label(ui.visuals().error_fg_color, message);
+
+                        if ui.small_button("Dismiss").clicked() {
+                            dismissed = Some(i);
+                        }
+                    });
+                }
+
+                if let Some(i) = dismissed {
+                    self.errors.remove(i);
+                }
+
+                ui.separator();
+
+                if ui.button("Dismiss all").clicked() {
+                    self.errors.clear();
+                }
+            });
+    }
+
     fn post_update(&mut self, _ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // Handle results from async tasks
         while let Ok(result) = self.rx.try_recv() {
             match result {
-                ImageProcessingTask::OpenImage { image, path } => {
-                    self.image = Some(image);
+                ImageProcessingTask::OpenImage {
+                    image,
+                    metadata,
+                    path,
+                } => {
+                    let label = match path.file_name() {
+                        Some(name) => format!("Open {}", name.to_string_lossy()),
+                        None => "Open image".to_string(),
+                    };
+
+                    self.image = Some(Arc::new(image));
+                    self.histograms = None;
                     self.image_path_info = Some(path);
+                    self.image_metadata = metadata;
+
+                    // The history of the previous image is not kept
+                    self.history = History::default();
+                    self.record_history(label, None);
+
+                    // Run the current pipeline on the new image
+                    self.pipeline_dirty = true;
                 }
-                ImageProcessingTask::OperationFinished { image, duration } => {
-                    self.modified_image = Some(image);
-                    self.texture_map = TextureMap::default(); // TODO: reset only the modified image texture
+                ImageProcessingTask::HistogramComputed {
+                    histograms,
+                    duration,
+                } => {
+                    self.histograms = Some(histograms);
                     self.last_operation_duration = Some(duration);
                 }
+                ImageProcessingTask::LiveHistogramComputed { source, histograms } => {
+                    let histograms = histograms
+                        .map_err(|message| warn!("Failed to compute the histograms: {}", message))
+                        .ok();
+
+                    // Drop the histograms of images that were replaced in the meantime
+                    match source {
+                        HistogramSource::Original(image)
+                            if self
+                                .live_histogram
+                                .original_source
+                                .as_ref()
+                                .is_some_and(|source| Arc::ptr_eq(source, &image)) =>
+                        {
+                            self.live_histogram.original = histograms;
+                        }
+                        HistogramSource::Modified(generation)
+                            if self.live_histogram.modified_generation == Some(generation) =>
+                        {
+                            self.live_histogram.modified = histograms;
+                        }
+                        _ => {}
+                    }
+                }
+                ImageProcessingTask::PipelineFinished {
+                    image,
+                    duration,
+                    generation,
+                } => {
+                    // Drop the results of outdated pipelines
+                    if generation == self.pipeline_generation {
+                        // Show the new result instead of the histogram it replaces
+                        if !self.histogram_beside {
+                            self.histograms = None;
+                        }
+                        self.modified_image = Some(image);
+                        self.save.image_generation += 1;
+                        self.texture_map.modified_image = None;
+                        self.last_operation_duration = Some(duration);
+
+                        // The pipeline ran for the current state unless it was changed since
+                        if !self.pipeline_dirty {
+                            self.history.set_current_duration(duration);
+                        }
+                    }
+                }
+                ImageProcessingTask::RecipeLoaded { pipeline, path } => {
+                    let label = match path.file_name() {
+                        Some(name) => format!("Load recipe {}", name.to_string_lossy()),
+                        None => "Load recipe".to_string(),
+                    };
+
+                    self.pipeline = pipeline;
+                    self.pipeline_dirty = true;
+                    self.record_history(label, None);
+                }
+                ImageProcessingTask::BatchFolderPicked { folder, path } => {
+                    let path = path.display().to_string();
+
+                    match folder {
+                        BatchFolder::Input => self.batch.input = path,
+                        BatchFolder::Output => self.batch.output_dir = path,
+                    }
+                }
+                ImageProcessingTask::BatchStarted { total } => {
+                    self.batch.total = total;
+                }
+                ImageProcessingTask::BatchFileFinished { result } => {
+                    self.batch.results.push(result);
+                }
+                ImageProcessingTask::BatchFinished { report } => {
+                    self.batch.running = false;
+
+                    match report {
+                        Ok(report) => {
+                            self.batch.duration = Some(report.duration);
+                            self.batch.results = report.results;
+                        }
+                        Err(message) => {
+                            error!("{}", message);
+                            self.errors.push(message);
+                        }
+                    }
+                }
+                ImageProcessingTask::SizeEstimated {
+                    format,
+                    options,
+                    keep_metadata,
+                    generation,
+                    size,
+                } => {
+                    self.save.estimating = false;
+
+                    // Outdated estimates are made again by the save window
+                    if (format, options, keep_metadata, generation) == self.save.estimate_key() {
+                        self.save.estimate = Some(size);
+                    }
+                }
+                ImageProcessingTask::Failed { message } => {
+                    error!("{}", message);
+
+                    // Re-running a failing pipeline repeats the same error
+                    if self.errors.last() != Some(&message) {
+                        self.errors.push(message);
+                    }
+                }
             }
         }
+
+        // Re-run the pipeline once the previous operation finished
+        if self.pipeline_dirty && !self.is_busy() {
+            self.run_pipeline();
+        }
+
+        // Follow the displayed images with the live histograms
+        self.update_live_histograms();
     }
 }
 
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // Handle the keyboard shortcuts
+        self.handle_shortcuts(ctx);
+
         // Update the menu bar
         self.draw_top_panel(ctx, _frame);
 
+        // Update the pipeline panel
+        self.draw_pipeline_panel(ctx);
+
+        // Update the history panel
+        self.draw_history_panel(ctx);
+
+        // Update the live histogram panel
+        self.draw_live_histogram_panel(ctx);
+
+        // Update the save image window
+        self.draw_save_window(ctx);
+
+        // Update the batch processing window
+        self.draw_batch_window(ctx);
+
         // Update the main panel
         self.draw_central_panel(ctx, _frame);
 
+        // Show the errors of the failed operations
+        self.draw_errors(ctx);
+
         // Post update
         self.post_update(ctx, _frame);
 
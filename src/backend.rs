@@ -0,0 +1,93 @@
+use crate::cpuimg::CpuBackend;
+use crate::cudaimg::{CudaHistogramData, CudaImageData, CudaImgLib, ImageOperation, SampleType};
+use crate::loader;
+use log::warn;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Trait to abstract over the implementations of the image processing operations.
+/// Every operation modifies the image data in place.
+///
+/// Implementations are shared between the async tasks of the GUI, so they have to be
+/// `Send` and `Sync`.
+pub trait ImageBackend: Send + Sync {
+    /// Get the human readable name of the backend.
+    fn name(&self) -> &str;
+
+    /// Get the operations the backend can run.
+    fn supported_operations(&self) -> &[ImageOperation];
+
+    /// Check whether the backend can run an operation.
+    fn supports(&self, operation: ImageOperation) -> bool {
+        self.supported_operations().contains(&operation)
+    }
+
+    /// Check whether the backend can process samples of a type.
+    /// Only 8-bit samples are processed unless the backend says otherwise.
+    fn supports_sample_type(&self, sample_type: SampleType) -> bool {
+        sample_type == SampleType::U8
+    }
+
+    /// Check whether the backend can run an operation on images with a number of color channels.
+    /// Only RGB images are processed unless the backend says otherwise.
+    fn supports_channels(&self, channels: u32, _operation: ImageOperation) -> bool {
+        channels == 3
+    }
+
+    /// Invert the image.
+    fn invert_image(&self, img: &mut CudaImageData) -> anyhow::Result<()>;
+
+    /// Apply a gamma transformation to the image.
+    fn gamma_transform_image(&self, img: &mut CudaImageData, gamma: f32) -> anyhow::Result<()>;
+
+    /// Apply a logarithmic transformation to the image.
+    fn logarithmic_transform_image(&self, img: &mut CudaImageData, base: f32)
+        -> anyhow::Result<()>;
+
+    /// Convert the image to grayscale.
+    fn grayscale_image(&self, img: &mut CudaImageData) -> anyhow::Result<()>;
+
+    /// Compute the histogram of the image into `histogram`.
+    fn compute_histogram(
+        &self,
+        img: &mut CudaImageData,
+        histogram: &mut CudaHistogramData,
+    ) -> anyhow::Result<()>;
+
+    /// Balance the histogram of the image.
+    fn balance_histogram(&self, img: &mut CudaImageData) -> anyhow::Result<()>;
+
+    /// Apply a box filter to the image.
+    fn box_filter(&self, img: &mut CudaImageData, filter_size: u32) -> anyhow::Result<()>;
+
+    /// Apply a Gaussian blur to the image.
+    fn gaussian_blur(&self, img: &mut CudaImageData, sigma: f32) -> anyhow::Result<()>;
+
+    /// Apply Sobel edge detection to the image.
+    fn sobel_edge_detection(&self, img: &mut CudaImageData) -> anyhow::Result<()>;
+
+    /// Apply Laplace edge detection to the image.
+    fn laplace_edge_detection(&self, img: &mut CudaImageData) -> anyhow::Result<()>;
+
+    /// Apply Harris corner detection to the image.
+    fn harris_corner_detection(&self, img: &mut CudaImageData) -> anyhow::Result<()>;
+}
+
+/// Load the libcudaimg library as the backend,
+/// fall back to the CPU implementation if it is missing or can not be used.
+///
+/// # Arguments
+///
+/// * `cli_path` - The path given with the `--libcudaimg` command line flag, if any.
+pub fn load_backend(cli_path: Option<&Path>) -> Arc<dyn ImageBackend> {
+    match loader::load_libcudaimg(cli_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|(libcudaimg, _)| CudaImgLib::new(libcudaimg))
+    {
+        Ok(backend) => Arc::new(backend),
+        Err(e) => {
+            warn!("{}\nUsing the CPU implementation instead", e);
+            Arc::new(CpuBackend)
+        }
+    }
+}
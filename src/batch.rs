@@ -0,0 +1,380 @@
+use crate::backend::ImageBackend;
+use crate::formats;
+use crate::metadata::{self, ImageMetadata};
+use crate::pipeline::Pipeline;
+use anyhow::Context;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// The default template of the output file names, keeping the name and format of the input.
+pub const DEFAULT_NAME_TEMPLATE: &str = "{name}.{ext}";
+
+/// Get the default number of concurrent jobs, one per available CPU core.
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism()
+        .map(usize::from)
+        .unwrap_or(1)
+}
+
+/// Check whether a path is a file in a format images can be opened from.
+pub fn is_image_file(path: &Path) -> bool {
+    path.is_file() && image::ImageFormat::from_path(path).is_ok_and(formats::is_readable)
+}
+
+/// Collect the images of a batch.
+///
+/// # Arguments
+///
+/// * `input` - A directory, a glob pattern such as `scans/*.png`, or a single image.
+///
+/// # Returns
+///
+/// * The images found, sorted by path, or an error if there are none.
+pub fn collect_inputs(input: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let path = Path::new(input);
+
+    let mut inputs: Vec<PathBuf> = if path.is_dir() {
+        std::fs::read_dir(path)
+            .with_context(|| format!("Failed to read the directory {}", path.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| is_image_file(path))
+            .collect()
+    } else if path.is_file() {
+        vec![path.to_path_buf()]
+    } else {
+        glob::glob(input)
+            .with_context(|| format!("Invalid glob pattern {}", input))?
+            .filter_map(Result::ok)
+            .filter(|path| is_image_file(path))
+            .collect()
+    };
+
+    if inputs.is_empty() {
+        anyhow::bail!("No images found in {}", input);
+    }
+
+    inputs.sort();
+    Ok(inputs)
+}
+
+/// Build the output file name of an image from a template.
+///
+/// The template can contain the following placeholders:
+///
+/// * `{name}` - The file name of the input without its extension.
+/// * `{ext}` - The extension of the input.
+/// * `{index}` - The position of the input in the batch, starting from 1.
+///
+/// # Arguments
+///
+/// * `template` - The template of the file name, its extension picks the output format.
+/// * `input` - The path of the input image.
+/// * `index` - The position of the input in the batch, starting from 1.
+pub fn output_name(template: &str, input: &Path, index: usize) -> anyhow::Result<String> {
+    let name = input
+        .file_stem()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let ext = input
+        .extension()
+        .map(|ext| ext.to_string_lossy())
+        .unwrap_or_default();
+
+    let index = index.to_string();
+    let mut output = String::new();
+    let mut rest = template;
+
+    // Substitute in a single pass, so names containing braces are not read as placeholders
+    while let Some(start) = rest.find(['{', '}']) {
+        output.push_str(&rest[..start]);
+
+        let (placeholder, value) = [("{name}", &*name), ("{ext}", &*ext), ("{index}", &*index)]
+            .into_iter()
+            .find(|(placeholder, _)| rest[start..].starts_with(placeholder))
+            .with_context(|| {
+                format!(
+                    "Unknown placeholder in the file name template {}, use {{name}}, {{ext}} or {{index}}",
+                    template
+                )
+            })?;
+
+        output.push_str(value);
+        rest = &rest[start + placeholder.len()..];
+    }
+
+    output.push_str(rest);
+
+    if output.is_empty() || template.contains(['/', '\\']) {
+        anyhow::bail!(
+            "The file name template {} must produce a file name",
+            template
+        );
+    }
+
+    Ok(output)
+}
+
+/// Pair every input of a batch with the path its result is written to.
+///
+/// # Arguments
+///
+/// * `inputs` - The images of the batch.
+/// * `output_dir` - The directory the results are written to.
+/// * `template` - The template of the output file names, see [`output_name`].
+///
+/// # Returns
+///
+/// * The input and output paths, or an error if two results would overwrite each other
+///   or a result would overwrite its input.
+pub fn plan_outputs(
+    inputs: &[PathBuf],
+    output_dir: &Path,
+    template: &str,
+) -> anyhow::Result<Vec<(PathBuf, PathBuf)>> {
+    let mut seen = HashSet::new();
+    let mut files = Vec::with_capacity(inputs.len());
+
+    // The results may not exist yet, so their directory is resolved instead
+    let canonical_dir = output_dir.canonicalize().ok();
+
+    for (i, input) in inputs.iter().enumerate() {
+        let name = output_name(template, input, i + 1)?;
+        let output = output_dir.join(&name);
+
+        if !seen.insert(output.clone()) {
+            anyhow::bail!(
+                "More than one image would be written to {}, add {{name}} or {{index}} to the template",
+                output.display()
+            );
+        }
+
+        formats::writable_format_from_path(&output)?;
+
+        // Compare the resolved paths, so `./` segments and symlinks do not hide the input
+        let overwrites_input = match (&canonical_dir, input.canonicalize()) {
+            (Some(dir), Ok(input)) => dir.join(&name) == input,
+            _ => output == *input,
+        };
+        if overwrites_input {
+            anyhow::bail!("{} would overwrite its input", output.display());
+        }
+
+        files.push((input.clone(), output));
+    }
+
+    Ok(files)
+}
+
+/// Run the pipeline on an image and write the result.
+/// The format of the result is picked from the extension of the output.
+///
+/// # Arguments
+///
+/// * `backend` - The backend to use for image processing.
+/// * `pipeline` - The pipeline to run.
+/// * `input` - The image to process.
+/// * `output` - The file to write the result to, the format is picked from its extension.
+/// * `strip_metadata` - Whether to drop the EXIF, XMP and ICC metadata of the input.
+pub fn process_file(
+    backend: &dyn ImageBackend,
+    pipeline: &Pipeline,
+    input: &Path,
+    output: &Path,
+    strip_metadata: bool,
+) -> anyhow::Result<()> {
+    let (image, mut metadata) = metadata::open(input)?;
+    let image = pipeline.run(backend, &image)?;
+
+    if strip_metadata {
+        metadata = ImageMetadata::default();
+    }
+
+    let format = formats::writable_format_from_path(output)?;
+
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create the directory {}", parent.display()))?;
+    }
+
+    formats::save(&image, output, format, &metadata)
+}
+
+/// The result of processing an image of a batch.
+///
+/// # Fields
+///
+/// * `input` - The image that was processed.
+/// * `output` - The file the result was written to.
+/// * `outcome` - How long processing took, or why it failed.
+#[derive(Debug, Clone)]
+pub struct BatchFileResult {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub outcome: Result<Duration, String>,
+}
+
+/// The results of a batch.
+///
+/// # Fields
+///
+/// * `results` - The result of every image, sorted by input path.
+/// * `duration` - How long the whole batch took.
+#[derive(Debug, Clone, Default)]
+pub struct BatchReport {
+    pub results: Vec<BatchFileResult>,
+    pub duration: Duration,
+}
+
+impl BatchReport {
+    /// Get the number of images that were processed successfully.
+    pub fn succeeded(&self) -> usize {
+        self.results.iter().filter(|r| r.outcome.is_ok()).count()
+    }
+
+    /// Get the number of images that failed.
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.succeeded()
+    }
+}
+
+/// Run the pipeline on every image of a batch, at most `jobs` images at a time.
+/// A failing image does not stop the batch, its error is recorded in the report.
+///
+/// # Arguments
+///
+/// * `backend` - The backend to use for image processing.
+/// * `pipeline` - The pipeline to run on every image.
+/// * `files` - The input and output paths, see [`plan_outputs`].
+/// * `jobs` - The number of images processed concurrently.
+/// * `strip_metadata` - Whether to drop the EXIF, XMP and ICC metadata of the inputs.
+/// * `on_result` - Called with the result of every image as soon as it finishes.
+pub async fn run_batch(
+    backend: Arc<dyn ImageBackend>,
+    pipeline: Pipeline,
+    files: Vec<(PathBuf, PathBuf)>,
+    jobs: usize,
+    strip_metadata: bool,
+    mut on_result: impl FnMut(&BatchFileResult),
+) -> BatchReport {
+    let start = Instant::now();
+    let semaphore = Arc::new(Semaphore::new(jobs.max(1)));
+    let pipeline = Arc::new(pipeline);
+    let mut tasks = JoinSet::new();
+
+    for (input, output) in files {
+        let semaphore = Arc::clone(&semaphore);
+        let backend = Arc::clone(&backend);
+        let pipeline = Arc::clone(&pipeline);
+
+        tasks.spawn(async move {
+            let _permit = semaphore
+                .acquire_owned()
+                .await
+                .expect("the semaphore is never closed");
+
+            let (task_input, task_output) = (input.clone(), output.clone());
+            let outcome = tokio::task::spawn_blocking(move || {
+                let start = Instant::now();
+                process_file(
+                    backend.as_ref(),
+                    &pipeline,
+                    &task_input,
+                    &task_output,
+                    strip_metadata,
+                )
+                .map(|()| start.elapsed())
+                .map_err(|e| format!("{:#}", e))
+            })
+            .await
+            .unwrap_or_else(|e| Err(format!("Processing panicked: {}", e)));
+
+            BatchFileResult {
+                input,
+                output,
+                outcome,
+            }
+        });
+    }
+
+    let mut results = Vec::with_capacity(tasks.len());
+    while let Some(result) = tasks.join_next().await {
+        // The tasks handle their own failures, so only a cancelled runtime ends up here
+        let Ok(result) = result else {
+            continue;
+        };
+
+        on_result(&result);
+        results.push(result);
+    }
+
+    results.sort_by(|a, b| a.input.cmp(&b.input));
+
+    BatchReport {
+        results,
+        duration: start.elapsed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_name_fills_the_placeholders() {
+        let name = output_name("{index}-{name}.{ext}", Path::new("in/scan.png"), 3).unwrap();
+        assert_eq!(name, "3-scan.png");
+    }
+
+    #[test]
+    fn output_name_keeps_braces_of_the_input_name() {
+        let name = output_name(DEFAULT_NAME_TEMPLATE, Path::new("in/a{b}.png"), 1).unwrap();
+        assert_eq!(name, "a{b}.png");
+
+        let name = output_name("{name}.jpg", Path::new("in/{index}.png"), 7).unwrap();
+        assert_eq!(name, "{index}.jpg");
+    }
+
+    #[test]
+    fn output_name_rejects_unknown_placeholders() {
+        assert!(output_name("{stem}.png", Path::new("scan.png"), 1).is_err());
+        assert!(output_name("{name.png", Path::new("scan.png"), 1).is_err());
+    }
+
+    #[test]
+    fn plan_outputs_rejects_overwriting_the_input() {
+        let dir = Path::new("target").join(format!("plan-outputs-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("scans")).unwrap();
+        std::fs::write(dir.join("scans/a.png"), b"").unwrap();
+        let output_dir = dir.join("scans").canonicalize().unwrap();
+
+        let inputs = [
+            Path::new(".").join(&dir).join("scans/a.png"),
+            dir.join("scans/../scans/a.png"),
+            dir.join("scans/a.png").canonicalize().unwrap(),
+        ];
+        for input in inputs {
+            let planned = plan_outputs(
+                std::slice::from_ref(&input),
+                &output_dir,
+                DEFAULT_NAME_TEMPLATE,
+            );
+            assert!(planned.is_err(), "{} was not rejected", input.display());
+        }
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&output_dir, dir.join("link")).unwrap();
+            let input = dir.join("scans/a.png");
+            assert!(plan_outputs(&[input], &dir.join("link"), DEFAULT_NAME_TEMPLATE).is_err());
+        }
+
+        let input = dir.join("scans/a.png");
+        assert!(plan_outputs(&[input], &dir.join("out"), DEFAULT_NAME_TEMPLATE).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,59 @@
+use img_utils::backend::{self, ImageBackend};
+use img_utils::cli::{self, CliCommand};
+use img_utils::cpuimg::CpuBackend;
+use img_utils::server;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+/// Load the libcudaimg library unless the CPU implementation was requested.
+fn load_backend(cpu: bool, libcudaimg: Option<&Path>) -> Arc<dyn ImageBackend> {
+    if cpu {
+        Arc::new(CpuBackend)
+    } else {
+        backend::load_backend(libcudaimg)
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    env_logger::init();
+
+    let options = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(CliCommand::Help) => {
+            print!("{}", cli::usage());
+            return ExitCode::SUCCESS;
+        }
+        Ok(CliCommand::Process(options)) => options,
+        Ok(CliCommand::Serve {
+            options,
+            cpu,
+            libcudaimg,
+        }) => {
+            let backend = load_backend(cpu, libcudaimg.as_deref());
+            println!("Listening on http://{}, press Ctrl+C to stop", options.addr);
+
+            return match server::serve(backend, &options).await {
+                Ok(()) => ExitCode::SUCCESS,
+                Err(e) => {
+                    eprintln!("error: {:#}", e);
+                    ExitCode::from(cli::EXIT_FAILURE)
+                }
+            };
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::usage());
+            return ExitCode::from(cli::EXIT_USAGE);
+        }
+    };
+
+    let backend = load_backend(options.cpu, options.libcudaimg.as_deref());
+
+    match cli::run(&options, backend).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            ExitCode::from(cli::EXIT_FAILURE)
+        }
+    }
+}
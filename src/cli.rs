@@ -0,0 +1,630 @@
+use crate::backend::ImageBackend;
+use crate::batch::{self, BatchFileResult};
+use crate::cudaimg::{process_image, ImageProcessingFunction};
+use crate::formats;
+use crate::loader::LIBCUDAIMG_CLI_FLAG;
+use crate::metadata::{self, ImageMetadata};
+use crate::operations::{self, OPERATIONS};
+use crate::pipeline::Pipeline;
+use crate::recipe::Recipe;
+use crate::server::{self, ServerOptions};
+use crate::watch::{self, WatchOptions};
+use anyhow::Context;
+use std::fmt;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The exit code of the command line tool when processing fails.
+pub const EXIT_FAILURE: u8 = 1;
+
+/// The exit code of the command line tool when the arguments are invalid.
+pub const EXIT_USAGE: u8 = 2;
+
+/// A step given on the command line.
+///
+/// * `Function` - An operation given with its own flag, e.g. `--gamma 2.2`.
+/// * `Recipe` - The steps of a recipe file given with `--recipe`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliStep {
+    Function(ImageProcessingFunction),
+    Recipe(PathBuf),
+}
+
+/// Where the results of a processing run are written.
+///
+/// * `File` - A single image is processed and written to the file given with `-o`.
+/// * `Directory` - Every image matching the input is processed and written to the directory
+///   given with `--output-dir`, named after the template given with `--name`.
+/// * `Watch` - The input directory is watched and new or changed images are processed
+///   into the directory given with `--output-dir` until the tool is stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliOutput {
+    File(PathBuf),
+    Directory {
+        path: PathBuf,
+        name_template: String,
+        jobs: usize,
+    },
+    Watch(WatchOptions),
+}
+
+/// The options of a processing run.
+///
+/// # Fields
+///
+/// * `input` - The image to process, or a directory or glob pattern when writing to a directory.
+/// * `output` - Where the results are written.
+/// * `steps` - The steps to apply, in the order they were given.
+/// * `cpu` - Whether to use the CPU implementation even if libcudaimg is available.
+/// * `libcudaimg` - The path given with the `--libcudaimg` flag, if any.
+/// * `quiet` - Whether to skip printing the timings and the progress.
+/// * `strip_metadata` - Whether to drop the EXIF, XMP and ICC metadata of the inputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CliOptions {
+    pub input: PathBuf,
+    pub output: CliOutput,
+    pub steps: Vec<CliStep>,
+    pub cpu: bool,
+    pub libcudaimg: Option<PathBuf>,
+    pub quiet: bool,
+    pub strip_metadata: bool,
+}
+
+/// The command requested on the command line.
+///
+/// * `Help` - Print the usage.
+/// * `Process` - Process an image.
+/// * `Serve` - Serve the operations over HTTP, with the backend options of `Process`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CliCommand {
+    Help,
+    Process(CliOptions),
+    Serve {
+        options: ServerOptions,
+        cpu: bool,
+        libcudaimg: Option<PathBuf>,
+    },
+}
+
+/// Error returned when the command line arguments are invalid.
+#[derive(Debug)]
+pub struct UsageError(pub String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+/// Get the usage of the command line tool, the operation flags are listed from the registry.
+pub fn usage() -> String {
+    let mut usage = String::from(
+        "Usage: img-utils-cli <INPUT> [OPERATIONS...] -o <OUTPUT>\n       \
+                img-utils-cli <DIR|GLOB> [OPERATIONS...] --output-dir <DIR>\n       \
+                img-utils-cli --serve [--addr <ADDR>]\n\
+         \n\
+         The operations are applied in the order they are given.\n\
+         \n\
+         Options:\n  \
+           -o, --output <FILE>         The file to write the result to\n  \
+           --output-dir <DIR>          Process every image of a directory or glob into DIR\n  \
+           --name <TEMPLATE>           The output file names, from {name}, {ext} and {index}\n                              \
+                                       (default: {name}.{ext})\n  \
+           -j, --jobs <N>              The number of images processed at a time, also by --serve\n  \
+           --watch                     Keep processing new or changed images of the input\n                              \
+                                       directory into --output-dir until Ctrl+C is pressed\n  \
+           --log-file <FILE>           The log of --watch (default: <DIR>/img-utils-watch.log)\n  \
+           --interval <SECONDS>        The time between two scans of --watch (default: 2)\n  \
+           --serve                     Serve the operations over HTTP until Ctrl+C is pressed\n  \
+           --addr <ADDR>               The address of --serve (default: 127.0.0.1:8080)\n  \
+           --max-body-size <MIB>       The largest image --serve accepts (default: 32)\n  \
+           --max-image-size <PIXELS>   The largest width and height --serve decodes\n                              \
+                                       (default: 16384)\n  \
+           --max-alloc <MIB>           The most memory --serve decodes an image into\n                              \
+                                       (default: 512)\n  \
+           --recipe <FILE>             Apply the steps of a JSON or TOML recipe\n  \
+           --strip-metadata            Do not copy the EXIF, XMP and ICC metadata to the outputs\n  \
+           --cpu                       Use the CPU implementation even if libcudaimg is available\n  \
+           --libcudaimg <PATH>         The libcudaimg library or the directory containing it\n  \
+           -q, --quiet                 Do not print the timings\n  \
+           -h, --help                  Print this help\n\
+         \n\
+         Operations:\n",
+    );
+
+    for spec in OPERATIONS {
+        let params = spec
+            .params
+            .iter()
+            .map(|param| format!(" <{}>", param.key().to_uppercase()))
+            .collect::<String>();
+        let flag = format!("--{}{}", spec.flag, params);
+
+        usage.push_str(&format!("  {:<28}{}\n", flag, spec.description));
+    }
+
+    usage
+}
+
+/// Take the value of a flag, given either as `--flag=value` or as the next argument.
+fn flag_value(
+    flag: &str,
+    inline: Option<String>,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<String, UsageError> {
+    inline
+        .or_else(|| args.next())
+        .ok_or_else(|| UsageError(format!("{} requires a value", flag)))
+}
+
+/// Parse the value of an operation parameter.
+fn parse_param(flag: &str, param: &operations::ParamSpec, value: &str) -> Result<f32, UsageError> {
+    param.parse(value).ok_or_else(|| {
+        UsageError(format!(
+            "Invalid {} for --{}: {}",
+            param.name.to_lowercase(),
+            flag,
+            value
+        ))
+    })
+}
+
+/// Parse the command line arguments of the command line tool.
+///
+/// # Arguments
+///
+/// * `args` - The command line arguments, without the program name.
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<CliCommand, UsageError> {
+    let mut args = args.into_iter();
+
+    let mut input = None;
+    let mut output = None;
+    let mut output_dir = None;
+    let mut name_template = None;
+    let mut jobs = None;
+    let mut watch = false;
+    let mut log_file = None;
+    let mut poll_interval = None;
+    let mut serve = false;
+    let mut addr = None;
+    let mut max_body_size = None;
+    let mut max_image_size = None;
+    let mut max_alloc = None;
+    let mut steps = Vec::new();
+    let mut cpu = false;
+    let mut libcudaimg = None;
+    let mut quiet = false;
+    let mut strip_metadata = false;
+
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => {
+                (name.to_string(), Some(value.to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+
+        match name.as_str() {
+            "-h" | "--help" => return Ok(CliCommand::Help),
+            "-o" | "--output" => {
+                output = Some(PathBuf::from(flag_value(&name, inline, &mut args)?));
+            }
+            "--output-dir" => {
+                output_dir = Some(PathBuf::from(flag_value(&name, inline, &mut args)?));
+            }
+            "--name" => name_template = Some(flag_value(&name, inline, &mut args)?),
+            "-j" | "--jobs" => {
+                let value = flag_value(&name, inline, &mut args)?;
+                jobs = match value.parse::<usize>() {
+                    Ok(jobs) if jobs > 0 => Some(jobs),
+                    _ => return Err(UsageError(format!("Invalid number of jobs: {}", value))),
+                };
+            }
+            "--watch" => watch = true,
+            "--log-file" => log_file = Some(PathBuf::from(flag_value(&name, inline, &mut args)?)),
+            "--interval" => {
+                let value = flag_value(&name, inline, &mut args)?;
+                poll_interval = match value.parse::<f64>() {
+                    Ok(secs) if secs.is_finite() && secs > 0.0 => {
+                        Some(Duration::from_secs_f64(secs))
+                    }
+                    _ => return Err(UsageError(format!("Invalid interval: {}", value))),
+                };
+            }
+            "--serve" => serve = true,
+            "--addr" => {
+                let value = flag_value(&name, inline, &mut args)?;
+                addr = Some(
+                    value
+                        .parse()
+                        .map_err(|_| UsageError(format!("Invalid address: {}", value)))?,
+                );
+            }
+            "--max-body-size" => {
+                let value = flag_value(&name, inline, &mut args)?;
+                max_body_size = match value.parse::<usize>() {
+                    Ok(mib) if mib > 0 => Some(mib * 1024 * 1024),
+                    _ => return Err(UsageError(format!("Invalid body size: {}", value))),
+                };
+            }
+            "--max-image-size" => {
+                let value = flag_value(&name, inline, &mut args)?;
+                max_image_size = match value.parse::<u32>() {
+                    Ok(pixels) if pixels > 0 => Some(pixels),
+                    _ => return Err(UsageError(format!("Invalid image size: {}", value))),
+                };
+            }
+            "--max-alloc" => {
+                let value = flag_value(&name, inline, &mut args)?;
+                max_alloc = match value.parse::<u64>() {
+                    Ok(mib) if mib > 0 => Some(mib * 1024 * 1024),
+                    _ => return Err(UsageError(format!("Invalid allocation size: {}", value))),
+                };
+            }
+            "--recipe" => {
+                let path = flag_value(&name, inline, &mut args)?;
+                steps.push(CliStep::Recipe(PathBuf::from(path)));
+            }
+            "--cpu" => cpu = true,
+            "-q" | "--quiet" => quiet = true,
+            "--strip-metadata" => strip_metadata = true,
+            flag if flag == LIBCUDAIMG_CLI_FLAG => {
+                libcudaimg = Some(PathBuf::from(flag_value(&name, inline, &mut args)?));
+            }
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                let spec = flag
+                    .strip_prefix("--")
+                    .and_then(operations::find_by_flag)
+                    .ok_or_else(|| UsageError(format!("Unknown option {}", flag)))?;
+
+                if spec.params.len() > 1 && inline.is_some() {
+                    return Err(UsageError(format!(
+                        "--{} takes {} values, pass them as separate arguments",
+                        spec.flag,
+                        spec.params.len()
+                    )));
+                }
+
+                let mut inline = inline;
+                let mut values = Vec::with_capacity(spec.params.len());
+                for param in spec.params {
+                    let value = flag_value(flag, inline.take(), &mut args)?;
+                    values.push(parse_param(spec.flag, param, &value)?);
+                }
+
+                if spec.params.is_empty() && inline.is_some() {
+                    return Err(UsageError(format!("--{} does not take a value", spec.flag)));
+                }
+
+                steps.push(CliStep::Function((spec.build)(&values)));
+            }
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => return Err(UsageError(format!("Unexpected argument {}", arg))),
+        }
+    }
+
+    if serve {
+        if input.is_some() || !steps.is_empty() || output.is_some() || output_dir.is_some() {
+            return Err(UsageError(
+                "--serve takes the operations from the requests, not the command line".to_string(),
+            ));
+        }
+
+        let default_addr = server::DEFAULT_ADDR
+            .parse()
+            .expect("the default address is valid");
+
+        return Ok(CliCommand::Serve {
+            options: ServerOptions {
+                addr: addr.unwrap_or(default_addr),
+                max_body_size: max_body_size.unwrap_or(server::DEFAULT_MAX_BODY_SIZE),
+                max_image_size: max_image_size.unwrap_or(server::DEFAULT_MAX_IMAGE_SIZE),
+                max_alloc: max_alloc.unwrap_or(server::DEFAULT_MAX_ALLOC),
+                max_concurrency: jobs.unwrap_or_else(batch::default_jobs),
+            },
+            cpu,
+            libcudaimg,
+        });
+    }
+
+    if addr.is_some() || max_body_size.is_some() || max_image_size.is_some() || max_alloc.is_some()
+    {
+        return Err(UsageError(
+            "--addr, --max-body-size, --max-image-size and --max-alloc require --serve".to_string(),
+        ));
+    }
+
+    let input = input.ok_or_else(|| UsageError("No input image given".to_string()))?;
+    let output = match (output, output_dir) {
+        (Some(_), Some(_)) => {
+            return Err(UsageError(
+                "-o and --output-dir can not be used together".to_string(),
+            ))
+        }
+        (Some(path), None) => {
+            if name_template.is_some() || jobs.is_some() {
+                return Err(UsageError(
+                    "--name and --jobs require --output-dir".to_string(),
+                ));
+            }
+
+            CliOutput::File(path)
+        }
+        (None, Some(path)) if watch => CliOutput::Watch(WatchOptions {
+            input_dir: input.clone(),
+            log_file: log_file
+                .take()
+                .unwrap_or_else(|| path.join(watch::LOG_FILE_NAME)),
+            output_dir: path,
+            name_template: name_template
+                .unwrap_or_else(|| batch::DEFAULT_NAME_TEMPLATE.to_string()),
+            jobs: jobs.unwrap_or_else(batch::default_jobs),
+            poll_interval: poll_interval.unwrap_or(watch::DEFAULT_POLL_INTERVAL),
+            strip_metadata,
+        }),
+        (None, Some(path)) => CliOutput::Directory {
+            path,
+            name_template: name_template
+                .unwrap_or_else(|| batch::DEFAULT_NAME_TEMPLATE.to_string()),
+            jobs: jobs.unwrap_or_else(batch::default_jobs),
+        },
+        (None, None) => {
+            return Err(UsageError(
+                "No output given, use -o or --output-dir".to_string(),
+            ))
+        }
+    };
+
+    if !matches!(output, CliOutput::Watch(_))
+        && (watch || log_file.is_some() || poll_interval.is_some())
+    {
+        return Err(UsageError(
+            "--watch, --log-file and --interval require --output-dir".to_string(),
+        ));
+    }
+
+    if steps.is_empty() {
+        return Err(UsageError("No operations given".to_string()));
+    }
+
+    // Analysis operations replace the image, so nothing can follow them
+    let analysis = steps[..steps.len() - 1].iter().find_map(|step| match step {
+        CliStep::Function(function) if !function.operation().modifies_image() => Some(function),
+        _ => None,
+    });
+
+    if let Some(function) = analysis {
+        return Err(UsageError(format!(
+            "--{} has to be the last operation",
+            operations::spec(function.operation()).flag
+        )));
+    }
+
+    Ok(CliCommand::Process(CliOptions {
+        input,
+        output,
+        steps,
+        cpu,
+        libcudaimg,
+        quiet,
+        strip_metadata,
+    }))
+}
+
+/// Build the pipeline of the command line steps, loading the recipes they refer to.
+///
+/// # Arguments
+///
+/// * `steps` - The steps given on the command line.
+pub fn build_pipeline(steps: &[CliStep]) -> anyhow::Result<Pipeline> {
+    let mut pipeline = Pipeline::default();
+
+    for step in steps {
+        match step {
+            CliStep::Function(function) => pipeline.push(*function),
+            CliStep::Recipe(path) => pipeline
+                .steps
+                .extend(Recipe::load(path)?.to_pipeline().steps),
+        }
+    }
+
+    Ok(pipeline)
+}
+
+/// Print a timing line unless the output is quiet.
+fn print_timing(quiet: bool, label: &str, duration: Duration) {
+    if !quiet {
+        println!("{:<48} {:>12.3?}", label, duration);
+    }
+}
+
+/// Process the images as described by the command line options and write the results.
+///
+/// # Arguments
+///
+/// * `options` - The options of the run.
+/// * `backend` - The backend to use for image processing.
+///
+/// # Returns
+///
+/// * An error if processing failed, or if any image of a batch failed.
+pub async fn run(options: &CliOptions, backend: Arc<dyn ImageBackend>) -> anyhow::Result<()> {
+    let pipeline = build_pipeline(&options.steps)?;
+
+    if !options.quiet {
+        println!("Backend: {}", backend.name());
+    }
+
+    match &options.output {
+        CliOutput::File(output) => run_single(options, backend.as_ref(), &pipeline, output),
+        CliOutput::Directory {
+            path,
+            name_template,
+            jobs,
+        } => run_batch(options, backend, pipeline, path, name_template, *jobs).await,
+        CliOutput::Watch(watch_options) => {
+            if !options.quiet {
+                println!(
+                    "Watching {}, press Ctrl+C to stop",
+                    watch_options.input_dir.display()
+                );
+            }
+
+            watch::watch(backend, pipeline, watch_options, |result| {
+                if !options.quiet || result.outcome.is_err() {
+                    print_file_result(result);
+                }
+            })
+            .await
+        }
+    }
+}
+
+/// Process a single image and print the time of every step.
+fn run_single(
+    options: &CliOptions,
+    backend: &dyn ImageBackend,
+    pipeline: &Pipeline,
+    output: &Path,
+) -> anyhow::Result<()> {
+    let total = Instant::now();
+    let format = formats::writable_format_from_path(output)?;
+
+    let start = Instant::now();
+    let (mut image, mut metadata) = metadata::open(&options.input)?;
+    if options.strip_metadata {
+        metadata = ImageMetadata::default();
+    }
+    print_timing(
+        options.quiet,
+        &format!("Open {}", options.input.display()),
+        start.elapsed(),
+    );
+
+    for (i, step) in pipeline.steps.iter().enumerate() {
+        if !step.enabled {
+            continue;
+        }
+
+        let start = Instant::now();
+        image = process_image(backend, &image, step.function)
+            .with_context(|| format!("Step {} ({}) failed", i + 1, step.name()))?;
+        print_timing(
+            options.quiet,
+            &format!("{}. {}", i + 1, operations::describe(&step.function)),
+            start.elapsed(),
+        );
+    }
+
+    let start = Instant::now();
+    formats::save(&image, output, format, &metadata)?;
+    print_timing(
+        options.quiet,
+        &format!("Save {}", output.display()),
+        start.elapsed(),
+    );
+
+    print_timing(options.quiet, "Total", total.elapsed());
+
+    Ok(())
+}
+
+/// The width of the progress bar printed while a batch runs.
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+/// Print the progress of a batch on a single, redrawn line.
+fn print_progress(completed: usize, total: usize) {
+    let filled = PROGRESS_BAR_WIDTH * completed / total.max(1);
+
+    eprint!(
+        "\r[{}{}] {}/{}",
+        "#".repeat(filled),
+        "-".repeat(PROGRESS_BAR_WIDTH - filled),
+        completed,
+        total
+    );
+    let _ = std::io::stderr().flush();
+}
+
+/// Print the result of an image of a batch.
+fn print_file_result(result: &BatchFileResult) {
+    match &result.outcome {
+        Ok(duration) => println!(
+            "ok      {} -> {} ({:.3?})",
+            result.input.display(),
+            result.output.display(),
+            duration
+        ),
+        Err(e) => println!("FAILED  {}: {}", result.input.display(), e),
+    }
+}
+
+/// Process every image matching the input into the output directory,
+/// printing a progress bar and the result of every image.
+async fn run_batch(
+    options: &CliOptions,
+    backend: Arc<dyn ImageBackend>,
+    pipeline: Pipeline,
+    output_dir: &Path,
+    name_template: &str,
+    jobs: usize,
+) -> anyhow::Result<()> {
+    let inputs = batch::collect_inputs(&options.input.to_string_lossy())?;
+    let files = batch::plan_outputs(&inputs, output_dir, name_template)?;
+
+    let total = files.len();
+    let show_progress = !options.quiet && std::io::stderr().is_terminal();
+    let mut completed = 0;
+
+    if !options.quiet {
+        println!("Processing {} images, {} at a time", total, jobs);
+    }
+
+    let report = batch::run_batch(
+        backend,
+        pipeline,
+        files,
+        jobs,
+        options.strip_metadata,
+        |result| {
+            completed += 1;
+
+            if show_progress {
+                // Clear the progress bar before printing the result over it
+                eprint!("\r\x1b[2K");
+            }
+
+            if !options.quiet || result.outcome.is_err() {
+                print_file_result(result);
+            }
+
+            if show_progress {
+                print_progress(completed, total);
+            }
+        },
+    )
+    .await;
+
+    if show_progress {
+        eprintln!();
+    }
+
+    if !options.quiet {
+        println!(
+            "{} succeeded, {} failed in {:.3?}",
+            report.succeeded(),
+            report.failed(),
+            report.duration
+        );
+    }
+
+    if report.failed() > 0 {
+        anyhow::bail!("{} of {} images failed", report.failed(), total);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,750 @@
+//! Pure Rust implementation of the libcudaimg kernels, used when the library can not be loaded.
+//!
+//! The results are meant to match libcudaimg, so the constants follow the kernels:
+//!
+//! * Luminance uses the ITU-R BT.601 weights 0.299, 0.587 and 0.114.
+//! * Sobel uses the 3x3 kernels `[-1 0 1; -2 0 2; -1 0 1]` and their transpose,
+//!   the result is the gradient magnitude.
+//! * Laplace uses the 4-neighbour kernel `[0 1 0; 1 -4 1; 0 1 0]`, the result is its absolute value.
+//! * Harris blurs with sigma 1, sums the structure tensor of the Sobel gradients over a 3x3 window
+//!   and uses the sensitivity `k = 0.04` of Harris and Stephens. Pixels above 1% of the strongest
+//!   response are corners.
+//! * Every kernel clamps the coordinates at the borders of the image.
+//!
+//! `golden_outputs` pins the results on a small fixture and `matches_libcudaimg` compares both
+//! backends on it, it is ignored unless run on a machine with libcudaimg and a CUDA device.
+
+use crate::backend::ImageBackend;
+use crate::cudaimg::{CudaHistogramData, CudaImageData, ImageOperation, SampleType};
+
+/// The sigma of the Gaussian blur applied before the Harris corner detection.
+const HARRIS_BLUR_SIGMA: f32 = 1.0;
+
+/// The sensitivity factor of the Harris corner response.
+const HARRIS_K: f32 = 0.04;
+
+/// The fraction of the strongest response a pixel needs to be marked as a corner.
+const HARRIS_THRESHOLD: f32 = 0.01;
+
+/// Get the luminance of an RGB or grayscale pixel.
+pub(crate) fn luminance(pixel: &[f32]) -> f32 {
+    match pixel {
+        [r, g, b, ..] => 0.299 * r + 0.587 * g + 0.114 * b,
+        [gray, ..] => *gray,
+        [] => 0.0,
+    }
+}
+
+/// Apply a point transformation to every sample of the image.
+/// Integer samples go through a lookup table, so the function runs once per possible value.
+fn map_samples(img: &mut CudaImageData, f: impl Fn(f32) -> f32) {
+    let sample_type = img.sample_type;
+
+    match sample_type {
+        SampleType::U8 => {
+            let lut: Vec<u8> = (0..=u8::MAX)
+                .map(|i| sample_type.clamp(f(i as f32)) as u8)
+                .collect();
+            img.bytes.iter_mut().for_each(|b| *b = lut[*b as usize]);
+        }
+        SampleType::U16 => {
+            let lut: Vec<f32> = (0..=u16::MAX).map(|i| f(i as f32)).collect();
+            let samples: Vec<f32> = img.samples().iter().map(|&v| lut[v as usize]).collect();
+            img.set_samples(&samples);
+        }
+        SampleType::F32 => {
+            let samples: Vec<f32> = img.samples().into_iter().map(f).collect();
+            img.set_samples(&samples);
+        }
+    }
+}
+
+/// Build a single channel luminance plane from the image.
+fn luminance_plane(img: &CudaImageData) -> Vec<f32> {
+    img.samples()
+        .chunks_exact(img.channels as usize)
+        .map(luminance)
+        .collect()
+}
+
+/// Write a single channel plane back into every channel of the image.
+fn write_plane(img: &mut CudaImageData, plane: &[f32]) {
+    let channels = img.channels as usize;
+    let samples: Vec<f32> = plane
+        .iter()
+        .flat_map(|&value| std::iter::repeat_n(value, channels))
+        .collect();
+
+    img.set_samples(&samples);
+}
+
+/// Convolve a single channel plane with a 3x3 kernel, clamping at the borders.
+fn convolve3x3(plane: &[f32], width: usize, height: usize, kernel: &[[f32; 3]; 3]) -> Vec<f32> {
+    let mut out = vec![0f32; plane.len()];
+
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0f32;
+
+            for (ky, row) in kernel.iter().enumerate() {
+                let sy = (y + ky).saturating_sub(1).min(height - 1);
+
+                for (kx, weight) in row.iter().enumerate() {
+                    let sx = (x + kx).saturating_sub(1).min(width - 1);
+                    sum += plane[sy * width + sx] * weight;
+                }
+            }
+
+            out[y * width + x] = sum;
+        }
+    }
+
+    out
+}
+
+/// Convolve every channel of the image with a separable kernel, clamping at the borders.
+/// The kernel is anchored at its middle element, so even kernels reach one sample further
+/// before the pixel than after it.
+fn convolve_separable(img: &mut CudaImageData, kernel: &[f32]) {
+    let width = img.width as usize;
+    let height = img.height as usize;
+    let channels = img.channels as usize;
+    let radius = (kernel.len() / 2) as isize;
+
+    let source = img.samples();
+    let mut horizontal = vec![0f32; source.len()];
+    let mut blurred = vec![0f32; source.len()];
+
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..channels {
+                let mut sum = 0f32;
+
+                for (k, weight) in kernel.iter().enumerate() {
+                    let sx = (x as isize + k as isize - radius).clamp(0, width as isize - 1);
+                    sum += source[(y * width + sx as usize) * channels + c] * weight;
+                }
+
+                horizontal[(y * width + x) * channels + c] = sum;
+            }
+        }
+    }
+
+    for y in 0..height {
+        for x in 0..width {
+            for c in 0..channels {
+                let mut sum = 0f32;
+
+                for (k, weight) in kernel.iter().enumerate() {
+                    let sy = (y as isize + k as isize - radius).clamp(0, height as isize - 1);
+                    sum += horizontal[(sy as usize * width + x) * channels + c] * weight;
+                }
+
+                blurred[(y * width + x) * channels + c] = sum;
+            }
+        }
+    }
+
+    img.set_samples(&blurred);
+}
+
+/// Build a normalized one dimensional Gaussian kernel covering three sigmas on each side.
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let radius = (3.0 * sigma).ceil().max(1.0) as isize;
+    let kernel: Vec<f32> = (-radius..=radius)
+        .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+
+    kernel.into_iter().map(|w| w / sum).collect()
+}
+
+/// Invert the image.
+pub fn invert_image(img: &mut CudaImageData) {
+    let max = img.sample_type.max_value();
+    map_samples(img, |v| max - v);
+}
+
+/// Apply a gamma transformation to the image.
+///
+/// # Arguments
+///
+/// * `img` - The image to transform.
+/// * `gamma` - The gamma value to use.
+pub fn gamma_transform_image(img: &mut CudaImageData, gamma: f32) {
+    let max = img.sample_type.max_value();
+    map_samples(img, |v| max * (v / max).powf(gamma));
+}
+
+/// Apply a logarithmic transformation to the image.
+///
+/// # Arguments
+///
+/// * `img` - The image to transform.
+/// * `base` - The base of the logarithm, a base of 1 leaves the image unchanged.
+pub fn logarithmic_transform_image(img: &mut CudaImageData, base: f32) {
+    if (base - 1.0).abs() < f32::EPSILON {
+        return;
+    }
+
+    let max = img.sample_type.max_value();
+    map_samples(img, |v| max * (1.0 + (base - 1.0) * v / max).log(base));
+}
+
+/// Convert the image to grayscale.
+pub fn grayscale_image(img: &mut CudaImageData) {
+    let plane = luminance_plane(img);
+    write_plane(img, &plane);
+}
+
+/// Compute the histogram of the image.
+///
+/// # Arguments
+///
+/// * `img` - The image to compute the histogram of.
+/// * `histogram` - The histogram to fill, every sample of the image is counted.
+///   Samples of more than 8 bits are binned into the 256 bins by their intensity.
+pub fn compute_histogram(img: &CudaImageData, histogram: &mut CudaHistogramData) {
+    histogram.data.fill(0);
+
+    match img.sample_type {
+        SampleType::U8 => img
+            .bytes
+            .iter()
+            .for_each(|&b| histogram.data[b as usize] += 1),
+        sample_type => {
+            let max = sample_type.max_value();
+            img.samples().iter().for_each(|&v| {
+                histogram.data[((v / max).clamp(0.0, 1.0) * 255.0).round() as usize] += 1
+            });
+        }
+    }
+}
+
+/// Balance the histogram of the image.
+/// Samples of more than 8 bits are balanced over 65536 intensity levels,
+/// floating point samples above white are treated as white.
+pub fn balance_histogram(img: &mut CudaImageData) {
+    let max = img.sample_type.max_value();
+    let levels = match img.sample_type {
+        SampleType::U8 => 1 << 8,
+        _ => 1 << 16,
+    };
+    let level = |v: f32| ((v / max).clamp(0.0, 1.0) * (levels - 1) as f32).round() as usize;
+
+    let samples = img.samples();
+    let mut histogram = vec![0u64; levels];
+    samples.iter().for_each(|&v| histogram[level(v)] += 1);
+
+    let total = samples.len() as u64;
+    let mut cdf = vec![0u64; levels];
+    let mut running = 0u64;
+    for (i, &count) in histogram.iter().enumerate() {
+        running += count;
+        cdf[i] = running;
+    }
+
+    let cdf_min = cdf.iter().copied().find(|&c| c > 0).unwrap_or(0);
+    if total == cdf_min {
+        // A single intensity level can not be spread out
+        return;
+    }
+
+    let lut: Vec<f32> = cdf
+        .iter()
+        .map(|&c| c.saturating_sub(cdf_min) as f32 / (total - cdf_min) as f32 * max)
+        .collect();
+    let balanced: Vec<f32> = samples.iter().map(|&v| lut[level(v)]).collect();
+
+    img.set_samples(&balanced);
+}
+
+/// Apply a box filter to the image.
+///
+/// # Arguments
+///
+/// * `img` - The image to filter.
+/// * `filter_size` - The width and height of the filter window. Even windows cover
+///   `filter_size / 2` pixels before the pixel and `filter_size / 2 - 1` after it.
+pub fn box_filter(img: &mut CudaImageData, filter_size: u32) {
+    if filter_size == 0 {
+        return;
+    }
+
+    let size = filter_size as usize;
+    convolve_separable(img, &vec![1.0 / size as f32; size]);
+}
+
+/// Apply a Gaussian blur to the image.
+///
+/// # Arguments
+///
+/// * `img` - The image to blur.
+/// * `sigma` - The standard deviation of the Gaussian kernel.
+pub fn gaussian_blur(img: &mut CudaImageData, sigma: f32) {
+    if sigma <= 0.0 {
+        return;
+    }
+
+    convolve_separable(img, &gaussian_kernel(sigma));
+}
+
+/// Apply Sobel edge detection to the image.
+/// The result is the gradient magnitude of the luminance.
+pub fn sobel_edge_detection(img: &mut CudaImageData) {
+    let width = img.width as usize;
+    let height = img.height as usize;
+    let plane = luminance_plane(img);
+
+    let gx = convolve3x3(
+        &plane,
+        width,
+        height,
+        &[[-1.0, 0.0, 1.0], [-2.0, 0.0, 2.0], [-1.0, 0.0, 1.0]],
+    );
+    let gy = convolve3x3(
+        &plane,
+        width,
+        height,
+        &[[-1.0, -2.0, -1.0], [0.0, 0.0, 0.0], [1.0, 2.0, 1.0]],
+    );
+
+    let magnitude: Vec<f32> = gx.iter().zip(&gy).map(|(x, y)| x.hypot(*y)).collect();
+    write_plane(img, &magnitude);
+}
+
+/// Apply Laplace edge detection to the image.
+/// The result is the absolute Laplacian of the luminance.
+pub fn laplace_edge_detection(img: &mut CudaImageData) {
+    let width = img.width as usize;
+    let height = img.height as usize;
+    let plane = luminance_plane(img);
+
+    let laplacian: Vec<f32> = convolve3x3(
+        &plane,
+        width,
+        height,
+        &[[0.0, 1.0, 0.0], [1.0, -4.0, 1.0], [0.0, 1.0, 0.0]],
+    )
+    .into_iter()
+    .map(f32::abs)
+    .collect();
+
+    write_plane(img, &laplacian);
+}
+
+/// Apply Harris corner detection to the image.
+/// Runs grayscale conversion, Gaussian blur (sigma=1) and then marks the corners in red.
+pub fn harris_corner_detection(img: &mut CudaImageData) {
+    grayscale_image(img);
+    gaussian_blur(img, HARRIS_BLUR_SIGMA);
+
+    let width = img.width as usize;
+    let height = img.height as usize;
+    let plane = luminance_plane(img);
+
+    let ix = convolve3x3(
+        &plane,
+        width,
+        height,
+        &[[-1.0, 0.0, 1.0], [-2.0, 0.0, 2.0], [-1.0, 0.0, 1.0]],
+    );
+    let iy = convolve3x3(
+        &plane,
+        width,
+        height,
+        &[[-1.0, -2.0, -1.0], [0.0, 0.0, 0.0], [1.0, 2.0, 1.0]],
+    );
+
+    // Sum the structure tensor over a 3x3 window
+    let window = [[1.0; 3]; 3];
+    let ixx: Vec<f32> = ix.iter().map(|x| x * x).collect();
+    let iyy: Vec<f32> = iy.iter().map(|y| y * y).collect();
+    let ixy: Vec<f32> = ix.iter().zip(&iy).map(|(x, y)| x * y).collect();
+    let sxx = convolve3x3(&ixx, width, height, &window);
+    let syy = convolve3x3(&iyy, width, height, &window);
+    let sxy = convolve3x3(&ixy, width, height, &window);
+
+    let response: Vec<f32> = (0..plane.len())
+        .map(|i| {
+            let det = sxx[i] * syy[i] - sxy[i] * sxy[i];
+            let trace = sxx[i] + syy[i];
+            det - HARRIS_K * trace * trace
+        })
+        .collect();
+
+    let max_response = response.iter().copied().fold(0f32, f32::max);
+    if max_response <= 0.0 {
+        return;
+    }
+
+    let threshold = HARRIS_THRESHOLD * max_response;
+    let max = img.sample_type.max_value();
+    let mut samples = img.samples();
+    samples
+        .chunks_exact_mut(img.channels as usize)
+        .zip(&response)
+        .filter(|(_, &r)| r > threshold)
+        .for_each(|(pixel, _)| {
+            pixel.fill(0.0);
+            pixel[0] = max;
+        });
+
+    img.set_samples(&samples);
+}
+
+/// Image processing backend using the pure Rust implementation of the libcudaimg kernels.
+/// Used as a fallback when libcudaimg can not be loaded.
+#[derive(Default)]
+pub struct CpuBackend;
+
+impl ImageBackend for CpuBackend {
+    fn name(&self) -> &str {
+        "CPU"
+    }
+
+    fn supported_operations(&self) -> &[ImageOperation] {
+        &ImageOperation::ALL
+    }
+
+    fn supports_sample_type(&self, _sample_type: SampleType) -> bool {
+        true
+    }
+
+    fn supports_channels(&self, _channels: u32, _operation: ImageOperation) -> bool {
+        true
+    }
+
+    fn invert_image(&self, img: &mut CudaImageData) -> anyhow::Result<()> {
+        invert_image(img);
+        Ok(())
+    }
+
+    fn gamma_transform_image(&self, img: &mut CudaImageData, gamma: f32) -> anyhow::Result<()> {
+        gamma_transform_image(img, gamma);
+        Ok(())
+    }
+
+    fn logarithmic_transform_image(
+        &self,
+        img: &mut CudaImageData,
+        base: f32,
+    ) -> anyhow::Result<()> {
+        logarithmic_transform_image(img, base);
+        Ok(())
+    }
+
+    fn grayscale_image(&self, img: &mut CudaImageData) -> anyhow::Result<()> {
+        grayscale_image(img);
+        Ok(())
+    }
+
+    fn compute_histogram(
+        &self,
+        img: &mut CudaImageData,
+        histogram: &mut CudaHistogramData,
+    ) -> anyhow::Result<()> {
+        compute_histogram(img, histogram);
+        Ok(())
+    }
+
+    fn balance_histogram(&self, img: &mut CudaImageData) -> anyhow::Result<()> {
+        balance_histogram(img);
+        Ok(())
+    }
+
+    fn box_filter(&self, img: &mut CudaImageData, filter_size: u32) -> anyhow::Result<()> {
+        box_filter(img, filter_size);
+        Ok(())
+    }
+
+    fn gaussian_blur(&self, img: &mut CudaImageData, sigma: f32) -> anyhow::Result<()> {
+        gaussian_blur(img, sigma);
+        Ok(())
+    }
+
+    fn sobel_edge_detection(&self, img: &mut CudaImageData) -> anyhow::Result<()> {
+        sobel_edge_detection(img);
+        Ok(())
+    }
+
+    fn laplace_edge_detection(&self, img: &mut CudaImageData) -> anyhow::Result<()> {
+        laplace_edge_detection(img);
+        Ok(())
+    }
+
+    fn harris_corner_detection(&self, img: &mut CudaImageData) -> anyhow::Result<()> {
+        harris_corner_detection(img);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cudaimg::ToCudaImageData;
+    use crate::test_utils::{gray, rgb};
+    use image::{DynamicImage, RgbImage};
+
+    /// A kernel on a grayscale image: the name of the kernel, the kernel,
+    /// the width of the image, its pixels and the expected pixels.
+    type Case = (
+        &'static str,
+        fn(&mut CudaImageData),
+        u32,
+        &'static [u8],
+        &'static [u8],
+    );
+
+    #[test]
+    fn grayscale_kernel_outputs() {
+        #[rustfmt::skip]
+        let cases: [Case; 13] = [
+            ("invert", invert_image, 3, &[0, 100, 255], &[255, 155, 0]),
+            ("gamma 2", |img| gamma_transform_image(img, 2.0), 3,
+                &[0, 100, 255], &[0, 39, 255]),
+            ("gamma 1", |img| gamma_transform_image(img, 1.0), 3,
+                &[0, 100, 255], &[0, 100, 255]),
+            ("logarithm 10", |img| logarithmic_transform_image(img, 10.0), 4,
+                &[0, 50, 100, 255], &[0, 113, 167, 255]),
+            ("logarithm 1", |img| logarithmic_transform_image(img, 1.0), 4,
+                &[0, 50, 100, 255], &[0, 50, 100, 255]),
+            ("balanced histogram", balance_histogram, 4,
+                &[0, 0, 128, 255], &[0, 0, 128, 255]),
+            ("narrow histogram", balance_histogram, 4,
+                &[10, 10, 20, 30], &[0, 0, 128, 255]),
+            // A single level is left as it is
+            ("single level histogram", balance_histogram, 2, &[42, 42], &[42, 42]),
+            ("box filter of size 1", |img| box_filter(img, 1), 3,
+                &[0, 10, 20, 30, 40, 50, 60, 70, 80],
+                &[0, 10, 20, 30, 40, 50, 60, 70, 80]),
+            // An even size uses the exact window instead of rounding it up
+            ("box filter of size 2", |img| box_filter(img, 2), 4,
+                &[
+                    0, 0, 0, 0,
+                    0, 100, 0, 0,
+                    0, 0, 0, 0,
+                    0, 0, 0, 0,
+                ],
+                &[
+                    0, 0, 0, 0,
+                    0, 25, 25, 0,
+                    0, 25, 25, 0,
+                    0, 0, 0, 0,
+                ]),
+            ("box filter of size 3", |img| box_filter(img, 3), 3,
+                &[
+                    0, 0, 0,
+                    0, 90, 0,
+                    0, 0, 0,
+                ],
+                &[10; 9]),
+            ("sobel", sobel_edge_detection, 4,
+                &[0, 0, 40, 40, 0, 0, 40, 40],
+                &[0, 160, 160, 0, 0, 160, 160, 0]),
+            ("laplace", laplace_edge_detection, 3,
+                &[
+                    0, 0, 0,
+                    0, 50, 0,
+                    0, 0, 0,
+                ],
+                &[
+                    0, 50, 0,
+                    50, 200, 50,
+                    0, 50, 0,
+                ]),
+        ];
+
+        for (name, function, width, pixels, expected) in cases {
+            let height = pixels.len() as u32 / width;
+            let mut img = gray(width, height, pixels).to_cuda_image_data();
+            function(&mut img);
+            assert_eq!(img.bytes, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn grayscale_writes_the_luminance_into_every_channel() {
+        let mut img = rgb(2, 1, &[255, 0, 0, 0, 255, 0]).to_cuda_image_data();
+        grayscale_image(&mut img);
+        assert_eq!(img.bytes, [76, 76, 76, 150, 150, 150]);
+    }
+
+    #[test]
+    fn histogram_counts_every_sample() {
+        let img = rgb(2, 1, &[0, 0, 255, 7, 7, 7]).to_cuda_image_data();
+        let mut histogram = CudaHistogramData::default();
+        compute_histogram(&img, &mut histogram);
+
+        assert_eq!(histogram.data[0], 2);
+        assert_eq!(histogram.data[7], 3);
+        assert_eq!(histogram.data[255], 1);
+        assert_eq!(histogram.data.iter().sum::<u32>(), 6);
+    }
+
+    #[test]
+    fn gaussian_blur_keeps_flat_images_and_spreads_impulses() {
+        let mut img = gray(3, 3, &[50; 9]).to_cuda_image_data();
+        gaussian_blur(&mut img, 1.5);
+        assert_eq!(img.bytes, [50; 9]);
+
+        let mut pixels = [0; 49];
+        pixels[24] = 255;
+        let mut img = gray(7, 7, &pixels).to_cuda_image_data();
+        gaussian_blur(&mut img, 1.0);
+
+        assert_eq!(img.bytes[24], 41);
+        assert_eq!(img.bytes[23], 25);
+        assert_eq!(img.bytes[25], 25);
+        assert_eq!(img.bytes[17], 25);
+        assert_eq!(img.bytes[31], 25);
+    }
+
+    #[test]
+    fn harris_marks_the_corners_of_a_square() {
+        let mut pixels = vec![0u8; 16 * 16 * 3];
+        for y in 4..12 {
+            for x in 4..12 {
+                pixels[(y * 16 + x) * 3..][..3].fill(255);
+            }
+        }
+        let mut img = rgb(16, 16, &pixels).to_cuda_image_data();
+        harris_corner_detection(&mut img);
+
+        let is_red = |x: usize, y: usize| img.bytes[(y * 16 + x) * 3..][..3] == [255, 0, 0];
+        let near = |cx: usize, cy: usize| {
+            (cx - 2..=cx + 2).any(|x| (cy - 2..=cy + 2).any(|y| is_red(x, y)))
+        };
+
+        assert!(near(4, 4) && near(11, 4) && near(4, 11) && near(11, 11));
+        assert!(!is_red(0, 0) && !is_red(8, 8) && !is_red(8, 4));
+    }
+
+    /// An 8x6 RGB fixture with a gradient background and a flat rectangle,
+    /// so the edge detectors see edges of different strengths and corners.
+    fn fixture() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(8, 6, |x, y| {
+            if (2..6).contains(&x) && (1..4).contains(&y) {
+                image::Rgb([90, 70, 50])
+            } else {
+                image::Rgb([20 + 10 * x as u8, 30, 60 + 5 * y as u8])
+            }
+        }))
+    }
+
+    /// The first channel of every pixel of the 8x6 fixture, by rows.
+    type Plane = [[u8; 8]; 6];
+
+    /// A golden output: the name of the kernel, the kernel and its expected plane.
+    type Golden = (&'static str, fn(&mut CudaImageData), Plane);
+
+    /// Get the first channel of every pixel, by rows.
+    fn plane(img: &CudaImageData) -> Vec<Vec<u8>> {
+        img.bytes
+            .chunks(img.width as usize * 3)
+            .map(|row| row.chunks(3).map(|pixel| pixel[0]).collect())
+            .collect()
+    }
+
+    #[test]
+    fn golden_outputs() {
+        #[rustfmt::skip]
+        let cases: [Golden; 4] = [
+            ("box filter", |img| box_filter(img, 3), [
+                [23, 36, 50, 63, 70, 76, 82, 87],
+                [23, 41, 60, 77, 80, 81, 84, 87],
+                [23, 47, 70, 90, 90, 87, 87, 87],
+                [23, 41, 60, 77, 80, 81, 84, 87],
+                [23, 36, 50, 63, 70, 76, 82, 87],
+                [23, 30, 40, 50, 60, 70, 80, 87],
+            ]),
+            ("gaussian blur", |img| gaussian_blur(img, 1.0), [
+                [24, 35, 49, 61, 69, 75, 81, 87],
+                [26, 41, 62, 75, 80, 82, 84, 87],
+                [26, 43, 68, 82, 86, 86, 85, 87],
+                [26, 41, 62, 75, 80, 82, 84, 87],
+                [24, 35, 49, 61, 69, 75, 81, 87],
+                [24, 31, 42, 52, 62, 71, 80, 86],
+            ]),
+            ("sobel", sobel_edge_detection, [
+                [12, 72, 124, 138, 127, 89, 30, 12],
+                [13, 140, 166, 137, 125, 112, 67, 13],
+                [13, 169, 157, 0, 0, 97, 85, 13],
+                [13, 135, 159, 128, 116, 103, 61, 13],
+                [13, 67, 114, 127, 115, 78, 22, 13],
+                [12, 24, 24, 24, 24, 24, 24, 12],
+            ]),
+            ("laplace", laplace_edge_detection, [
+                [4, 1, 37, 34, 31, 28, 1, 2],
+                [3, 37, 77, 34, 31, 53, 28, 3],
+                [3, 36, 39, 0, 0, 24, 27, 3],
+                [3, 36, 74, 32, 29, 50, 27, 3],
+                [3, 0, 36, 33, 30, 27, 0, 3],
+                [2, 1, 1, 1, 1, 1, 1, 4],
+            ]),
+        ];
+
+        for (name, function, expected) in cases {
+            let mut img = fixture().to_cuda_image_data();
+            function(&mut img);
+            assert_eq!(plane(&img), expected, "{}", name);
+        }
+
+        // The marked corners, a low threshold marks most of a small image
+        let mut img = fixture().to_cuda_image_data();
+        harris_corner_detection(&mut img);
+        let corners: Vec<Vec<bool>> = img
+            .bytes
+            .chunks(8 * 3)
+            .map(|row| row.chunks(3).map(|pixel| pixel == [255, 0, 0]).collect())
+            .collect();
+
+        #[rustfmt::skip]
+        let expected = [
+            [0, 0, 1, 1, 1, 1, 0, 0],
+            [0, 1, 1, 1, 1, 1, 1, 0],
+            [1, 1, 1, 1, 1, 1, 1, 0],
+            [0, 1, 1, 1, 1, 1, 1, 0],
+            [0, 0, 1, 1, 1, 1, 0, 0],
+            [0, 0, 1, 1, 1, 0, 0, 0],
+        ]
+        .map(|row| row.map(|corner| corner == 1));
+        assert_eq!(corners, expected);
+    }
+
+    #[test]
+    #[ignore = "needs libcudaimg and a CUDA device"]
+    fn matches_libcudaimg() {
+        use crate::cudaimg::{process_image, CudaImgLib, ImageProcessingFunction};
+
+        let (libcudaimg, _) = crate::loader::load_libcudaimg(None).unwrap();
+        let cuda = CudaImgLib::new(libcudaimg).unwrap();
+
+        let functions = [
+            ImageProcessingFunction::Invert,
+            ImageProcessingFunction::GammaTransform(2.2),
+            ImageProcessingFunction::LogarithmicTransform(10.0),
+            ImageProcessingFunction::Grayscale,
+            ImageProcessingFunction::BalanceHistogram,
+            ImageProcessingFunction::BoxFilter(2),
+            ImageProcessingFunction::BoxFilter(3),
+            ImageProcessingFunction::GaussianBlur(1.0),
+            ImageProcessingFunction::SobelEdgeDetection,
+            ImageProcessingFunction::LaplaceEdgeDetection,
+            ImageProcessingFunction::HarrisCornerDetection,
+        ];
+
+        for function in functions {
+            let expected = process_image(&cuda, &fixture(), function).unwrap();
+            let actual = process_image(&CpuBackend, &fixture(), function).unwrap();
+
+            // Allow the rounding of floating point math on the device to differ by one
+            let differs = expected
+                .as_bytes()
+                .iter()
+                .zip(actual.as_bytes())
+                .any(|(e, a)| e.abs_diff(*a) > 1);
+            assert!(!differs, "{:?} differs from libcudaimg", function);
+        }
+    }
+}
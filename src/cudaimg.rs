@@ -1,80 +1,266 @@
+use crate::backend::ImageBackend;
+use crate::cpuimg::{self, CpuBackend};
+use crate::histogram::{self, HistogramChannel};
 use image::DynamicImage;
 use libloading::{Library, Symbol};
-use log::info;
-use plotters::prelude::*;
+use log::{info, warn};
+
+/// The ABI version of libcudaimg the function definitions below match.
+/// Libraries that do not export getAbiVersion have version 1.
+pub const LIBCUDAIMG_ABI_VERSION: u32 = 2;
+
+/// The oldest ABI version of libcudaimg that can still be loaded.
+/// The functions of version 1 return nothing, so their errors can not be reported.
+pub const LIBCUDAIMG_MIN_ABI_VERSION: u32 = 1;
+
+/// The status returned by the functions of libcudaimg when they succeed, since ABI version 2.
+const STATUS_SUCCESS: i32 = 0;
+
+/// The status returned when there is no CUDA device.
+const STATUS_DEVICE_MISSING: i32 = 1;
+
+/// The status returned when the CUDA device runs out of memory.
+const STATUS_OUT_OF_MEMORY: i32 = 2;
+
+/// The status returned when an argument is rejected by the library.
+const STATUS_INVALID_ARGUMENT: i32 = 3;
+
+/// Definition of the getAbiVersion function from libcudaimg.
+type GetAbiVersionFn = unsafe extern "C" fn() -> u32;
+
+// The functions below return `()` in ABI version 1 and a status code since version 2
 
 /// Definition of the invertImage function from libcudaimg.
-type InvertImageFn = unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32);
+type InvertImageFn<R> =
+    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32) -> R;
 
 /// Definition of the gammaTransformImage function from libcudaimg.
-type GammaTransformImage =
-    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32, gamma: f32);
+type GammaTransformImage<R> =
+    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32, gamma: f32) -> R;
 
 /// Definition of the logarithmicTransformImage function from libcudaimg.
-type LogarithmicTransformImage =
-    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32, base: f32);
+type LogarithmicTransformImage<R> =
+    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32, base: f32) -> R;
 
 /// Definition of the grayscaleImage function from libcudaimg.
-type GrayscaleImageFn =
-    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32);
+type GrayscaleImageFn<R> =
+    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32) -> R;
 
 /// Definition of the computeHistogram function from libcudaimg.
-type ComputeHistogramFn = unsafe extern "C" fn(
+type ComputeHistogramFn<R> = unsafe extern "C" fn(
     image: *mut u8,
     image_len: u32,
     histogram: *mut u32,
     width: u32,
     height: u32,
-);
+) -> R;
 
 /// Definition of the balanceHistogram function from libcudaimg.
-type BalanceHistogramFn =
-    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32);
+type BalanceHistogramFn<R> =
+    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32) -> R;
 
 /// Definition of the boxFilter function from libcudaimg.
-type BoxFilterFn =
-    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32, filter_size: u32);
+type BoxFilterFn<R> = unsafe extern "C" fn(
+    image: *mut u8,
+    image_len: u32,
+    width: u32,
+    height: u32,
+    filter_size: u32,
+) -> R;
 
-// Definition of the gaussFilter function from libcudaimg.
-type GaussianBlurFn =
-    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32, sigma: f32);
+/// Definition of the gaussianBlur function from libcudaimg.
+type GaussianBlurFn<R> =
+    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32, sigma: f32) -> R;
 
 /// Definition of the sobelEdgeDetection function from libcudaimg.
-type SobelEdgeDetectionFn =
-    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32);
+type SobelEdgeDetectionFn<R> =
+    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32) -> R;
 
 /// Definition of the laplaceEdgeDetection function from libcudaimg.
-type LaplaceEdgeDetectionFn =
-    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32);
+type LaplaceEdgeDetectionFn<R> =
+    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32) -> R;
 
 /// Definition of the harrisCornerDetection function from libcudaimg.
-type HarrisCornerDetectionFn =
-    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32);
+type HarrisCornerDetectionFn<R> =
+    unsafe extern "C" fn(image: *mut u8, image_len: u32, width: u32, height: u32) -> R;
+
+/// A function of libcudaimg, resolved with the signature of the library's ABI version.
+///
+/// * `Void` - A function of ABI version 1, which does not report errors.
+/// * `Status` - A function returning a status code, since ABI version 2.
+#[derive(Clone, Copy)]
+enum Kernel<V, S> {
+    Void(V),
+    Status(S),
+}
+
+/// Call a resolved function of libcudaimg and check its status.
+/// Functions of ABI version 1 are assumed to succeed.
+macro_rules! call_kernel {
+    ($kernel:expr, $($arg:expr),* $(,)?) => {{
+        let status = match $kernel {
+            Kernel::Void(kernel) => {
+                unsafe { kernel($($arg),*) };
+                STATUS_SUCCESS
+            }
+            Kernel::Status(kernel) => unsafe { kernel($($arg),*) },
+        };
+        CudaImgError::from_status(status)
+    }};
+}
+
+/// Enum to represent the errors of the image processing functions.
+/// The arguments are validated before libcudaimg is called, the other errors are reported
+/// by the library through the status codes of ABI version 2.
+///
+/// * `InvalidArgument` - An argument of the operation is invalid.
+/// * `DeviceMissing` - There is no CUDA device to run the operation on.
+/// * `OutOfMemory` - The CUDA device ran out of memory.
+/// * `KernelFailure` - The kernel failed with a status code of its own.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CudaImgError {
+    InvalidArgument(String),
+    DeviceMissing,
+    OutOfMemory,
+    KernelFailure(i32),
+}
+
+impl CudaImgError {
+    /// Map the status code returned by a function of libcudaimg to an error.
+    pub fn from_status(status: i32) -> Result<(), CudaImgError> {
+        match status {
+            STATUS_SUCCESS => Ok(()),
+            STATUS_DEVICE_MISSING => Err(CudaImgError::DeviceMissing),
+            STATUS_OUT_OF_MEMORY => Err(CudaImgError::OutOfMemory),
+            STATUS_INVALID_ARGUMENT => Err(CudaImgError::InvalidArgument(
+                "rejected by libcudaimg".to_string(),
+            )),
+            status => Err(CudaImgError::KernelFailure(status)),
+        }
+    }
+}
+
+impl std::fmt::Display for CudaImgError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CudaImgError::InvalidArgument(reason) => write!(f, "Invalid argument: {}", reason),
+            CudaImgError::DeviceMissing => write!(f, "No CUDA device is available"),
+            CudaImgError::OutOfMemory => write!(f, "The CUDA device ran out of memory"),
+            CudaImgError::KernelFailure(status) => {
+                write!(f, "The CUDA kernel failed with status {}", status)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CudaImgError {}
 
 /// Trait to convert an image to CudaImageData.
 pub trait ToCudaImageData {
     fn to_cuda_image_data(&self) -> CudaImageData;
 }
 
+/// Enum to represent the type of the samples of an image.
+///
+/// * `U8` - 8-bit unsigned integers, the only type libcudaimg processes.
+/// * `U16` - 16-bit unsigned integers, e.g. of 16-bit PNG and TIFF images.
+/// * `F32` - 32-bit floats with 1.0 as white, e.g. of HDR and OpenEXR images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleType {
+    U8,
+    U16,
+    F32,
+}
+
+impl SampleType {
+    /// Get the size of a sample in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            SampleType::U8 => 1,
+            SampleType::U16 => 2,
+            SampleType::F32 => 4,
+        }
+    }
+
+    /// Get the value of a white sample.
+    pub fn max_value(&self) -> f32 {
+        match self {
+            SampleType::U8 => u8::MAX as f32,
+            SampleType::U16 => u16::MAX as f32,
+            SampleType::F32 => 1.0,
+        }
+    }
+
+    /// Round and clamp a value to the range of the sample type.
+    /// Floating point samples are only kept non-negative, so values above white survive.
+    pub fn clamp(&self, value: f32) -> f32 {
+        match self {
+            SampleType::F32 if value.is_nan() => 0.0,
+            SampleType::F32 => value.max(0.0),
+            _ => value.round().clamp(0.0, self.max_value()),
+        }
+    }
+}
+
 /// Struct to hold the image data for communication with libcudaimg.
 ///
 /// # Fields
 ///
-/// * `bytes` - The image data as a vector of bytes.
-/// * `raw_len` - The length of the raw image data.
+/// * `bytes` - The interleaved color samples of the image in native byte order.
+/// * `raw_len` - The length of the raw image data in bytes.
 /// * `width` - The width of the image.
 /// * `height` - The height of the image.
-/// * `pixel_size` - The size of each pixel in bytes.
+/// * `channels` - The number of color samples of each pixel.
+/// * `sample_type` - The type of the samples.
+/// * `alpha` - The alpha channel of the image in the range of the sample type, if it has one.
+///   It is kept apart from the color samples, so the operations leave it untouched.
 pub struct CudaImageData {
     pub bytes: Vec<u8>,
     pub raw_len: u32,
     pub width: u32,
     pub height: u32,
-    pub pixel_size: u32,
+    pub channels: u32,
+    pub sample_type: SampleType,
+    pub alpha: Option<Vec<f32>>,
+}
+
+/// Convert samples to their bytes in native byte order.
+fn to_ne_bytes<T: Copy, const N: usize>(samples: &[T], to_bytes: fn(T) -> [u8; N]) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|&sample| to_bytes(sample))
+        .collect()
+}
+
+/// Split interleaved samples with a trailing alpha sample per pixel
+/// into the bytes of the color samples and the alpha channel.
+fn split_alpha<T: Copy, const N: usize>(
+    samples: &[T],
+    channels: usize,
+    to_bytes: fn(T) -> [u8; N],
+    to_f32: fn(T) -> f32,
+) -> (Vec<u8>, Option<Vec<f32>>) {
+    let bytes = samples
+        .chunks_exact(channels + 1)
+        .flat_map(|pixel| {
+            pixel[..channels]
+                .iter()
+                .flat_map(|&sample| to_bytes(sample))
+        })
+        .collect();
+    let alpha = samples
+        .chunks_exact(channels + 1)
+        .map(|pixel| to_f32(pixel[channels]))
+        .collect();
+
+    (bytes, Some(alpha))
 }
 
 impl ToCudaImageData for DynamicImage {
     /// Get the image data from a DynamicImage.
+    /// The samples keep the bit depth of the image, 8-bit, 16-bit or floating point,
+    /// and the alpha channel is kept if the image has one.
+    /// Grayscale images have a single color channel, other images are RGB.
     ///
     /// # Arguments
     ///
@@ -84,25 +270,386 @@ impl ToCudaImageData for DynamicImage {
     ///
     /// * An ImageData struct containing the image data.
     fn to_cuda_image_data(&self) -> CudaImageData {
-        let img_rgb8 = self.to_rgb8();
-        let bytes = img_rgb8.as_raw().to_owned();
+        let sample_type = match self {
+            DynamicImage::ImageLuma16(_)
+            | DynamicImage::ImageLumaA16(_)
+            | DynamicImage::ImageRgb16(_)
+            | DynamicImage::ImageRgba16(_) => SampleType::U16,
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => SampleType::F32,
+            _ => SampleType::U8,
+        };
+
+        let color = self.color();
+        let channels = if color.has_color() { 3 } else { 1 };
+
+        let (bytes, alpha) = match (sample_type, channels, color.has_alpha()) {
+            (SampleType::U8, 1, false) => (self.to_luma8().into_raw(), None),
+            (SampleType::U8, 1, true) => split_alpha(
+                self.to_luma_alpha8().as_raw(),
+                1,
+                u8::to_ne_bytes,
+                f32::from,
+            ),
+            (SampleType::U8, _, false) => (self.to_rgb8().into_raw(), None),
+            (SampleType::U8, _, true) => {
+                split_alpha(self.to_rgba8().as_raw(), 3, u8::to_ne_bytes, f32::from)
+            }
+            (SampleType::U16, 1, false) => (
+                to_ne_bytes(self.to_luma16().as_raw(), u16::to_ne_bytes),
+                None,
+            ),
+            (SampleType::U16, 1, true) => split_alpha(
+                self.to_luma_alpha16().as_raw(),
+                1,
+                u16::to_ne_bytes,
+                f32::from,
+            ),
+            (SampleType::U16, _, false) => (
+                to_ne_bytes(self.to_rgb16().as_raw(), u16::to_ne_bytes),
+                None,
+            ),
+            (SampleType::U16, _, true) => {
+                split_alpha(self.to_rgba16().as_raw(), 3, u16::to_ne_bytes, f32::from)
+            }
+            (SampleType::F32, _, false) => (
+                to_ne_bytes(self.to_rgb32f().as_raw(), f32::to_ne_bytes),
+                None,
+            ),
+            (SampleType::F32, _, true) => {
+                split_alpha(self.to_rgba32f().as_raw(), 3, f32::to_ne_bytes, |v| v)
+            }
+        };
         let raw_len = bytes.len() as u32;
 
         CudaImageData {
             bytes,
             raw_len,
-            width: img_rgb8.width(),
-            height: img_rgb8.height(),
-            pixel_size: 3, // RGB format (3 bytes per pixel)
+            width: self.width(),
+            height: self.height(),
+            channels: if sample_type == SampleType::F32 {
+                3
+            } else {
+                channels
+            },
+            sample_type,
+            alpha,
         }
     }
 }
 
+impl CudaImageData {
+    /// Get the color samples of the image as floats, in the range of the sample type.
+    pub fn samples(&self) -> Vec<f32> {
+        match self.sample_type {
+            SampleType::U8 => self.bytes.iter().map(|&b| b as f32).collect(),
+            SampleType::U16 => self
+                .bytes
+                .chunks_exact(2)
+                .map(|b| u16::from_ne_bytes([b[0], b[1]]) as f32)
+                .collect(),
+            SampleType::F32 => self
+                .bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+        }
+    }
+
+    /// Replace the color samples of the image, rounding and clamping them to the sample type.
+    pub fn set_samples(&mut self, samples: &[f32]) {
+        let sample_type = self.sample_type;
+
+        match sample_type {
+            SampleType::U8 => self
+                .bytes
+                .iter_mut()
+                .zip(samples)
+                .for_each(|(b, &value)| *b = sample_type.clamp(value) as u8),
+            SampleType::U16 => {
+                self.bytes
+                    .chunks_exact_mut(2)
+                    .zip(samples)
+                    .for_each(|(b, &value)| {
+                        b.copy_from_slice(&(sample_type.clamp(value) as u16).to_ne_bytes())
+                    })
+            }
+            SampleType::F32 => self
+                .bytes
+                .chunks_exact_mut(4)
+                .zip(samples)
+                .for_each(|(b, &value)| b.copy_from_slice(&sample_type.clamp(value).to_ne_bytes())),
+        }
+    }
+
+    /// Multiply the color samples by the alpha channel,
+    /// so spatial filters do not spread the color of transparent pixels.
+    pub fn premultiply_alpha(&mut self) {
+        let Some(alpha) = &self.alpha else {
+            return;
+        };
+
+        let max = self.sample_type.max_value();
+        let mut samples = self.samples();
+        samples
+            .chunks_exact_mut(self.channels as usize)
+            .zip(alpha)
+            .for_each(|(pixel, &a)| pixel.iter_mut().for_each(|v| *v *= a / max));
+
+        self.set_samples(&samples);
+    }
+
+    /// Divide the color samples by the alpha channel, undoing [`Self::premultiply_alpha`].
+    /// Fully transparent pixels become black.
+    pub fn unpremultiply_alpha(&mut self) {
+        let Some(alpha) = &self.alpha else {
+            return;
+        };
+
+        let max = self.sample_type.max_value();
+        let mut samples = self.samples();
+        samples
+            .chunks_exact_mut(self.channels as usize)
+            .zip(alpha)
+            .for_each(|(pixel, &a)| {
+                pixel
+                    .iter_mut()
+                    .for_each(|v| *v = if a > 0.0 { *v * max / a } else { 0.0 })
+            });
+
+        self.set_samples(&samples);
+    }
+
+    /// Convert the image to another sample type, scaling the samples and the alpha channel
+    /// to its range.
+    ///
+    /// # Arguments
+    ///
+    /// * `sample_type` - The sample type of the converted image.
+    pub fn to_sample_type(&self, sample_type: SampleType) -> CudaImageData {
+        let scale = sample_type.max_value() / self.sample_type.max_value();
+        let samples: Vec<f32> = self.samples().iter().map(|v| v * scale).collect();
+        let bytes = vec![0; samples.len() * sample_type.size()];
+
+        let mut img = CudaImageData {
+            raw_len: bytes.len() as u32,
+            bytes,
+            width: self.width,
+            height: self.height,
+            channels: self.channels,
+            sample_type,
+            alpha: self.alpha.as_ref().map(|alpha| {
+                alpha
+                    .iter()
+                    .map(|&a| sample_type.clamp(a * scale).min(sample_type.max_value()))
+                    .collect()
+            }),
+        };
+        img.set_samples(&samples);
+
+        img
+    }
+
+    /// Get the alpha channel as an image with every color channel set to it,
+    /// so it can be processed by the backends like the color samples.
+    pub fn alpha_image(&self) -> Option<CudaImageData> {
+        let alpha = self.alpha.as_ref()?;
+
+        let mut img = CudaImageData {
+            bytes: vec![0; self.bytes.len()],
+            raw_len: self.raw_len,
+            width: self.width,
+            height: self.height,
+            channels: self.channels,
+            sample_type: self.sample_type,
+            alpha: None,
+        };
+        let samples: Vec<f32> = alpha
+            .iter()
+            .flat_map(|&a| std::iter::repeat_n(a, self.channels as usize))
+            .collect();
+        img.set_samples(&samples);
+
+        Some(img)
+    }
+
+    /// Replace the alpha channel with the first channel of an image made by [`Self::alpha_image`].
+    pub fn set_alpha_from(&mut self, img: &CudaImageData) {
+        let max = self.sample_type.max_value();
+        let alpha = img
+            .samples()
+            .chunks_exact(img.channels as usize)
+            .map(|pixel| pixel[0].clamp(0.0, max))
+            .collect();
+
+        self.alpha = Some(alpha);
+    }
+
+    /// Create a single channel image of the same size and sample type from samples.
+    fn plane(&self, samples: &[f32]) -> CudaImageData {
+        let bytes = vec![0; samples.len() * self.sample_type.size()];
+
+        let mut plane = CudaImageData {
+            raw_len: bytes.len() as u32,
+            bytes,
+            width: self.width,
+            height: self.height,
+            channels: 1,
+            sample_type: self.sample_type,
+            alpha: None,
+        };
+        plane.set_samples(samples);
+
+        plane
+    }
+
+    /// Get a color channel of the image as a single channel image.
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The index of the channel, e.g. 0 for red.
+    pub fn channel_plane(&self, channel: usize) -> CudaImageData {
+        let samples: Vec<f32> = self
+            .samples()
+            .chunks_exact(self.channels as usize)
+            .map(|pixel| pixel[channel])
+            .collect();
+
+        self.plane(&samples)
+    }
+
+    /// Get the luminance of the image as a single channel image.
+    pub fn luma_plane(&self) -> CudaImageData {
+        let samples: Vec<f32> = self
+            .samples()
+            .chunks_exact(self.channels as usize)
+            .map(cpuimg::luminance)
+            .collect();
+
+        self.plane(&samples)
+    }
+
+    /// Keep only the first color channel of the image.
+    /// Used once every channel holds the same value, e.g. after a grayscale conversion.
+    pub fn reduce_to_gray(&mut self) {
+        if self.channels == 1 {
+            return;
+        }
+
+        let size = self.sample_type.size();
+        self.bytes = self
+            .bytes
+            .chunks_exact(size * self.channels as usize)
+            .flat_map(|pixel| pixel[..size].iter().copied())
+            .collect();
+        self.raw_len = self.bytes.len() as u32;
+        self.channels = 1;
+    }
+
+    /// Copy the single color channel of a grayscale image into three RGB channels.
+    pub fn expand_to_rgb(&mut self) {
+        if self.channels != 1 {
+            return;
+        }
+
+        let size = self.sample_type.size();
+        self.bytes = self
+            .bytes
+            .chunks_exact(size)
+            .flat_map(|sample| sample.repeat(3))
+            .collect();
+        self.raw_len = self.bytes.len() as u32;
+        self.channels = 3;
+    }
+
+    /// Create a DynamicImage from the image data.
+    ///
+    /// # Returns
+    ///
+    /// * The image data as a grayscale or RGB DynamicImage, with an alpha channel if it has one
+    ///   and the bit depth of the samples. Floating point grayscale images are returned as RGB.
+    pub fn into_dynamic_image(mut self) -> anyhow::Result<DynamicImage> {
+        let error = || anyhow::anyhow!("Failed to create the modified image from bytes");
+
+        if self.sample_type == SampleType::F32 {
+            // There are no floating point grayscale images
+            self.expand_to_rgb();
+        }
+
+        let (width, height) = (self.width, self.height);
+        let gray = self.channels == 1;
+        let has_alpha = self.alpha.is_some();
+
+        if self.sample_type == SampleType::U8 && !has_alpha {
+            let image = if gray {
+                DynamicImage::ImageLuma8(
+                    image::GrayImage::from_raw(width, height, self.bytes).ok_or_else(error)?,
+                )
+            } else {
+                DynamicImage::ImageRgb8(
+                    image::RgbImage::from_raw(width, height, self.bytes).ok_or_else(error)?,
+                )
+            };
+            return Ok(image);
+        }
+
+        let samples: Vec<f32> = match &self.alpha {
+            Some(alpha) => self
+                .samples()
+                .chunks_exact(self.channels as usize)
+                .zip(alpha)
+                .flat_map(|(pixel, &a)| pixel.iter().copied().chain([a]))
+                .collect(),
+            None => self.samples(),
+        };
+
+        let image = match self.sample_type {
+            SampleType::U8 => {
+                let raw = samples.iter().map(|&v| v as u8).collect();
+                if gray {
+                    DynamicImage::ImageLumaA8(
+                        image::ImageBuffer::from_raw(width, height, raw).ok_or_else(error)?,
+                    )
+                } else {
+                    DynamicImage::ImageRgba8(
+                        image::ImageBuffer::from_raw(width, height, raw).ok_or_else(error)?,
+                    )
+                }
+            }
+            SampleType::U16 => {
+                let raw = samples.iter().map(|&v| v as u16).collect();
+                match (gray, has_alpha) {
+                    (true, false) => DynamicImage::ImageLuma16(
+                        image::ImageBuffer::from_raw(width, height, raw).ok_or_else(error)?,
+                    ),
+                    (true, true) => DynamicImage::ImageLumaA16(
+                        image::ImageBuffer::from_raw(width, height, raw).ok_or_else(error)?,
+                    ),
+                    (false, false) => DynamicImage::ImageRgb16(
+                        image::ImageBuffer::from_raw(width, height, raw).ok_or_else(error)?,
+                    ),
+                    (false, true) => DynamicImage::ImageRgba16(
+                        image::ImageBuffer::from_raw(width, height, raw).ok_or_else(error)?,
+                    ),
+                }
+            }
+            SampleType::F32 if has_alpha => DynamicImage::ImageRgba32F(
+                image::ImageBuffer::from_raw(width, height, samples).ok_or_else(error)?,
+            ),
+            SampleType::F32 => DynamicImage::ImageRgb32F(
+                image::ImageBuffer::from_raw(width, height, samples).ok_or_else(error)?,
+            ),
+        };
+
+        Ok(image)
+    }
+}
+
 /// Struct to hold the histogram data for communication with libcudaimg.
 ///
 /// # Fields
 ///
 /// * `data` - The histogram data as a vector of u32 values.
+#[derive(Debug, Clone, PartialEq)]
 pub struct CudaHistogramData {
     pub data: Vec<u32>,
 }
@@ -126,6 +673,9 @@ impl Default for CudaHistogramData {
 /// * `BoxFilter` - Apply a box filter to the image.
 /// * `GaussianBlur` - Apply a Gaussian blur to the image.
 /// * `SobelEdgeDetection` - Apply Sobel edge detection to the image.
+/// * `LaplaceEdgeDetection` - Apply Laplace edge detection to the image.
+/// * `HarrisCornerDetection` - Apply Harris corner detection to the image.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ImageProcessingFunction {
     Invert,
     GammaTransform(f32),
@@ -140,232 +690,999 @@ pub enum ImageProcessingFunction {
     HarrisCornerDetection,
 }
 
-/// Plot a histogram using plotters.
+impl ImageProcessingFunction {
+    /// Get the parameter values of the function, in the order the operation registry lists them.
+    pub fn params(&self) -> Vec<f32> {
+        match *self {
+            ImageProcessingFunction::GammaTransform(gamma) => vec![gamma],
+            ImageProcessingFunction::LogarithmicTransform(base) => vec![base],
+            ImageProcessingFunction::BoxFilter(filter_size) => vec![filter_size as f32],
+            ImageProcessingFunction::GaussianBlur(sigma) => vec![sigma],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Get the operation of the function without its parameters.
+    pub fn operation(&self) -> ImageOperation {
+        match self {
+            ImageProcessingFunction::Invert => ImageOperation::Invert,
+            ImageProcessingFunction::GammaTransform(_) => ImageOperation::GammaTransform,
+            ImageProcessingFunction::LogarithmicTransform(_) => {
+                ImageOperation::LogarithmicTransform
+            }
+            ImageProcessingFunction::Grayscale => ImageOperation::Grayscale,
+            ImageProcessingFunction::ComputeHistogram => ImageOperation::ComputeHistogram,
+            ImageProcessingFunction::BalanceHistogram => ImageOperation::BalanceHistogram,
+            ImageProcessingFunction::BoxFilter(_) => ImageOperation::BoxFilter,
+            ImageProcessingFunction::GaussianBlur(_) => ImageOperation::GaussianBlur,
+            ImageProcessingFunction::SobelEdgeDetection => ImageOperation::SobelEdgeDetection,
+            ImageProcessingFunction::LaplaceEdgeDetection => ImageOperation::LaplaceEdgeDetection,
+            ImageProcessingFunction::HarrisCornerDetection => ImageOperation::HarrisCornerDetection,
+        }
+    }
+}
+
+impl ImageProcessingFunction {
+    /// Validate the parameters of the function for an image before running it.
+    /// This only rejects invalid parameters with a reason, failures of the kernels themselves
+    /// are reported by libcudaimg through its status codes.
+    ///
+    /// # Arguments
+    ///
+    /// * `img` - The image the function will be applied to.
+    pub fn validate(&self, img: &CudaImageData) -> Result<(), CudaImgError> {
+        let invalid = |reason: String| Err(CudaImgError::InvalidArgument(reason));
+
+        if img.width == 0 || img.height == 0 {
+            return invalid("the image is empty".to_string());
+        }
+
+        match *self {
+            ImageProcessingFunction::GammaTransform(gamma)
+                if !gamma.is_finite() || gamma <= 0.0 =>
+            {
+                invalid(format!("gamma must be positive, got {}", gamma))
+            }
+            ImageProcessingFunction::LogarithmicTransform(base)
+                if !base.is_finite() || base <= 0.0 =>
+            {
+                invalid(format!("the logarithm base must be positive, got {}", base))
+            }
+            ImageProcessingFunction::BoxFilter(filter_size)
+                if filter_size == 0 || filter_size > img.width.min(img.height) =>
+            {
+                invalid(format!(
+                    "the filter size must be between 1 and {}, got {}",
+                    img.width.min(img.height),
+                    filter_size
+                ))
+            }
+            ImageProcessingFunction::GaussianBlur(sigma) if !sigma.is_finite() || sigma <= 0.0 => {
+                invalid(format!("sigma must be positive, got {}", sigma))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Enum to represent the operations a backend can support, without their parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ImageOperation {
+    Invert,
+    GammaTransform,
+    LogarithmicTransform,
+    Grayscale,
+    ComputeHistogram,
+    BalanceHistogram,
+    BoxFilter,
+    GaussianBlur,
+    SobelEdgeDetection,
+    LaplaceEdgeDetection,
+    HarrisCornerDetection,
+}
+
+impl ImageOperation {
+    /// Every operation, in the order they are listed in the Tools menu.
+    pub const ALL: [ImageOperation; 11] = [
+        ImageOperation::Invert,
+        ImageOperation::GammaTransform,
+        ImageOperation::LogarithmicTransform,
+        ImageOperation::Grayscale,
+        ImageOperation::ComputeHistogram,
+        ImageOperation::BalanceHistogram,
+        ImageOperation::BoxFilter,
+        ImageOperation::GaussianBlur,
+        ImageOperation::SobelEdgeDetection,
+        ImageOperation::LaplaceEdgeDetection,
+        ImageOperation::HarrisCornerDetection,
+    ];
+
+    /// Check whether the operation produces a modified image.
+    /// Analysis operations such as the histogram produce a chart instead.
+    pub fn modifies_image(&self) -> bool {
+        !matches!(self, ImageOperation::ComputeHistogram)
+    }
+
+    /// Check whether the operation handles every sample on its own,
+    /// so it gives the same result whatever the number of channels of the image is.
+    pub fn is_per_sample(&self) -> bool {
+        matches!(
+            self,
+            ImageOperation::Invert
+                | ImageOperation::GammaTransform
+                | ImageOperation::LogarithmicTransform
+                | ImageOperation::ComputeHistogram
+        )
+    }
+
+    /// Get the name of the function exported by libcudaimg for the operation.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            ImageOperation::Invert => "invertImage",
+            ImageOperation::GammaTransform => "gammaTransformImage",
+            ImageOperation::LogarithmicTransform => "logarithmicTransformImage",
+            ImageOperation::Grayscale => "grayscaleImage",
+            ImageOperation::ComputeHistogram => "computeHistogram",
+            ImageOperation::BalanceHistogram => "balanceHistogram",
+            ImageOperation::BoxFilter => "boxFilter",
+            ImageOperation::GaussianBlur => "gaussianBlur",
+            ImageOperation::SobelEdgeDetection => "sobelEdgeDetection",
+            ImageOperation::LaplaceEdgeDetection => "laplaceEdgeDetection",
+            ImageOperation::HarrisCornerDetection => "harrisCornerDetection",
+        }
+    }
+}
+
+/// Resolve an exported function of libcudaimg, recording the operation if it is missing.
 ///
-/// # Arguments
+/// # Safety
 ///
-/// * `histogram` - The histogram data to plot.
+/// `V` and `S` have to match the signature of the exported function
+/// in ABI version 1 and since version 2.
+unsafe fn resolve<V: Copy, S: Copy>(
+    libcudaimg: &Library,
+    abi_version: u32,
+    operation: ImageOperation,
+    missing: &mut Vec<(ImageOperation, libloading::Error)>,
+) -> Option<Kernel<V, S>> {
+    let symbol = operation.symbol().as_bytes();
+    let kernel = if abi_version == 1 {
+        libcudaimg
+            .get::<V>(symbol)
+            .map(|symbol| Kernel::Void(*symbol))
+    } else {
+        libcudaimg
+            .get::<S>(symbol)
+            .map(|symbol| Kernel::Status(*symbol))
+    };
+
+    match kernel {
+        Ok(kernel) => Some(kernel),
+        Err(e) => {
+            missing.push((operation, e));
+            None
+        }
+    }
+}
+
+/// The libcudaimg library with every exported function resolved once at load time.
+/// The function pointers stay valid as long as the struct is alive, since it owns the library,
+/// so it can be shared immutably between tasks.
 ///
-/// # Returns
+/// # Fields
 ///
-/// * The plotted histogram as a DynamicImage.
-pub fn plot_histogram(histogram: &CudaHistogramData) -> anyhow::Result<DynamicImage> {
-    let root = BitMapBackend::new("data/histogram.png", (600, 400)).into_drawing_area();
-    root.fill(&WHITE)?;
-
-    let mut chart = ChartBuilder::on(&root)
-        .caption("Histogram", ("sans-serif", 30).into_font())
-        .margin(5)
-        .x_label_area_size(30)
-        .y_label_area_size(30)
-        .build_cartesian_2d(
-            (0u32..256u32).into_segmented(),
-            0u32..*histogram.data.iter().max().unwrap_or(&0),
+/// * `_libcudaimg` - The loaded libcudaimg library, kept alive for the function pointers.
+/// * `abi_version` - The ABI version reported by the library.
+/// * `supported_operations` - The operations exported by the library.
+/// * The remaining fields are the resolved functions, `None` if the library does not export them.
+pub struct CudaImgLib {
+    _libcudaimg: Library,
+    abi_version: u32,
+    supported_operations: Vec<ImageOperation>,
+    invert_image: Option<Kernel<InvertImageFn<()>, InvertImageFn<i32>>>,
+    gamma_transform_image: Option<Kernel<GammaTransformImage<()>, GammaTransformImage<i32>>>,
+    logarithmic_transform_image:
+        Option<Kernel<LogarithmicTransformImage<()>, LogarithmicTransformImage<i32>>>,
+    grayscale_image: Option<Kernel<GrayscaleImageFn<()>, GrayscaleImageFn<i32>>>,
+    compute_histogram: Option<Kernel<ComputeHistogramFn<()>, ComputeHistogramFn<i32>>>,
+    balance_histogram: Option<Kernel<BalanceHistogramFn<()>, BalanceHistogramFn<i32>>>,
+    box_filter: Option<Kernel<BoxFilterFn<()>, BoxFilterFn<i32>>>,
+    gaussian_blur: Option<Kernel<GaussianBlurFn<()>, GaussianBlurFn<i32>>>,
+    sobel_edge_detection: Option<Kernel<SobelEdgeDetectionFn<()>, SobelEdgeDetectionFn<i32>>>,
+    laplace_edge_detection: Option<Kernel<LaplaceEdgeDetectionFn<()>, LaplaceEdgeDetectionFn<i32>>>,
+    harris_corner_detection:
+        Option<Kernel<HarrisCornerDetectionFn<()>, HarrisCornerDetectionFn<i32>>>,
+}
+
+impl CudaImgLib {
+    /// Check the ABI version of the loaded libcudaimg library and resolve its functions.
+    /// A library without getAbiVersion is treated as version 1.
+    /// A mismatched build is rejected before any of its functions are called,
+    /// missing functions are collected and their operations reported as unsupported.
+    ///
+    /// # Arguments
+    ///
+    /// * `libcudaimg` - The libcudaimg library to use for image processing.
+    pub fn new(libcudaimg: Library) -> anyhow::Result<Self> {
+        let get_abi_version: Option<Symbol<GetAbiVersionFn>> =
+            unsafe { libcudaimg.get(b"getAbiVersion\0") }.ok();
+
+        let abi_version = match get_abi_version {
+            Some(get_abi_version) => unsafe { get_abi_version() },
+            None => 1,
+        };
+        if !(LIBCUDAIMG_MIN_ABI_VERSION..=LIBCUDAIMG_ABI_VERSION).contains(&abi_version) {
+            anyhow::bail!(
+                "libcudaimg has ABI version {}, but versions {} to {} are supported",
+                abi_version,
+                LIBCUDAIMG_MIN_ABI_VERSION,
+                LIBCUDAIMG_ABI_VERSION
+            );
+        }
+        if abi_version < LIBCUDAIMG_ABI_VERSION {
+            warn!(
+                "libcudaimg has ABI version {}, its errors are not reported",
+                abi_version
+            );
+        }
+
+        // The ABI version is supported, so the definitions above match the exported functions
+        let mut missing = Vec::new();
+        let invert_image = unsafe {
+            resolve(
+                &libcudaimg,
+                abi_version,
+                ImageOperation::Invert,
+                &mut missing,
+            )
+        };
+        let gamma_transform_image = unsafe {
+            resolve(
+                &libcudaimg,
+                abi_version,
+                ImageOperation::GammaTransform,
+                &mut missing,
+            )
+        };
+        let logarithmic_transform_image = unsafe {
+            resolve(
+                &libcudaimg,
+                abi_version,
+                ImageOperation::LogarithmicTransform,
+                &mut missing,
+            )
+        };
+        let grayscale_image = unsafe {
+            resolve(
+                &libcudaimg,
+                abi_version,
+                ImageOperation::Grayscale,
+                &mut missing,
+            )
+        };
+        let compute_histogram = unsafe {
+            resolve(
+                &libcudaimg,
+                abi_version,
+                ImageOperation::ComputeHistogram,
+                &mut missing,
+            )
+        };
+        let balance_histogram = unsafe {
+            resolve(
+                &libcudaimg,
+                abi_version,
+                ImageOperation::BalanceHistogram,
+                &mut missing,
+            )
+        };
+        let box_filter = unsafe {
+            resolve(
+                &libcudaimg,
+                abi_version,
+                ImageOperation::BoxFilter,
+                &mut missing,
+            )
+        };
+        let gaussian_blur = unsafe {
+            resolve(
+                &libcudaimg,
+                abi_version,
+                ImageOperation::GaussianBlur,
+                &mut missing,
+            )
+        };
+        let sobel_edge_detection = unsafe {
+            resolve(
+                &libcudaimg,
+                abi_version,
+                ImageOperation::SobelEdgeDetection,
+                &mut missing,
+            )
+        };
+        let laplace_edge_detection = unsafe {
+            resolve(
+                &libcudaimg,
+                abi_version,
+                ImageOperation::LaplaceEdgeDetection,
+                &mut missing,
+            )
+        };
+        let harris_corner_detection = unsafe {
+            resolve(
+                &libcudaimg,
+                abi_version,
+                ImageOperation::HarrisCornerDetection,
+                &mut missing,
+            )
+        };
+
+        for (operation, e) in &missing {
+            warn!("libcudaimg does not export {:?}: {}", operation, e);
+        }
+
+        let supported_operations: Vec<_> = ImageOperation::ALL
+            .into_iter()
+            .filter(|op| !missing.iter().any(|(missing, _)| missing == op))
+            .collect();
+
+        info!(
+            "libcudaimg ABI version {}, supported operations: {:?}",
+            abi_version, supported_operations
+        );
+
+        Ok(Self {
+            _libcudaimg: libcudaimg,
+            abi_version,
+            supported_operations,
+            invert_image,
+            gamma_transform_image,
+            logarithmic_transform_image,
+            grayscale_image,
+            compute_histogram,
+            balance_histogram,
+            box_filter,
+            gaussian_blur,
+            sobel_edge_detection,
+            laplace_edge_detection,
+            harris_corner_detection,
+        })
+    }
+
+    /// Get the ABI version reported by the library.
+    pub fn abi_version(&self) -> u32 {
+        self.abi_version
+    }
+}
+
+/// Get a resolved function, or an error if the library does not export it.
+fn require<T: Copy>(function: Option<T>, operation: ImageOperation) -> anyhow::Result<T> {
+    function.ok_or_else(|| {
+        anyhow::anyhow!(
+            "libcudaimg does not export {} ({:?})",
+            operation.symbol(),
+            operation
+        )
+    })
+}
+
+/// Check whether the kernels of libcudaimg can run an operation on images with a number of channels.
+/// The kernels see a row of `width * channels` bytes, so single channel images
+/// work for the operations that do not look at the other channels of a pixel.
+fn kernels_support_channels(channels: u32, operation: ImageOperation) -> bool {
+    channels == 3 || (channels == 1 && operation.is_per_sample())
+}
+
+impl ImageBackend for CudaImgLib {
+    fn name(&self) -> &str {
+        "CUDA (libcudaimg)"
+    }
+
+    fn supported_operations(&self) -> &[ImageOperation] {
+        &self.supported_operations
+    }
+
+    fn supports_channels(&self, channels: u32, operation: ImageOperation) -> bool {
+        kernels_support_channels(channels, operation)
+    }
+
+    fn invert_image(&self, img: &mut CudaImageData) -> anyhow::Result<()> {
+        let invert_image = require(self.invert_image, ImageOperation::Invert)?;
+
+        call_kernel!(
+            invert_image,
+            img.bytes.as_mut_ptr(),
+            img.raw_len,
+            img.width * img.channels,
+            img.height,
         )?;
 
-    chart
-        .configure_mesh()
-        .disable_x_mesh()
-        .bold_line_style(WHITE.mix(0.3))
-        .y_desc("Count")
-        .x_desc("Pixel value")
-        .axis_desc_style(("sans-serif", 15))
-        .draw()?;
-
-    chart.draw_series(
-        Histogram::vertical(&chart)
-            .style(RED.mix(0.5).filled())
-            .data(
-                histogram
-                    .data
-                    .iter()
-                    .enumerate()
-                    .map(|(i, &count)| (i as u32, count)),
-            ),
-    )?;
+        Ok(())
+    }
+
+    fn gamma_transform_image(&self, img: &mut CudaImageData, gamma: f32) -> anyhow::Result<()> {
+        let gamma_transform_image =
+            require(self.gamma_transform_image, ImageOperation::GammaTransform)?;
+
+        call_kernel!(
+            gamma_transform_image,
+            img.bytes.as_mut_ptr(),
+            img.raw_len,
+            img.width * img.channels,
+            img.height,
+            gamma,
+        )?;
+
+        Ok(())
+    }
+
+    fn logarithmic_transform_image(
+        &self,
+        img: &mut CudaImageData,
+        base: f32,
+    ) -> anyhow::Result<()> {
+        let logarithmic_transform_image = require(
+            self.logarithmic_transform_image,
+            ImageOperation::LogarithmicTransform,
+        )?;
+
+        call_kernel!(
+            logarithmic_transform_image,
+            img.bytes.as_mut_ptr(),
+            img.raw_len,
+            img.width * img.channels,
+            img.height,
+            base,
+        )?;
+
+        Ok(())
+    }
+
+    fn grayscale_image(&self, img: &mut CudaImageData) -> anyhow::Result<()> {
+        let grayscale_image = require(self.grayscale_image, ImageOperation::Grayscale)?;
+
+        call_kernel!(
+            grayscale_image,
+            img.bytes.as_mut_ptr(),
+            img.raw_len,
+            img.width * img.channels,
+            img.height,
+        )?;
+
+        Ok(())
+    }
+
+    fn compute_histogram(
+        &self,
+        img: &mut CudaImageData,
+        histogram: &mut CudaHistogramData,
+    ) -> anyhow::Result<()> {
+        let compute_histogram = require(self.compute_histogram, ImageOperation::ComputeHistogram)?;
+
+        call_kernel!(
+            compute_histogram,
+            img.bytes.as_mut_ptr(),
+            img.raw_len,
+            histogram.data.as_mut_ptr(),
+            img.width * img.channels,
+            img.height,
+        )?;
+
+        Ok(())
+    }
+
+    fn balance_histogram(&self, img: &mut CudaImageData) -> anyhow::Result<()> {
+        let balance_histogram = require(self.balance_histogram, ImageOperation::BalanceHistogram)?;
+
+        call_kernel!(
+            balance_histogram,
+            img.bytes.as_mut_ptr(),
+            img.raw_len,
+            img.width * img.channels,
+            img.height,
+        )?;
 
-    root.present()?;
-    let img = image::open("data/histogram.png")?;
-    Ok(img)
+        Ok(())
+    }
+
+    fn box_filter(&self, img: &mut CudaImageData, filter_size: u32) -> anyhow::Result<()> {
+        let box_filter = require(self.box_filter, ImageOperation::BoxFilter)?;
+
+        call_kernel!(
+            box_filter,
+            img.bytes.as_mut_ptr(),
+            img.raw_len,
+            img.width * img.channels,
+            img.height,
+            filter_size,
+        )?;
+
+        Ok(())
+    }
+
+    fn gaussian_blur(&self, img: &mut CudaImageData, sigma: f32) -> anyhow::Result<()> {
+        let gaussian_blur = require(self.gaussian_blur, ImageOperation::GaussianBlur)?;
+
+        call_kernel!(
+            gaussian_blur,
+            img.bytes.as_mut_ptr(),
+            img.raw_len,
+            img.width * img.channels,
+            img.height,
+            sigma,
+        )?;
+
+        Ok(())
+    }
+
+    fn sobel_edge_detection(&self, img: &mut CudaImageData) -> anyhow::Result<()> {
+        let sobel_edge_detection = require(
+            self.sobel_edge_detection,
+            ImageOperation::SobelEdgeDetection,
+        )?;
+
+        call_kernel!(
+            sobel_edge_detection,
+            img.bytes.as_mut_ptr(),
+            img.raw_len,
+            img.width * img.channels,
+            img.height,
+        )?;
+
+        Ok(())
+    }
+
+    fn laplace_edge_detection(&self, img: &mut CudaImageData) -> anyhow::Result<()> {
+        let laplace_edge_detection = require(
+            self.laplace_edge_detection,
+            ImageOperation::LaplaceEdgeDetection,
+        )?;
+
+        call_kernel!(
+            laplace_edge_detection,
+            img.bytes.as_mut_ptr(),
+            img.raw_len,
+            img.width * img.channels,
+            img.height,
+        )?;
+
+        Ok(())
+    }
+
+    fn harris_corner_detection(&self, img: &mut CudaImageData) -> anyhow::Result<()> {
+        let harris_corner_detection = require(
+            self.harris_corner_detection,
+            ImageOperation::HarrisCornerDetection,
+        )?;
+
+        call_kernel!(
+            harris_corner_detection,
+            img.bytes.as_mut_ptr(),
+            img.raw_len,
+            img.width * img.channels,
+            img.height,
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Get the backend to run an operation on an image with.
+/// Backends that only process 8-bit RGB images, such as libcudaimg, fall back to the CPU
+/// implementation for high bit depth images and for grayscale images the operation can not
+/// run on, so they are processed as they are.
+///
+/// # Arguments
+///
+/// * `backend` - The backend chosen for image processing.
+/// * `img` - The image to process.
+/// * `operation` - The operation to run on the image.
+pub fn backend_for_image<'a>(
+    backend: &'a dyn ImageBackend,
+    img: &CudaImageData,
+    operation: ImageOperation,
+) -> &'a dyn ImageBackend {
+    static CPU_BACKEND: CpuBackend = CpuBackend;
+
+    if backend.supports_sample_type(img.sample_type)
+        && backend.supports_channels(img.channels, operation)
+    {
+        backend
+    } else {
+        info!(
+            "The {} backend does not run {:?} on {:?} images with {} channels, using the CPU implementation",
+            backend.name(),
+            operation,
+            img.sample_type,
+            img.channels
+        );
+        &CPU_BACKEND
+    }
+}
+
+/// Run a spatial filter on an image, on premultiplied alpha if it has an alpha channel.
+/// The alpha channel is filtered like the color samples, so edges fade out smoothly.
+/// Premultiplied images are filtered with floating point samples and quantized once at
+/// the end, so the color of nearly transparent pixels is not lost to rounding.
+///
+/// # Arguments
+///
+/// * `img` - The image to filter.
+/// * `filter` - Runs the filter of the backend on an image.
+fn filter_premultiplied(
+    img: &mut CudaImageData,
+    filter: impl Fn(&mut CudaImageData) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let Some(mut alpha) = img.alpha_image() else {
+        return filter(img);
+    };
+
+    let mut premultiplied = img.to_sample_type(SampleType::F32);
+    alpha = alpha.to_sample_type(SampleType::F32);
+
+    premultiplied.premultiply_alpha();
+    filter(&mut premultiplied)?;
+    filter(&mut alpha)?;
+    premultiplied.set_alpha_from(&alpha);
+    premultiplied.unpremultiply_alpha();
+
+    *img = premultiplied.to_sample_type(img.sample_type);
+
+    Ok(())
 }
 
 /// Process an image using a specified image processing function.
-/// The image is modified in place using the CUDA kernels.
+/// The image is modified in place by the given backend.
 /// The modified image is returned as a DynamicImage.
 ///
 /// # Arguments
 ///
-/// * `libcudaimg` - The libcudaimg library to use for image processing.
+/// * `backend` - The backend to use for image processing.
 /// * `image` - The image to process.
 /// * `function` - The image processing function to apply.
 pub fn process_image(
-    libcudaimg: &Library,
+    backend: &dyn ImageBackend,
     image: &DynamicImage,
     function: ImageProcessingFunction,
 ) -> anyhow::Result<DynamicImage> {
     // Get the image data
     let mut img = image.to_cuda_image_data();
+    if function == ImageProcessingFunction::HarrisCornerDetection {
+        // The corners are marked in red
+        img.expand_to_rgb();
+    }
+    let requested_backend = backend;
+    let backend = backend_for_image(requested_backend, &img, function.operation());
+
+    if !backend.supports(function.operation()) {
+        anyhow::bail!(
+            "{:?} is not supported by the {} backend",
+            function.operation(),
+            backend.name()
+        );
+    }
 
-    info!("Image width: {}, height: {}", img.width, img.height);
+    function.validate(&img)?;
+
+    info!(
+        "Image width: {}, height: {}, backend: {}",
+        img.width,
+        img.height,
+        backend.name()
+    );
 
     match function {
-        ImageProcessingFunction::Invert => {
-            let process_image: Symbol<InvertImageFn> = unsafe { libcudaimg.get(b"invertImage\0")? };
-            unsafe {
-                process_image(
-                    img.bytes.as_mut_ptr(),
-                    img.raw_len,
-                    img.width * img.pixel_size,
-                    img.height,
-                );
-            }
-        }
+        ImageProcessingFunction::Invert => backend.invert_image(&mut img)?,
         ImageProcessingFunction::GammaTransform(gamma) => {
-            let process_image: Symbol<GammaTransformImage> =
-                unsafe { libcudaimg.get(b"gammaTransformImage\0")? };
-
-            unsafe {
-                process_image(
-                    img.bytes.as_mut_ptr(),
-                    img.raw_len,
-                    img.width * img.pixel_size,
-                    img.height,
-                    gamma,
-                );
-            }
+            backend.gamma_transform_image(&mut img, gamma)?
         }
         ImageProcessingFunction::LogarithmicTransform(base) => {
-            let process_image: Symbol<LogarithmicTransformImage> =
-                unsafe { libcudaimg.get(b"logarithmicTransformImage\0")? };
-
-            unsafe {
-                process_image(
-                    img.bytes.as_mut_ptr(),
-                    img.raw_len,
-                    img.width * img.pixel_size,
-                    img.height,
-                    base,
-                );
-            }
+            backend.logarithmic_transform_image(&mut img, base)?
         }
         ImageProcessingFunction::Grayscale => {
-            let process_image: Symbol<GrayscaleImageFn> =
-                unsafe { libcudaimg.get(b"grayscaleImage\0")? };
-
-            unsafe {
-                process_image(
-                    img.bytes.as_mut_ptr(),
-                    img.raw_len,
-                    img.width * img.pixel_size,
-                    img.height,
-                );
-            }
+            backend.grayscale_image(&mut img)?;
+            img.reduce_to_gray();
         }
         ImageProcessingFunction::ComputeHistogram => {
-            let process_image: Symbol<ComputeHistogramFn> =
-                unsafe { libcudaimg.get(b"computeHistogram\0")? };
-
-            let mut histogram = CudaHistogramData::default();
-
-            unsafe {
-                process_image(
-                    img.bytes.as_mut_ptr(),
-                    img.raw_len,
-                    histogram.data.as_mut_ptr(),
-                    img.width * img.pixel_size,
-                    img.height,
-                );
-            }
+            let histograms = histogram::compute_histograms(backend, &img)?;
 
             // Return explicitly to avoid creating a new image from the modified bytes
-            return plot_histogram(&histogram);
-        }
-        ImageProcessingFunction::BalanceHistogram => {
-            let process_image: Symbol<BalanceHistogramFn> =
-                unsafe { libcudaimg.get(b"balanceHistogram\0")? };
-
-            unsafe {
-                process_image(
-                    img.bytes.as_mut_ptr(),
-                    img.raw_len,
-                    img.width * img.pixel_size,
-                    img.height,
-                );
-            }
-        }
-        ImageProcessingFunction::BoxFilter(filter_size) => {
-            let process_image: Symbol<BoxFilterFn> = unsafe { libcudaimg.get(b"boxFilter\0")? };
-
-            unsafe {
-                process_image(
-                    img.bytes.as_mut_ptr(),
-                    img.raw_len,
-                    img.width * img.pixel_size,
-                    img.height,
-                    filter_size,
-                );
-            }
-        }
-        ImageProcessingFunction::GaussianBlur(sigma) => {
-            let process_image: Symbol<GaussianBlurFn> =
-                unsafe { libcudaimg.get(b"gaussianBlur\0")? };
-
-            unsafe {
-                process_image(
-                    img.bytes.as_mut_ptr(),
-                    img.raw_len,
-                    img.width * img.pixel_size,
-                    img.height,
-                    sigma,
-                );
-            }
-        }
-        ImageProcessingFunction::SobelEdgeDetection => {
-            let process_image: Symbol<SobelEdgeDetectionFn> =
-                unsafe { libcudaimg.get(b"sobelEdgeDetection\0")? };
-
-            unsafe {
-                process_image(
-                    img.bytes.as_mut_ptr(),
-                    img.raw_len,
-                    img.width * img.pixel_size,
-                    img.height,
-                );
-            }
+            return histogram::plot_histograms(&histograms, &HistogramChannel::ALL);
         }
+        ImageProcessingFunction::BalanceHistogram => backend.balance_histogram(&mut img)?,
+        // Premultiplied images are filtered as floating point, which may need another backend
+        ImageProcessingFunction::BoxFilter(filter_size) => filter_premultiplied(&mut img, |img| {
+            backend_for_image(requested_backend, img, ImageOperation::BoxFilter)
+                .box_filter(img, filter_size)
+        })?,
+        ImageProcessingFunction::GaussianBlur(sigma) => filter_premultiplied(&mut img, |img| {
+            backend_for_image(requested_backend, img, ImageOperation::GaussianBlur)
+                .gaussian_blur(img, sigma)
+        })?,
+        ImageProcessingFunction::SobelEdgeDetection => backend.sobel_edge_detection(&mut img)?,
         ImageProcessingFunction::LaplaceEdgeDetection => {
-            let process_image: Symbol<LaplaceEdgeDetectionFn> =
-                unsafe { libcudaimg.get(b"laplaceEdgeDetection\0")? };
-
-            unsafe {
-                process_image(
-                    img.bytes.as_mut_ptr(),
-                    img.raw_len,
-                    img.width * img.pixel_size,
-                    img.height,
-                );
-            }
+            backend.laplace_edge_detection(&mut img)?
         }
         ImageProcessingFunction::HarrisCornerDetection => {
-            let process_image: Symbol<HarrisCornerDetectionFn> =
-                unsafe { libcudaimg.get(b"harrisCornerDetection\0")? };
-
-            unsafe {
-                process_image(
-                    img.bytes.as_mut_ptr(),
-                    img.raw_len,
-                    img.width * img.pixel_size,
-                    img.height,
-                );
-            }
+            backend.harris_corner_detection(&mut img)?
         }
     };
 
     // Create a new image from the modified bytes
-    let modified_image = image::DynamicImage::ImageRgb8(
-        image::RgbImage::from_raw(img.width, img.height, img.bytes)
-            .expect("Failed to create the modified image from bytes"),
-    );
+    img.into_dynamic_image()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::Pipeline;
+    use crate::ImageProcessingTask;
+    use image::{GrayImage, Luma, Rgb, Rgb32FImage, Rgba, RgbaImage};
+
+    /// A backend whose operations fail like libcudaimg returning a status code.
+    /// It takes the images libcudaimg takes, so the failure shows which backend ran.
+    struct FailingBackend(i32);
+
+    impl FailingBackend {
+        fn fail(&self) -> anyhow::Result<()> {
+            CudaImgError::from_status(self.0)?;
+            Ok(())
+        }
+    }
+
+    impl ImageBackend for FailingBackend {
+        fn name(&self) -> &str {
+            "Failing"
+        }
+
+        fn supported_operations(&self) -> &[ImageOperation] {
+            &ImageOperation::ALL
+        }
+
+        fn supports_channels(&self, channels: u32, operation: ImageOperation) -> bool {
+            kernels_support_channels(channels, operation)
+        }
+
+        fn invert_image(&self, _img: &mut CudaImageData) -> anyhow::Result<()> {
+            self.fail()
+        }
+
+        fn gamma_transform_image(
+            &self,
+            _img: &mut CudaImageData,
+            _gamma: f32,
+        ) -> anyhow::Result<()> {
+            self.fail()
+        }
+
+        fn logarithmic_transform_image(
+            &self,
+            _img: &mut CudaImageData,
+            _base: f32,
+        ) -> anyhow::Result<()> {
+            self.fail()
+        }
+
+        fn grayscale_image(&self, _img: &mut CudaImageData) -> anyhow::Result<()> {
+            self.fail()
+        }
+
+        fn compute_histogram(
+            &self,
+            _img: &mut CudaImageData,
+            _histogram: &mut CudaHistogramData,
+        ) -> anyhow::Result<()> {
+            self.fail()
+        }
+
+        fn balance_histogram(&self, _img: &mut CudaImageData) -> anyhow::Result<()> {
+            self.fail()
+        }
+
+        fn box_filter(&self, _img: &mut CudaImageData, _filter_size: u32) -> anyhow::Result<()> {
+            self.fail()
+        }
 
-    Ok(modified_image)
+        fn gaussian_blur(&self, _img: &mut CudaImageData, _sigma: f32) -> anyhow::Result<()> {
+            self.fail()
+        }
+
+        fn sobel_edge_detection(&self, _img: &mut CudaImageData) -> anyhow::Result<()> {
+            self.fail()
+        }
+
+        fn laplace_edge_detection(&self, _img: &mut CudaImageData) -> anyhow::Result<()> {
+            self.fail()
+        }
+
+        fn harris_corner_detection(&self, _img: &mut CudaImageData) -> anyhow::Result<()> {
+            self.fail()
+        }
+    }
+
+    #[test]
+    fn status_codes_map_to_errors() {
+        let cases = [
+            (STATUS_SUCCESS, Ok(())),
+            (STATUS_DEVICE_MISSING, Err(CudaImgError::DeviceMissing)),
+            (STATUS_OUT_OF_MEMORY, Err(CudaImgError::OutOfMemory)),
+            (
+                STATUS_INVALID_ARGUMENT,
+                Err(CudaImgError::InvalidArgument(
+                    "rejected by libcudaimg".to_string(),
+                )),
+            ),
+            (700, Err(CudaImgError::KernelFailure(700))),
+        ];
+
+        for (status, expected) in cases {
+            assert_eq!(CudaImgError::from_status(status), expected);
+        }
+    }
+
+    #[test]
+    fn kernel_errors_reach_the_error_window() {
+        let image = DynamicImage::new_rgb8(4, 4);
+        let mut pipeline = Pipeline::default();
+        pipeline.push(ImageProcessingFunction::GaussianBlur(1.0));
+
+        let error = pipeline
+            .run(&FailingBackend(STATUS_OUT_OF_MEMORY), &image)
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CudaImgError>(),
+            Some(&CudaImgError::OutOfMemory)
+        );
+
+        // The message of the failed task is what the errors window lists
+        let ImageProcessingTask::Failed { message } =
+            ImageProcessingTask::failed("Failed to run the pipeline", error)
+        else {
+            panic!("The task did not fail");
+        };
+        assert_eq!(
+            message,
+            "Failed to run the pipeline: Step 1 (Gaussian blur) failed: The CUDA device ran out of memory"
+        );
+    }
+
+    #[test]
+    fn filters_keep_the_color_of_nearly_transparent_pixels() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(16, 1, |x, _| {
+            Rgba([200, 100, 37, x as u8 + 1])
+        }));
+
+        let filtered =
+            process_image(&CpuBackend, &image, ImageProcessingFunction::BoxFilter(1)).unwrap();
+
+        assert_eq!(filtered.as_rgba8(), image.as_rgba8());
+    }
+
+    #[test]
+    fn sample_type_conversion_round_trips() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(256, 1, |x, _| {
+            Rgba([x as u8, 255 - x as u8, 51, x as u8])
+        }));
+        let img = image.to_cuda_image_data();
+        let converted = img.to_sample_type(SampleType::F32);
+
+        assert_eq!(converted.samples()[..2], [0.0, 1.0]);
+        assert_eq!(converted.alpha.as_ref().unwrap()[255], 1.0);
+
+        let img = converted.to_sample_type(SampleType::U8);
+        assert_eq!(
+            img.into_dynamic_image().unwrap().as_rgba8(),
+            image.as_rgba8()
+        );
+    }
+
+    #[test]
+    fn sixteen_bit_images_keep_their_precision() {
+        let image = DynamicImage::ImageRgb16(image::ImageBuffer::from_fn(4, 4, |x, y| {
+            Rgb([1000 + x as u16, 40000 + y as u16, 300])
+        }));
+
+        let inverted = process_image(&CpuBackend, &image, ImageProcessingFunction::Invert).unwrap();
+        let inverted = inverted.as_rgb16().expect("The result is not 16-bit RGB");
+        assert_eq!(inverted.get_pixel(3, 2), &Rgb([64532, 25533, 65235]));
+
+        // Values that differ only in the low byte are averaged, not quantized to 8 bits
+        let blurred =
+            process_image(&CpuBackend, &image, ImageProcessingFunction::BoxFilter(3)).unwrap();
+        let blurred = blurred.as_rgb16().expect("The result is not 16-bit RGB");
+        assert_eq!(blurred.get_pixel(1, 1), &Rgb([1001, 40001, 300]));
+    }
+
+    #[test]
+    fn floating_point_images_keep_values_above_white() {
+        let image = DynamicImage::ImageRgb32F(Rgb32FImage::from_fn(4, 4, |x, _| {
+            Rgb([2.0, 0.5, x as f32 * 4.0])
+        }));
+
+        let transformed = process_image(
+            &CpuBackend,
+            &image,
+            ImageProcessingFunction::GammaTransform(2.0),
+        )
+        .unwrap();
+        let transformed = transformed
+            .as_rgb32f()
+            .expect("The result is not floating point RGB");
+        assert_eq!(transformed.get_pixel(1, 0), &Rgb([4.0, 0.25, 16.0]));
+
+        let blurred =
+            process_image(&CpuBackend, &image, ImageProcessingFunction::BoxFilter(3)).unwrap();
+        let blurred = blurred.as_rgb32f().unwrap();
+        assert_eq!(blurred.get_pixel(1, 1)[0], 2.0);
+        assert_eq!(blurred.get_pixel(1, 1)[2], 4.0);
+    }
+
+    /// A 4x4 grayscale image with a bright pixel.
+    fn gray_image() -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(4, 4, |x, y| {
+            Luma([if (x, y) == (1, 1) { 200 } else { 20 }])
+        }))
+    }
+
+    #[test]
+    fn grayscale_images_stay_single_channel() {
+        let inverted =
+            process_image(&CpuBackend, &gray_image(), ImageProcessingFunction::Invert).unwrap();
+        let inverted = inverted.as_luma8().expect("The result is not grayscale");
+        assert_eq!(inverted.get_pixel(1, 1), &Luma([55]));
+
+        let blurred = process_image(
+            &CpuBackend,
+            &gray_image(),
+            ImageProcessingFunction::BoxFilter(3),
+        )
+        .unwrap();
+        let blurred = blurred.as_luma8().expect("The result is not grayscale");
+        assert_eq!(blurred.get_pixel(1, 1), &Luma([40]));
+
+        let rgb = DynamicImage::ImageRgb8(gray_image().to_rgb8());
+        let grayscale =
+            process_image(&CpuBackend, &rgb, ImageProcessingFunction::Grayscale).unwrap();
+        assert_eq!(grayscale.as_luma8(), gray_image().as_luma8());
+    }
+
+    #[test]
+    fn harris_expands_grayscale_images_to_rgb() {
+        let corners = process_image(
+            &CpuBackend,
+            &gray_image(),
+            ImageProcessingFunction::HarrisCornerDetection,
+        )
+        .unwrap();
+
+        assert!(corners.as_rgb8().is_some(), "The result is not RGB");
+    }
+
+    #[test]
+    fn grayscale_images_run_per_sample_operations_on_libcudaimg() {
+        let libcudaimg = FailingBackend(STATUS_OUT_OF_MEMORY);
+        let img = gray_image().to_cuda_image_data();
+
+        for operation in ImageOperation::ALL {
+            let backend = backend_for_image(&libcudaimg, &img, operation);
+            let expected = if operation.is_per_sample() {
+                "Failing"
+            } else {
+                "CPU"
+            };
+            assert_eq!(backend.name(), expected, "{:?}", operation);
+        }
+
+        // The filter falls back to the CPU, the inversion reaches libcudaimg and fails
+        let blurred = process_image(
+            &libcudaimg,
+            &gray_image(),
+            ImageProcessingFunction::GaussianBlur(1.0),
+        )
+        .unwrap();
+        assert!(blurred.as_luma8().is_some());
+        assert!(
+            process_image(&libcudaimg, &gray_image(), ImageProcessingFunction::Invert).is_err()
+        );
+    }
 }
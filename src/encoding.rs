@@ -0,0 +1,406 @@
+use crate::formats;
+use crate::metadata::{self, ImageMetadata};
+use anyhow::Context;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use jpeg_encoder::{ColorType, SamplingFactor};
+use std::io::Cursor;
+use std::path::Path;
+use tiff::encoder::colortype;
+use tiff::encoder::compression::{Compression, Deflate, Lzw, Packbits, Uncompressed};
+use tiff::encoder::TiffEncoder;
+
+/// The resolution the color of a JPEG image is stored at, relative to its brightness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JpegSubsampling {
+    /// Full color resolution.
+    #[default]
+    Yuv444,
+    /// Half the horizontal color resolution.
+    Yuv422,
+    /// Half the horizontal and vertical color resolution.
+    Yuv420,
+}
+
+impl JpegSubsampling {
+    pub const ALL: [Self; 3] = [Self::Yuv444, Self::Yuv422, Self::Yuv420];
+
+    /// Get the name of the subsampling shown in the save dialog.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Yuv444 => "4:4:4 (none)",
+            Self::Yuv422 => "4:2:2",
+            Self::Yuv420 => "4:2:0",
+        }
+    }
+}
+
+impl From<JpegSubsampling> for SamplingFactor {
+    fn from(subsampling: JpegSubsampling) -> Self {
+        match subsampling {
+            JpegSubsampling::Yuv444 => SamplingFactor::R_4_4_4,
+            JpegSubsampling::Yuv422 => SamplingFactor::R_4_2_2,
+            JpegSubsampling::Yuv420 => SamplingFactor::R_4_2_0,
+        }
+    }
+}
+
+/// The options of the JPEG encoder.
+///
+/// # Fields
+///
+/// * `quality` - The quality from 1 (smallest) to 100 (best).
+/// * `subsampling` - The resolution the color is stored at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JpegOptions {
+    pub quality: u8,
+    pub subsampling: JpegSubsampling,
+}
+
+impl Default for JpegOptions {
+    fn default() -> Self {
+        Self {
+            quality: 75,
+            subsampling: JpegSubsampling::default(),
+        }
+    }
+}
+
+/// The compression level of the PNG encoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PngCompression {
+    Fast,
+    #[default]
+    Default,
+    Best,
+}
+
+impl PngCompression {
+    pub const ALL: [Self; 3] = [Self::Fast, Self::Default, Self::Best];
+
+    /// Get the name of the compression level shown in the save dialog.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Fast => "Fast",
+            Self::Default => "Default",
+            Self::Best => "Best",
+        }
+    }
+}
+
+impl From<PngCompression> for CompressionType {
+    fn from(compression: PngCompression) -> Self {
+        match compression {
+            PngCompression::Fast => CompressionType::Fast,
+            PngCompression::Default => CompressionType::Default,
+            PngCompression::Best => CompressionType::Best,
+        }
+    }
+}
+
+/// The filter the PNG encoder applies to the rows before compressing them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PngFilter {
+    None,
+    Sub,
+    Up,
+    Avg,
+    Paeth,
+    /// Pick the best filter for every row.
+    #[default]
+    Adaptive,
+}
+
+impl PngFilter {
+    pub const ALL: [Self; 6] = [
+        Self::None,
+        Self::Sub,
+        Self::Up,
+        Self::Avg,
+        Self::Paeth,
+        Self::Adaptive,
+    ];
+
+    /// Get the name of the filter shown in the save dialog.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::None => "None",
+            Self::Sub => "Sub",
+            Self::Up => "Up",
+            Self::Avg => "Average",
+            Self::Paeth => "Paeth",
+            Self::Adaptive => "Adaptive",
+        }
+    }
+}
+
+impl From<PngFilter> for FilterType {
+    fn from(filter: PngFilter) -> Self {
+        match filter {
+            PngFilter::None => FilterType::NoFilter,
+            PngFilter::Sub => FilterType::Sub,
+            PngFilter::Up => FilterType::Up,
+            PngFilter::Avg => FilterType::Avg,
+            PngFilter::Paeth => FilterType::Paeth,
+            PngFilter::Adaptive => FilterType::Adaptive,
+        }
+    }
+}
+
+/// The options of the PNG encoder.
+///
+/// # Fields
+///
+/// * `compression` - The compression level.
+/// * `filter` - The filter applied to the rows before compressing them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PngOptions {
+    pub compression: PngCompression,
+    pub filter: PngFilter,
+}
+
+/// The options of the WebP encoder.
+///
+/// # Fields
+///
+/// * `lossless` - Whether the pixels are stored exactly.
+/// * `quality` - The quality of lossy images from 0 (smallest) to 100 (best).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WebpOptions {
+    pub lossless: bool,
+    pub quality: u8,
+}
+
+impl Default for WebpOptions {
+    fn default() -> Self {
+        Self {
+            lossless: true,
+            quality: 90,
+        }
+    }
+}
+
+/// The compression of TIFF images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TiffCompression {
+    #[default]
+    None,
+    Lzw,
+    Deflate,
+    PackBits,
+}
+
+impl TiffCompression {
+    pub const ALL: [Self; 4] = [Self::None, Self::Lzw, Self::Deflate, Self::PackBits];
+
+    /// Get the name of the compression shown in the save dialog.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::None => "None",
+            Self::Lzw => "LZW",
+            Self::Deflate => "Deflate",
+            Self::PackBits => "PackBits",
+        }
+    }
+}
+
+/// The format specific options of the encoders.
+/// Formats without options are written with the defaults of the `image` crate.
+///
+/// # Fields
+///
+/// * `jpeg` - The options of JPEG images.
+/// * `png` - The options of PNG images.
+/// * `webp` - The options of WebP images.
+/// * `tiff` - The compression of TIFF images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EncoderOptions {
+    pub jpeg: JpegOptions,
+    pub png: PngOptions,
+    pub webp: WebpOptions,
+    pub tiff: TiffCompression,
+}
+
+/// Encode an image in a format, converting it to a color type the format supports.
+///
+/// # Arguments
+///
+/// * `image` - The image to encode.
+/// * `format` - The format to encode the image in.
+/// * `options` - The options of the encoder, only the ones of `format` are used.
+/// * `metadata` - The metadata written into the file, if the format supports it.
+///
+/// # Returns
+///
+/// * The contents of the image file.
+pub fn encode(
+    image: &DynamicImage,
+    format: ImageFormat,
+    options: &EncoderOptions,
+    metadata: &ImageMetadata,
+) -> anyhow::Result<Vec<u8>> {
+    let image = formats::convert_for_format(image, format);
+    let mut bytes = Cursor::new(Vec::new());
+
+    match format {
+        ImageFormat::Jpeg => write_jpeg(&mut bytes, &image, &options.jpeg)?,
+        ImageFormat::Png => {
+            image.write_with_encoder(PngEncoder::new_with_quality(
+                &mut bytes,
+                options.png.compression.into(),
+                options.png.filter.into(),
+            ))?;
+        }
+        ImageFormat::WebP if options.webp.lossless => {
+            image.write_with_encoder(WebPEncoder::new_lossless(&mut bytes))?;
+        }
+        ImageFormat::WebP => {
+            bytes
+                .get_mut()
+                .extend_from_slice(&encode_lossy_webp(&image, options.webp.quality)?);
+        }
+        ImageFormat::Tiff => match options.tiff {
+            TiffCompression::None => write_tiff(&mut bytes, &image, Uncompressed)?,
+            TiffCompression::Lzw => write_tiff(&mut bytes, &image, Lzw)?,
+            TiffCompression::Deflate => write_tiff(&mut bytes, &image, Deflate::default())?,
+            TiffCompression::PackBits => write_tiff(&mut bytes, &image, Packbits)?,
+        },
+        _ => image.write_to(&mut bytes, format)?,
+    }
+
+    metadata::embed(bytes.into_inner(), format, &image, metadata)
+}
+
+/// Encode an image in a format and write it to a file.
+///
+/// # Arguments
+///
+/// * `image` - The image to save.
+/// * `path` - The file to save the image to.
+/// * `format` - The format to save the image in.
+/// * `options` - The options of the encoder, only the ones of `format` are used.
+/// * `metadata` - The metadata written into the file, if the format supports it.
+pub fn save(
+    image: &DynamicImage,
+    path: &Path,
+    format: ImageFormat,
+    options: &EncoderOptions,
+    metadata: &ImageMetadata,
+) -> anyhow::Result<()> {
+    let bytes = encode(image, format, options, metadata)
+        .with_context(|| format!("Failed to encode {}", path.display()))?;
+
+    std::fs::write(path, bytes).with_context(|| format!("Failed to save {}", path.display()))
+}
+
+/// Write an 8-bit grayscale or RGB image as a JPEG file.
+fn write_jpeg(
+    bytes: &mut Cursor<Vec<u8>>,
+    image: &DynamicImage,
+    options: &JpegOptions,
+) -> anyhow::Result<()> {
+    let (width, height) = image.dimensions();
+    let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+        anyhow::bail!("JPEG images can be at most 65535 pixels wide and high");
+    };
+
+    let mut encoder = jpeg_encoder::Encoder::new(bytes, options.quality.clamp(1, 100));
+    encoder.set_sampling_factor(options.subsampling.into());
+
+    match image {
+        DynamicImage::ImageLuma8(image) => {
+            encoder.encode(image.as_raw(), width, height, ColorType::Luma)?
+        }
+        _ => encoder.encode(image.to_rgb8().as_raw(), width, height, ColorType::Rgb)?,
+    }
+
+    Ok(())
+}
+
+/// Encode an 8-bit image as a lossy WebP file.
+///
+/// # Arguments
+///
+/// * `image` - The image to encode.
+/// * `quality` - The quality from 0 (smallest) to 100 (best).
+fn encode_lossy_webp(image: &DynamicImage, quality: u8) -> anyhow::Result<Vec<u8>> {
+    let (width, height) = image.dimensions();
+    let quality = f32::from(quality.min(100));
+
+    let encoded = if image.color().has_alpha() {
+        let rgba = image.to_rgba8();
+        webp::Encoder::from_rgba(&rgba, width, height).encode_simple(false, quality)
+    } else {
+        let rgb = image.to_rgb8();
+        webp::Encoder::from_rgb(&rgb, width, height).encode_simple(false, quality)
+    }
+    .map_err(|e| anyhow::anyhow!("Failed to encode the lossy WebP image: {:?}", e))?;
+
+    Ok(encoded.to_vec())
+}
+
+/// Write an image as a TIFF file with a compression.
+fn write_tiff<D: Compression>(
+    bytes: &mut Cursor<Vec<u8>>,
+    image: &DynamicImage,
+    compression: D,
+) -> anyhow::Result<()> {
+    let mut encoder = TiffEncoder::new(bytes)?;
+    let (width, height) = image.dimensions();
+
+    match image {
+        DynamicImage::ImageLuma8(image) => encoder
+            .write_image_with_compression::<colortype::Gray8, _>(
+                width,
+                height,
+                compression,
+                image.as_raw(),
+            )?,
+        DynamicImage::ImageRgb8(image) => encoder
+            .write_image_with_compression::<colortype::RGB8, _>(
+                width,
+                height,
+                compression,
+                image.as_raw(),
+            )?,
+        DynamicImage::ImageLuma16(image) => encoder
+            .write_image_with_compression::<colortype::Gray16, _>(
+                width,
+                height,
+                compression,
+                image.as_raw(),
+            )?,
+        DynamicImage::ImageRgb16(image) => encoder
+            .write_image_with_compression::<colortype::RGB16, _>(
+                width,
+                height,
+                compression,
+                image.as_raw(),
+            )?,
+        DynamicImage::ImageRgba16(image) => encoder
+            .write_image_with_compression::<colortype::RGBA16, _>(
+                width,
+                height,
+                compression,
+                image.as_raw(),
+            )?,
+        // TIFF has no gray with alpha color type, so it is expanded to RGBA
+        DynamicImage::ImageLumaA16(_) => encoder
+            .write_image_with_compression::<colortype::RGBA16, _>(
+                width,
+                height,
+                compression,
+                image.to_rgba16().as_raw(),
+            )?,
+        // RGBA and 8-bit gray with alpha
+        _ => encoder.write_image_with_compression::<colortype::RGBA8, _>(
+            width,
+            height,
+            compression,
+            image.to_rgba8().as_raw(),
+        )?,
+    }
+
+    Ok(())
+}
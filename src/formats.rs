@@ -0,0 +1,179 @@
+use crate::encoding::{self, EncoderOptions};
+use crate::metadata::ImageMetadata;
+use image::{DynamicImage, ImageFormat};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+/// Get the human readable name of an image format.
+pub fn format_name(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Png => "PNG",
+        ImageFormat::Jpeg => "JPEG",
+        ImageFormat::Gif => "GIF",
+        ImageFormat::WebP => "WebP",
+        ImageFormat::Pnm => "PNM",
+        ImageFormat::Tiff => "TIFF",
+        ImageFormat::Tga => "TGA",
+        ImageFormat::Dds => "DDS",
+        ImageFormat::Bmp => "BMP",
+        ImageFormat::Ico => "ICO",
+        ImageFormat::Hdr => "Radiance HDR",
+        ImageFormat::OpenExr => "OpenEXR",
+        ImageFormat::Farbfeld => "Farbfeld",
+        ImageFormat::Avif => "AVIF",
+        ImageFormat::Qoi => "QOI",
+        _ => "Image",
+    }
+}
+
+/// Check whether images of a format can be opened.
+pub fn is_readable(format: ImageFormat) -> bool {
+    // The avif feature only enables the encoder, decoding needs the native avif-native feature
+    format.reading_enabled() && format != ImageFormat::Avif
+}
+
+/// Check whether images can be saved in a format.
+pub fn is_writable(format: ImageFormat) -> bool {
+    format.writing_enabled()
+}
+
+/// Get every format images can be opened from, sorted by name.
+pub fn readable_formats() -> Vec<ImageFormat> {
+    let mut formats: Vec<_> = ImageFormat::all().filter(|f| is_readable(*f)).collect();
+    formats.sort_by_key(|f| format_name(*f));
+    formats
+}
+
+/// Get every format images can be saved in, sorted by name.
+pub fn writable_formats() -> Vec<ImageFormat> {
+    let mut formats: Vec<_> = ImageFormat::all().filter(|f| is_writable(*f)).collect();
+    formats.sort_by_key(|f| format_name(*f));
+    formats
+}
+
+/// Get the file extensions of the formats, e.g. for the filters of a file dialog.
+pub fn extensions(formats: &[ImageFormat]) -> Vec<&'static str> {
+    formats
+        .iter()
+        .flat_map(|format| format.extensions_str().iter().copied())
+        .collect()
+}
+
+/// Get the format of a file from its extension, if images can be saved in it.
+///
+/// # Arguments
+///
+/// * `path` - The path of the file to save.
+pub fn writable_format_from_path(path: &Path) -> anyhow::Result<ImageFormat> {
+    ImageFormat::from_path(path)
+        .ok()
+        .filter(|format| is_writable(*format))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Can not save {}, use one of the extensions {}",
+                path.display(),
+                extensions(&writable_formats()).join(", ")
+            )
+        })
+}
+
+/// Make sure a path ends with an extension of the format, appending the default one if not.
+///
+/// # Arguments
+///
+/// * `path` - The path picked to save the image to.
+/// * `format` - The format the image is saved in.
+pub fn with_format_extension(path: &Path, format: ImageFormat) -> PathBuf {
+    let matches = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            format
+                .extensions_str()
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(ext))
+        });
+
+    match format.extensions_str().first() {
+        Some(ext) if !matches => {
+            let mut path = path.as_os_str().to_owned();
+            path.push(".");
+            path.push(ext);
+            PathBuf::from(path)
+        }
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Convert an image to a color type the encoder of a format supports.
+/// Images the encoder accepts as they are are borrowed.
+///
+/// # Arguments
+///
+/// * `image` - The image to save.
+/// * `format` - The format the image is saved in.
+pub fn convert_for_format(image: &DynamicImage, format: ImageFormat) -> Cow<'_, DynamicImage> {
+    let has_alpha = image.color().has_alpha();
+    let is_8bit = matches!(
+        image,
+        DynamicImage::ImageLuma8(_)
+            | DynamicImage::ImageLumaA8(_)
+            | DynamicImage::ImageRgb8(_)
+            | DynamicImage::ImageRgba8(_)
+    );
+    let is_float = matches!(
+        image,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+    );
+
+    match format {
+        // Floating point only
+        ImageFormat::Hdr => Cow::Owned(DynamicImage::ImageRgb32F(image.to_rgb32f())),
+        ImageFormat::OpenExr if is_float => Cow::Borrowed(image),
+        ImageFormat::OpenExr if has_alpha => {
+            Cow::Owned(DynamicImage::ImageRgba32F(image.to_rgba32f()))
+        }
+        ImageFormat::OpenExr => Cow::Owned(DynamicImage::ImageRgb32F(image.to_rgb32f())),
+        // 16 bit RGBA only
+        ImageFormat::Farbfeld => Cow::Owned(DynamicImage::ImageRgba16(image.to_rgba16())),
+        // No alpha channel
+        ImageFormat::Jpeg => match image {
+            DynamicImage::ImageLuma8(_) | DynamicImage::ImageRgb8(_) => Cow::Borrowed(image),
+            _ if !image.color().has_color() => {
+                Cow::Owned(DynamicImage::ImageLuma8(image.to_luma8()))
+            }
+            _ => Cow::Owned(DynamicImage::ImageRgb8(image.to_rgb8())),
+        },
+        // 8 and 16 bit integers
+        ImageFormat::Png | ImageFormat::Tiff | ImageFormat::Pnm if is_float => {
+            if has_alpha {
+                Cow::Owned(DynamicImage::ImageRgba16(image.to_rgba16()))
+            } else {
+                Cow::Owned(DynamicImage::ImageRgb16(image.to_rgb16()))
+            }
+        }
+        ImageFormat::Png | ImageFormat::Tiff | ImageFormat::Pnm => Cow::Borrowed(image),
+        // 8 bit integers
+        _ if is_8bit => Cow::Borrowed(image),
+        _ if has_alpha => Cow::Owned(DynamicImage::ImageRgba8(image.to_rgba8())),
+        _ => Cow::Owned(DynamicImage::ImageRgb8(image.to_rgb8())),
+    }
+}
+
+/// Save an image in a format with the default encoder options,
+/// converting it to a color type the format supports.
+///
+/// # Arguments
+///
+/// * `image` - The image to save.
+/// * `path` - The file to save the image to.
+/// * `format` - The format to save the image in.
+/// * `metadata` - The metadata written into the file, if the format supports it.
+pub fn save(
+    image: &DynamicImage,
+    path: &Path,
+    format: ImageFormat,
+    metadata: &ImageMetadata,
+) -> anyhow::Result<()> {
+    encoding::save(image, path, format, &EncoderOptions::default(), metadata)
+}
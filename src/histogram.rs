@@ -0,0 +1,646 @@
+use crate::backend::ImageBackend;
+use crate::cudaimg::{
+    backend_for_image, CudaHistogramData, CudaImageData, ImageOperation, ImageProcessingFunction,
+    ToCudaImageData,
+};
+use anyhow::Context;
+use image::DynamicImage;
+use plotters::prelude::*;
+use serde::Serialize;
+use std::path::Path;
+
+/// The size of the plotted histogram chart in pixels.
+const PLOT_SIZE: (u32, u32) = (600, 400);
+
+/// The percentiles reported in the statistics of a histogram, besides the median.
+pub const PERCENTILES: [u32; 6] = [1, 5, 25, 75, 95, 99];
+
+/// Enum to represent the channels histograms are computed for.
+///
+/// * `Red` - The red channel.
+/// * `Green` - The green channel.
+/// * `Blue` - The blue channel.
+/// * `Luma` - The luminance of the pixels, the only channel of grayscale images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HistogramChannel {
+    Red,
+    Green,
+    Blue,
+    Luma,
+}
+
+impl HistogramChannel {
+    /// Every channel, in the order they are drawn.
+    pub const ALL: [HistogramChannel; 4] = [
+        HistogramChannel::Red,
+        HistogramChannel::Green,
+        HistogramChannel::Blue,
+        HistogramChannel::Luma,
+    ];
+
+    /// Get the human readable name of the channel.
+    pub fn label(&self) -> &'static str {
+        match self {
+            HistogramChannel::Red => "Red",
+            HistogramChannel::Green => "Green",
+            HistogramChannel::Blue => "Blue",
+            HistogramChannel::Luma => "Luma",
+        }
+    }
+
+    /// Get the color the histogram of the channel is drawn in, as RGB.
+    pub fn color(&self) -> [u8; 3] {
+        match self {
+            HistogramChannel::Red => [220, 50, 47],
+            HistogramChannel::Green => [60, 170, 60],
+            HistogramChannel::Blue => [40, 110, 210],
+            HistogramChannel::Luma => [90, 90, 90],
+        }
+    }
+}
+
+/// A percentile of the values counted by a histogram.
+///
+/// # Fields
+///
+/// * `percent` - The share of the values at or below `value`, in percent.
+/// * `value` - The lowest bin reaching that share.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Percentile {
+    pub percent: u32,
+    pub value: u32,
+}
+
+/// Statistics of the values counted by a histogram, in bins from 0 to 255.
+///
+/// # Fields
+///
+/// * `count` - The number of values counted.
+/// * `mean` - The mean value.
+/// * `median` - The lowest bin reaching half of the values.
+/// * `std_dev` - The standard deviation of the values.
+/// * `min` - The lowest bin with a value.
+/// * `max` - The highest bin with a value.
+/// * `percentiles` - The percentiles of [`PERCENTILES`].
+/// * `entropy` - The Shannon entropy of the histogram in bits, 8 at most.
+/// * `clipped_shadows` - The share of the values in the lowest bin, in percent.
+/// * `clipped_highlights` - The share of the values in the highest bin, in percent.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistogramStats {
+    pub count: u64,
+    pub mean: f64,
+    pub median: u32,
+    pub std_dev: f64,
+    pub min: u32,
+    pub max: u32,
+    pub percentiles: Vec<Percentile>,
+    pub entropy: f64,
+    pub clipped_shadows: f64,
+    pub clipped_highlights: f64,
+}
+
+impl HistogramStats {
+    /// Compute the statistics of a histogram. An empty histogram has only zeros.
+    pub fn from_histogram(histogram: &CudaHistogramData) -> Self {
+        let bins = &histogram.data;
+        let count: u64 = bins.iter().map(|&c| c as u64).sum();
+        let total = count.max(1) as f64;
+
+        let mean = bins
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| i as f64 * c as f64)
+            .sum::<f64>()
+            / total;
+        let variance = bins
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| (i as f64 - mean).powi(2) * c as f64)
+            .sum::<f64>()
+            / total;
+        let entropy = bins
+            .iter()
+            .filter(|&&c| c > 0)
+            .map(|&c| {
+                let p = c as f64 / total;
+                p * (1.0 / p).log2()
+            })
+            .sum();
+
+        // The lowest bin at which the running count reaches a share of the values
+        let percentile = |percent: u32| {
+            let target = (count as f64 * percent as f64 / 100.0).ceil().max(1.0) as u64;
+            let mut running = 0u64;
+            bins.iter()
+                .position(|&c| {
+                    running += c as u64;
+                    running >= target
+                })
+                .unwrap_or(0) as u32
+        };
+        let share = |c: Option<&u32>| c.copied().unwrap_or(0) as f64 / total * 100.0;
+
+        HistogramStats {
+            count,
+            mean,
+            median: percentile(50),
+            std_dev: variance.sqrt(),
+            min: bins.iter().position(|&c| c > 0).unwrap_or(0) as u32,
+            max: bins.iter().rposition(|&c| c > 0).unwrap_or(0) as u32,
+            percentiles: PERCENTILES
+                .iter()
+                .map(|&percent| Percentile {
+                    percent,
+                    value: percentile(percent),
+                })
+                .collect(),
+            entropy,
+            clipped_shadows: share(bins.first()),
+            clipped_highlights: share(bins.last()),
+        }
+    }
+
+    /// Get the statistics as labelled rows, in the order they are shown and exported.
+    pub fn rows(&self) -> Vec<(String, f64)> {
+        let mut rows = vec![
+            ("Count".to_string(), self.count as f64),
+            ("Mean".to_string(), self.mean),
+            ("Median".to_string(), self.median as f64),
+            ("Std dev".to_string(), self.std_dev),
+            ("Min".to_string(), self.min as f64),
+            ("Max".to_string(), self.max as f64),
+        ];
+        rows.extend(
+            self.percentiles
+                .iter()
+                .map(|p| (format!("P{}", p.percent), p.value as f64)),
+        );
+        rows.extend([
+            ("Entropy (bits)".to_string(), self.entropy),
+            ("Clipped shadows (%)".to_string(), self.clipped_shadows),
+            (
+                "Clipped highlights (%)".to_string(),
+                self.clipped_highlights,
+            ),
+        ]);
+
+        rows
+    }
+}
+
+/// The file formats histograms can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    /// Pick the format from the extension of a file, exports default to JSON.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => ExportFormat::Csv,
+            _ => ExportFormat::Json,
+        }
+    }
+}
+
+/// The bins and statistics of a channel, as exported to JSON.
+#[derive(Serialize)]
+struct ChannelExport<'a> {
+    bins: &'a [u32],
+    stats: HistogramStats,
+}
+
+/// The histograms of every channel, as exported to JSON.
+#[derive(Serialize)]
+struct HistogramsExport<'a> {
+    red: ChannelExport<'a>,
+    green: ChannelExport<'a>,
+    blue: ChannelExport<'a>,
+    luma: ChannelExport<'a>,
+}
+
+/// The histograms of the channels of an image, with 256 bins each.
+/// The channels of grayscale images all hold the histogram of the gray values.
+///
+/// # Fields
+///
+/// * `red` - The histogram of the red channel.
+/// * `green` - The histogram of the green channel.
+/// * `blue` - The histogram of the blue channel.
+/// * `luma` - The histogram of the luminance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelHistograms {
+    pub red: CudaHistogramData,
+    pub green: CudaHistogramData,
+    pub blue: CudaHistogramData,
+    pub luma: CudaHistogramData,
+}
+
+impl ChannelHistograms {
+    /// Get the histogram of a channel.
+    pub fn get(&self, channel: HistogramChannel) -> &CudaHistogramData {
+        match channel {
+            HistogramChannel::Red => &self.red,
+            HistogramChannel::Green => &self.green,
+            HistogramChannel::Blue => &self.blue,
+            HistogramChannel::Luma => &self.luma,
+        }
+    }
+
+    /// Compute the statistics of the histogram of a channel.
+    pub fn stats(&self, channel: HistogramChannel) -> HistogramStats {
+        HistogramStats::from_histogram(self.get(channel))
+    }
+
+    /// Serialize the bins and statistics of every channel.
+    ///
+    /// CSV files have a column per channel, with a row per statistic followed by a row per bin.
+    /// JSON files have an object per channel with its `bins` and `stats`.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format to serialize the histograms to.
+    pub fn serialize(&self, format: ExportFormat) -> anyhow::Result<String> {
+        let contents = match format {
+            ExportFormat::Csv => {
+                let mut csv = String::from("key");
+                for channel in HistogramChannel::ALL {
+                    csv.push(',');
+                    csv.push_str(&channel.label().to_lowercase());
+                }
+                csv.push('\n');
+
+                let rows: Vec<Vec<(String, f64)>> = HistogramChannel::ALL
+                    .iter()
+                    .map(|&channel| self.stats(channel).rows())
+                    .collect();
+                for (i, (label, _)) in rows[0].iter().enumerate() {
+                    csv.push_str(label);
+                    for channel_rows in &rows {
+                        csv.push_str(&format!(",{}", channel_rows[i].1));
+                    }
+                    csv.push('\n');
+                }
+
+                for bin in 0..self.luma.data.len() {
+                    csv.push_str(&format!("bin {}", bin));
+                    for channel in HistogramChannel::ALL {
+                        csv.push_str(&format!(",{}", self.get(channel).data[bin]));
+                    }
+                    csv.push('\n');
+                }
+
+                csv
+            }
+            ExportFormat::Json => {
+                let export = HistogramsExport {
+                    red: ChannelExport {
+                        bins: &self.red.data,
+                        stats: self.stats(HistogramChannel::Red),
+                    },
+                    green: ChannelExport {
+                        bins: &self.green.data,
+                        stats: self.stats(HistogramChannel::Green),
+                    },
+                    blue: ChannelExport {
+                        bins: &self.blue.data,
+                        stats: self.stats(HistogramChannel::Blue),
+                    },
+                    luma: ChannelExport {
+                        bins: &self.luma.data,
+                        stats: self.stats(HistogramChannel::Luma),
+                    },
+                };
+
+                serde_json::to_string_pretty(&export)?
+            }
+        };
+
+        Ok(contents)
+    }
+
+    /// Export the bins and statistics of every channel, the format is picked from the file's extension.
+    pub fn export(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = self.serialize(ExportFormat::from_path(path))?;
+
+        std::fs::write(path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Get the counts of the red, green and blue channels added together.
+    pub fn combined(&self) -> Vec<u32> {
+        self.red
+            .data
+            .iter()
+            .zip(&self.green.data)
+            .zip(&self.blue.data)
+            .map(|((r, g), b)| r + g + b)
+            .collect()
+    }
+}
+
+/// Compute the histogram of a single channel plane.
+fn compute_plane_histogram(
+    backend: &dyn ImageBackend,
+    mut plane: CudaImageData,
+) -> anyhow::Result<CudaHistogramData> {
+    let backend = backend_for_image(backend, &plane, ImageOperation::ComputeHistogram);
+
+    let mut histogram = CudaHistogramData::default();
+    backend.compute_histogram(&mut plane, &mut histogram)?;
+
+    Ok(histogram)
+}
+
+/// Compute the histograms of the red, green, blue and luminance channels of an image.
+///
+/// # Arguments
+///
+/// * `backend` - The backend to count the samples of each channel with.
+/// * `img` - The image to compute the histograms of.
+pub fn compute_histograms(
+    backend: &dyn ImageBackend,
+    img: &CudaImageData,
+) -> anyhow::Result<ChannelHistograms> {
+    let luma = compute_plane_histogram(backend, img.luma_plane())?;
+
+    if img.channels == 1 {
+        return Ok(ChannelHistograms {
+            red: luma.clone(),
+            green: luma.clone(),
+            blue: luma.clone(),
+            luma,
+        });
+    }
+
+    Ok(ChannelHistograms {
+        red: compute_plane_histogram(backend, img.channel_plane(0))?,
+        green: compute_plane_histogram(backend, img.channel_plane(1))?,
+        blue: compute_plane_histogram(backend, img.channel_plane(2))?,
+        luma,
+    })
+}
+
+/// Compute the histograms of the red, green, blue and luminance channels of an image.
+/// Unlike [`compute_histograms`], the backend is checked and the image validated first.
+///
+/// # Arguments
+///
+/// * `backend` - The backend chosen for image processing.
+/// * `image` - The image to compute the histograms of.
+pub fn image_histograms(
+    backend: &dyn ImageBackend,
+    image: &DynamicImage,
+) -> anyhow::Result<ChannelHistograms> {
+    let img = image.to_cuda_image_data();
+    let function = ImageProcessingFunction::ComputeHistogram;
+    let backend = backend_for_image(backend, &img, function.operation());
+
+    if !backend.supports(function.operation()) {
+        anyhow::bail!(
+            "{:?} is not supported by the {} backend",
+            function.operation(),
+            backend.name()
+        );
+    }
+    function.validate(&img)?;
+
+    compute_histograms(backend, &img)
+}
+
+/// Plot the histograms of channels over each other using plotters.
+///
+/// # Arguments
+///
+/// * `histograms` - The histograms to plot.
+/// * `channels` - The channels to draw, each in its own color.
+///
+/// # Returns
+///
+/// * The plotted histograms as a DynamicImage, rendered in memory.
+pub fn plot_histograms(
+    histograms: &ChannelHistograms,
+    channels: &[HistogramChannel],
+) -> anyhow::Result<DynamicImage> {
+    let (width, height) = PLOT_SIZE;
+    let mut buffer = vec![0u8; width as usize * height as usize * 3];
+
+    // Draw in a scope, so the backend releases the buffer once it is done
+    {
+        let root = BitMapBackend::with_buffer(&mut buffer, PLOT_SIZE).into_drawing_area();
+        draw_histograms(&root, histograms, channels)?;
+        root.present()?;
+    }
+
+    let image = image::RgbImage::from_raw(width, height, buffer)
+        .ok_or_else(|| anyhow::anyhow!("Failed to create the histogram image from bytes"))?;
+    Ok(DynamicImage::ImageRgb8(image))
+}
+
+/// Draw the histograms of channels over each other on a plotters drawing area.
+fn draw_histograms<DB: DrawingBackend>(
+    root: &DrawingArea<DB, plotters::coord::Shift>,
+    histograms: &ChannelHistograms,
+    channels: &[HistogramChannel],
+) -> anyhow::Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+
+    let max_count = channels
+        .iter()
+        .flat_map(|&channel| histograms.get(channel).data.iter().copied())
+        .max()
+        .unwrap_or(0);
+
+    let mut chart = ChartBuilder::on(root)
+        .caption("Histogram", ("sans-serif", 30).into_font())
+        .margin(5)
+        .x_label_area_size(30)
+        .y_label_area_size(30)
+        .build_cartesian_2d((0u32..256u32).into_segmented(), 0u32..max_count.max(1))?;
+
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .bold_line_style(WHITE.mix(0.3))
+        .y_desc("Count")
+        .x_desc("Pixel value")
+        .axis_desc_style(("sans-serif", 15))
+        .draw()?;
+
+    for &channel in channels {
+        let [r, g, b] = channel.color();
+        let color = RGBColor(r, g, b);
+
+        chart
+            .draw_series(
+                Histogram::vertical(&chart)
+                    .style(color.mix(0.4).filled())
+                    .margin(0)
+                    .data(
+                        histograms
+                            .get(channel)
+                            .data
+                            .iter()
+                            .enumerate()
+                            .map(|(i, &count)| (i as u32, count)),
+                    ),
+            )?
+            .label(channel.label())
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
+    }
+
+    if !channels.is_empty() {
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpuimg::CpuBackend;
+    use crate::test_utils::constant_rgb;
+    use image::{Rgb, RgbImage};
+
+    fn histogram(counts: &[(usize, u32)]) -> CudaHistogramData {
+        let mut histogram = CudaHistogramData::default();
+        for &(bin, count) in counts {
+            histogram.data[bin] = count;
+        }
+        histogram
+    }
+
+    /// A 4x4 image with black pixels on the left half and white pixels on the right half.
+    fn two_value_image() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(4, 4, |x, _| {
+            if x < 2 {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        }))
+    }
+
+    fn percentiles(stats: &HistogramStats) -> Vec<u32> {
+        stats.percentiles.iter().map(|p| p.value).collect()
+    }
+
+    #[test]
+    fn histogram_stats() {
+        // The bins, the count, mean and standard deviation, the median, minimum
+        // and maximum, the percentiles, the entropy and the clipped shares
+        type Case = (
+            &'static [(usize, u32)],
+            (u64, f64, f64),
+            (u32, u32, u32),
+            [u32; PERCENTILES.len()],
+            f64,
+            (f64, f64),
+        );
+        let cases: [Case; 3] = [
+            (
+                &[(10, 16)],
+                (16, 10.0, 0.0),
+                (10, 10, 10),
+                [10; 6],
+                0.0,
+                (0.0, 0.0),
+            ),
+            // Half of the values are reached in the lowest bin already
+            (
+                &[(0, 8), (255, 8)],
+                (16, 127.5, 127.5),
+                (0, 0, 255),
+                [0, 0, 0, 255, 255, 255],
+                1.0,
+                (50.0, 50.0),
+            ),
+            (&[], (0, 0.0, 0.0), (0, 0, 0), [0; 6], 0.0, (0.0, 0.0)),
+        ];
+
+        for (counts, moments, levels, expected_percentiles, entropy, clipped) in cases {
+            let stats = HistogramStats::from_histogram(&histogram(counts));
+
+            assert_eq!((stats.count, stats.mean, stats.std_dev), moments);
+            assert_eq!((stats.median, stats.min, stats.max), levels);
+            assert_eq!(percentiles(&stats), expected_percentiles);
+            assert_eq!(stats.entropy, entropy);
+            assert_eq!((stats.clipped_shadows, stats.clipped_highlights), clipped);
+        }
+    }
+
+    #[test]
+    fn percentiles_round_the_share_up() {
+        let stats = HistogramStats::from_histogram(&histogram(&[(1, 1), (2, 3)]));
+
+        assert_eq!(stats.median, 2);
+        assert_eq!(percentiles(&stats), [1, 1, 1, 2, 2, 2]);
+    }
+
+    #[test]
+    fn channel_histograms_of_a_constant_image() {
+        let histograms = image_histograms(&CpuBackend, &constant_rgb(4, 4, [10, 20, 30])).unwrap();
+
+        assert_eq!(histograms.red, histogram(&[(10, 16)]));
+        assert_eq!(histograms.green, histogram(&[(20, 16)]));
+        assert_eq!(histograms.blue, histogram(&[(30, 16)]));
+        assert_eq!(histograms.stats(HistogramChannel::Luma).std_dev, 0.0);
+        assert_eq!(histograms.combined()[10..31].iter().sum::<u32>(), 48);
+    }
+
+    #[test]
+    fn channel_histograms_of_a_two_value_image() {
+        // Grayscale images share the luma histogram between the channels
+        let rgb = two_value_image();
+        let gray = DynamicImage::ImageLuma8(rgb.to_luma8());
+
+        for image in [rgb, gray] {
+            let histograms = image_histograms(&CpuBackend, &image).unwrap();
+            for channel in HistogramChannel::ALL {
+                assert_eq!(histograms.get(channel), &histogram(&[(0, 8), (255, 8)]));
+            }
+        }
+    }
+
+    #[test]
+    fn csv_export_has_the_stats_and_every_bin() {
+        let histograms = image_histograms(&CpuBackend, &constant_rgb(4, 4, [10, 20, 30])).unwrap();
+        let csv = histograms.serialize(ExportFormat::Csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        let stats_rows = histograms.stats(HistogramChannel::Red).rows().len();
+        assert_eq!(lines.len(), 1 + stats_rows + 256);
+        assert_eq!(lines[0], "key,red,green,blue,luma");
+        assert!(lines[1].starts_with("Count,16,16,16,16"));
+        assert!(lines[3].starts_with("Median,10,20,30,"));
+        assert!(lines[1 + stats_rows + 10].starts_with("bin 10,16,0,0,"));
+        assert_eq!(lines[lines.len() - 1], "bin 255,0,0,0,0");
+    }
+
+    #[test]
+    fn json_export_has_the_bins_and_stats_of_every_channel() {
+        let histograms = image_histograms(&CpuBackend, &two_value_image()).unwrap();
+        let json = histograms.serialize(ExportFormat::Json).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        for channel in ["red", "green", "blue", "luma"] {
+            let export = &value[channel];
+            assert_eq!(export["bins"].as_array().unwrap().len(), 256);
+            assert_eq!(export["bins"][255], 8);
+            assert_eq!(export["stats"]["mean"], 127.5);
+            assert_eq!(export["stats"]["median"], 0);
+            assert_eq!(export["stats"]["percentiles"][5]["percent"], 99);
+            assert_eq!(export["stats"]["percentiles"][5]["value"], 255);
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use crate::pipeline::Pipeline;
+use image::DynamicImage;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The default memory limit of the image snapshots kept in the history, in bytes.
+pub const DEFAULT_HISTORY_MEMORY_LIMIT: usize = 512 * 1024 * 1024;
+
+/// A state of the editor recorded in the history.
+///
+/// # Fields
+///
+/// * `label` - A description of the change that led to the state.
+/// * `source` - A snapshot of the original image, shared between entries until it changes.
+/// * `pipeline` - The pipeline applied to the original image.
+/// * `duration` - How long running the pipeline of the state took, once it finished.
+#[derive(Clone)]
+pub struct HistoryEntry {
+    pub label: String,
+    pub source: Arc<DynamicImage>,
+    pub pipeline: Pipeline,
+    pub duration: Option<Duration>,
+}
+
+/// A multi-level undo/redo history of the editor's states.
+///
+/// # Fields
+///
+/// * `entries` - The recorded states, oldest first.
+/// * `current` - The index of the state currently shown.
+/// * `memory_limit` - The maximum size of the image snapshots kept, in bytes.
+/// * `merge_key` - The merge key of the last recorded state, cleared when moving in the history.
+/// * `sources` - The number of entries sharing each snapshot, by the address of the snapshot.
+/// * `memory_usage` - The size of the distinct snapshots kept, in bytes.
+pub struct History {
+    entries: Vec<HistoryEntry>,
+    current: usize,
+    memory_limit: usize,
+    merge_key: Option<String>,
+    sources: HashMap<usize, usize>,
+    memory_usage: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_MEMORY_LIMIT)
+    }
+}
+
+impl History {
+    /// Create an empty history.
+    ///
+    /// # Arguments
+    ///
+    /// * `memory_limit` - The maximum size of the image snapshots kept, in bytes.
+    ///   The oldest entries are dropped when it is exceeded, the current state is always kept.
+    pub fn new(memory_limit: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            current: 0,
+            memory_limit,
+            merge_key: None,
+            sources: HashMap::new(),
+            memory_usage: 0,
+        }
+    }
+
+    /// Get the recorded states, oldest first.
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    /// Get the index of the state currently shown.
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
+
+    /// Get the maximum size of the image snapshots kept, in bytes.
+    pub fn memory_limit(&self) -> usize {
+        self.memory_limit
+    }
+
+    /// Get the state currently shown.
+    pub fn current(&self) -> Option<&HistoryEntry> {
+        self.entries.get(self.current)
+    }
+
+    /// Record a new state. The states that could be redone are discarded.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - The state to record.
+    /// * `merge_key` - If the previous state was recorded with the same key, it is replaced
+    ///   instead, so dragging a slider is recorded as a single change.
+    pub fn record(&mut self, entry: HistoryEntry, merge_key: Option<String>) {
+        let discarded: Vec<_> = self
+            .entries
+            .drain((self.current + 1).min(self.entries.len())..)
+            .collect();
+        for entry in &discarded {
+            self.release(&entry.source);
+        }
+
+        let merge = merge_key.is_some() && merge_key == self.merge_key;
+        self.merge_key = merge_key;
+
+        // Counted before the merged entry is dropped, so a snapshot they share stays counted
+        self.retain(&entry.source);
+        if merge {
+            if let Some(last) = self.entries.pop() {
+                self.release(&last.source);
+            }
+        }
+        self.entries.push(entry);
+
+        self.current = self.entries.len() - 1;
+        self.enforce_memory_limit();
+    }
+
+    /// Check whether there is a state to go back to.
+    pub fn can_undo(&self) -> bool {
+        self.current > 0
+    }
+
+    /// Check whether there is an undone state to go forward to.
+    pub fn can_redo(&self) -> bool {
+        self.current + 1 < self.entries.len()
+    }
+
+    /// Go back to the previous state.
+    pub fn undo(&mut self) -> Option<&HistoryEntry> {
+        if !self.can_undo() {
+            return None;
+        }
+
+        self.current -= 1;
+        self.merge_key = None;
+        self.entries.get(self.current)
+    }
+
+    /// Go forward to the next undone state.
+    pub fn redo(&mut self) -> Option<&HistoryEntry> {
+        if !self.can_redo() {
+            return None;
+        }
+
+        self.current += 1;
+        self.merge_key = None;
+        self.entries.get(self.current)
+    }
+
+    /// Jump to any recorded state.
+    pub fn jump_to(&mut self, index: usize) -> Option<&HistoryEntry> {
+        if index >= self.entries.len() {
+            return None;
+        }
+
+        self.current = index;
+        self.merge_key = None;
+        self.entries.get(self.current)
+    }
+
+    /// Set how long running the pipeline of the current state took.
+    pub fn set_current_duration(&mut self, duration: Duration) {
+        if let Some(entry) = self.entries.get_mut(self.current) {
+            entry.duration = Some(duration);
+        }
+    }
+
+    /// Get the size of the distinct image snapshots kept, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage
+    }
+
+    /// Count a new entry sharing a snapshot, the size of a snapshot is counted once.
+    fn retain(&mut self, source: &Arc<DynamicImage>) {
+        let count = self
+            .sources
+            .entry(Arc::as_ptr(source) as usize)
+            .or_insert(0);
+        *count += 1;
+
+        if *count == 1 {
+            self.memory_usage += source.as_bytes().len();
+        }
+    }
+
+    /// Stop counting a dropped entry, the size of its snapshot is freed with the last entry sharing it.
+    fn release(&mut self, source: &Arc<DynamicImage>) {
+        let key = Arc::as_ptr(source) as usize;
+        let Some(count) = self.sources.get_mut(&key) else {
+            return;
+        };
+
+        *count -= 1;
+        if *count == 0 {
+            self.sources.remove(&key);
+            self.memory_usage -= source.as_bytes().len();
+        }
+    }
+
+    /// Drop the oldest entries until the snapshots fit into the memory limit.
+    fn enforce_memory_limit(&mut self) {
+        let mut dropped = 0;
+        while self.current > dropped && self.memory_usage > self.memory_limit {
+            let source = self.entries[dropped].source.clone();
+            self.release(&source);
+            dropped += 1;
+        }
+
+        self.entries.drain(..dropped);
+        self.current -= dropped;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::constant_rgb;
+
+    /// The size of the snapshots of the tests, in bytes.
+    const SNAPSHOT_SIZE: usize = 4 * 4 * 3;
+
+    fn snapshot() -> Arc<DynamicImage> {
+        Arc::new(constant_rgb(4, 4, [0, 0, 0]))
+    }
+
+    fn entry(label: &str, source: &Arc<DynamicImage>) -> HistoryEntry {
+        HistoryEntry {
+            label: label.to_string(),
+            source: source.clone(),
+            pipeline: Pipeline::default(),
+            duration: None,
+        }
+    }
+
+    fn labels(history: &History) -> Vec<&str> {
+        history
+            .entries()
+            .iter()
+            .map(|entry| entry.label.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn entries_with_the_same_key_are_merged() {
+        let source = snapshot();
+        let mut history = History::default();
+
+        history.record(entry("open", &source), None);
+        history.record(entry("gamma 1.1", &source), Some("gamma".to_string()));
+        history.record(entry("gamma 1.2", &source), Some("gamma".to_string()));
+        history.record(entry("blur", &source), Some("blur".to_string()));
+        assert_eq!(labels(&history), ["open", "gamma 1.2", "blur"]);
+
+        // Moving in the history ends the merge and discards the redo states
+        history.undo();
+        history.record(entry("blur 2", &source), Some("blur".to_string()));
+        history.record(entry("blur 3", &source), Some("blur".to_string()));
+        assert_eq!(labels(&history), ["open", "gamma 1.2", "blur 3"]);
+        assert_eq!(history.current_index(), 2);
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn entries_without_a_key_are_never_merged() {
+        let source = snapshot();
+        let mut history = History::default();
+
+        history.record(entry("invert", &source), None);
+        history.record(entry("invert", &source), None);
+        assert_eq!(history.entries().len(), 2);
+    }
+
+    #[test]
+    fn shared_snapshots_are_counted_once() {
+        let first = snapshot();
+        let second = snapshot();
+        let mut history = History::default();
+
+        history.record(entry("open", &first), None);
+        history.record(entry("invert", &first), None);
+        assert_eq!(history.memory_usage(), SNAPSHOT_SIZE);
+
+        history.record(entry("open other", &second), None);
+        assert_eq!(history.memory_usage(), 2 * SNAPSHOT_SIZE);
+
+        // The discarded redo state frees its snapshot, merging keeps the shared one
+        history.undo();
+        history.record(entry("gamma", &first), Some("gamma".to_string()));
+        history.record(entry("gamma", &first), Some("gamma".to_string()));
+        assert_eq!(history.memory_usage(), SNAPSHOT_SIZE);
+        assert_eq!(labels(&history), ["open", "invert", "gamma"]);
+    }
+
+    #[test]
+    fn entries_are_dropped_over_the_limit() {
+        // The limit, the number of recorded snapshots and the entries left
+        let cases: [(usize, usize, &[&str]); 3] = [
+            (4 * SNAPSHOT_SIZE, 3, &["0", "1", "2"]),
+            (2 * SNAPSHOT_SIZE, 4, &["2", "3"]),
+            // The current entry is kept even when it is over the limit alone
+            (SNAPSHOT_SIZE / 2, 2, &["1"]),
+        ];
+
+        for (limit, count, expected) in cases {
+            let mut history = History::new(limit);
+            for i in 0..count {
+                history.record(entry(&i.to_string(), &snapshot()), None);
+            }
+
+            assert_eq!(labels(&history), expected, "limit {}", limit);
+            assert_eq!(history.current_index(), expected.len() - 1);
+            assert_eq!(history.memory_usage(), expected.len() * SNAPSHOT_SIZE);
+        }
+    }
+}
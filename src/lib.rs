@@ -1,9 +1,30 @@
 use egui::{ColorImage, ImageSource, TextureHandle};
 use image::DynamicImage;
-use std::{borrow::Cow, path::PathBuf, sync::Arc};
+use std::{
+    borrow::Cow,
+    path::PathBuf,
+    sync::{Arc, Mutex, PoisonError},
+};
 
 pub mod app;
+pub mod backend;
+pub mod batch;
+pub mod cli;
+pub mod cpuimg;
 pub mod cudaimg;
+pub mod encoding;
+pub mod formats;
+pub mod histogram;
+pub mod history;
+pub mod loader;
+pub mod metadata;
+pub mod operations;
+pub mod pipeline;
+pub mod recipe;
+pub mod server;
+#[cfg(test)]
+mod test_utils;
+pub mod watch;
 
 /// A struct to hold the original and modified images as texture handles.
 /// This is useful to avoid loading the same image multiple times.
@@ -18,26 +39,22 @@ struct TextureMap {
     pub modified_image: Option<TextureHandle>,
 }
 
-/// A struct to hold the image modifiers.
+/// A struct to hold the parameter values of the operations, as set on the Tools menu sliders.
 ///
 /// # Fields
 ///
-/// * `gamma` - The gamma value to use for gamma transformation.
-/// * `log_base` - The base value to use for logarithmic transformation.
+/// * `values` - The parameter values of each operation, in the order of the operation registry.
 struct ImageModifiers {
-    pub gamma: f32,
-    pub log_base: f32,
-    pub box_filter_size: u32,
-    pub gauss_sigma: f32,
+    pub values: Vec<Vec<f32>>,
 }
 
 impl Default for ImageModifiers {
     fn default() -> Self {
         Self {
-            gamma: 2.2,
-            log_base: 2f32,
-            box_filter_size: 3,
-            gauss_sigma: 1.0,
+            values: operations::OPERATIONS
+                .iter()
+                .map(operations::OperationSpec::default_values)
+                .collect(),
         }
     }
 }
@@ -59,11 +76,11 @@ impl ToColorImage for DynamicImage {
 }
 
 pub trait ToImageSource {
-    fn to_image_source(&self, image_id: &str) -> egui::ImageSource;
+    fn to_image_source(&self, image_id: &str) -> egui::ImageSource<'_>;
 }
 
 impl ToImageSource for DynamicImage {
-    fn to_image_source(&self, image_id: &str) -> egui::ImageSource {
+    fn to_image_source(&self, image_id: &str) -> egui::ImageSource<'_> {
         let image_buffer: Arc<[u8]> = Arc::from(self.to_rgba8().into_raw().into_boxed_slice());
 
         ImageSource::Bytes {
@@ -109,13 +126,126 @@ impl ShowResizedTexture for egui::Ui {
     }
 }
 
+/// The folders of the batch processing window that can be picked with a dialog.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BatchFolder {
+    Input,
+    Output,
+}
+
+/// The image the histograms of the live histogram panel were computed for.
+///
+/// * `Original` - The original image.
+/// * `Modified` - The modified image of a generation.
+enum HistogramSource {
+    Original(Arc<DynamicImage>),
+    Modified(u64),
+}
+
 enum ImageProcessingTask {
     OpenImage {
         image: image::DynamicImage,
+        metadata: metadata::ImageMetadata,
         path: PathBuf,
     },
-    OperationFinished {
+    HistogramComputed {
+        histograms: histogram::ChannelHistograms,
+        duration: std::time::Duration,
+    },
+    LiveHistogramComputed {
+        source: HistogramSource,
+        histograms: Result<histogram::ChannelHistograms, String>,
+    },
+    PipelineFinished {
         image: image::DynamicImage,
         duration: std::time::Duration,
+        generation: u64,
+    },
+    RecipeLoaded {
+        pipeline: pipeline::Pipeline,
+        path: PathBuf,
+    },
+    BatchFolderPicked {
+        folder: BatchFolder,
+        path: PathBuf,
+    },
+    BatchStarted {
+        total: usize,
+    },
+    BatchFileFinished {
+        result: batch::BatchFileResult,
     },
+    BatchFinished {
+        report: Result<batch::BatchReport, String>,
+    },
+    SizeEstimated {
+        format: image::ImageFormat,
+        options: encoding::EncoderOptions,
+        keep_metadata: bool,
+        generation: u64,
+        size: Result<usize, String>,
+    },
+    Failed {
+        message: String,
+    },
+}
+
+impl ImageProcessingTask {
+    /// Create a failure message from an error.
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - What the task was trying to do.
+    /// * `error` - The error that made the task fail.
+    fn failed(context: &str, error: anyhow::Error) -> Self {
+        ImageProcessingTask::Failed {
+            message: format!("{}: {:#}", context, error),
+        }
+    }
+}
+
+/// A guard to mark an operation as in progress.
+/// The flag is released when the guard is dropped, so it can not get stuck
+/// even if the task returns early or fails.
+///
+/// # Fields
+///
+/// * `op_in_progress` - The flag shared between the GUI and the async tasks.
+struct OperationGuard {
+    op_in_progress: Arc<Mutex<bool>>,
+}
+
+impl OperationGuard {
+    /// Wait for the previous operation to finish and mark a new one as in progress.
+    ///
+    /// # Arguments
+    ///
+    /// * `op_in_progress` - The flag shared between the GUI and the async tasks.
+    async fn acquire(op_in_progress: Arc<Mutex<bool>>) -> Self {
+        loop {
+            {
+                let mut in_progress = op_in_progress
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+
+                if !*in_progress {
+                    *in_progress = true;
+                    break;
+                }
+            }
+
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+
+        Self { op_in_progress }
+    }
+}
+
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        *self
+            .op_in_progress
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = false;
+    }
 }
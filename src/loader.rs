@@ -0,0 +1,171 @@
+use libloading::Library;
+use log::{debug, info};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Environment variable that can point to the libcudaimg library or the directory containing it.
+pub const LIBCUDAIMG_ENV_VAR: &str = "IMG_UTILS_LIBCUDAIMG";
+
+/// Command line flag that can point to the libcudaimg library or the directory containing it.
+pub const LIBCUDAIMG_CLI_FLAG: &str = "--libcudaimg";
+
+/// Get the platform specific file name of the libcudaimg library.
+///
+/// # Returns
+///
+/// * `libcudaimg.dll` on Windows, `libcudaimg.dylib` on macOS and `libcudaimg.so` elsewhere.
+pub fn libcudaimg_file_name() -> String {
+    format!("libcudaimg{}", std::env::consts::DLL_SUFFIX)
+}
+
+/// A location that was tried while searching for libcudaimg.
+///
+/// # Fields
+///
+/// * `location` - The description of the location that was tried.
+/// * `error` - The reason the library could not be loaded from the location.
+#[derive(Debug)]
+pub struct LoadAttempt {
+    pub location: String,
+    pub error: String,
+}
+
+/// Error returned when libcudaimg could not be loaded from any of the searched locations.
+///
+/// # Fields
+///
+/// * `attempts` - Every location that was tried, in search order.
+#[derive(Debug)]
+pub struct LibcudaimgNotFound {
+    pub attempts: Vec<LoadAttempt>,
+}
+
+impl fmt::Display for LibcudaimgNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to load {}, tried:", libcudaimg_file_name())?;
+
+        for attempt in &self.attempts {
+            write!(f, "\n  - {}: {}", attempt.location, attempt.error)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for LibcudaimgNotFound {}
+
+/// Resolve a user supplied path, which may point to the library itself or to its directory.
+fn resolve_user_path(path: &Path) -> PathBuf {
+    if path.is_dir() {
+        path.join(libcudaimg_file_name())
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Collect the candidate locations of libcudaimg in search order.
+///
+/// # Arguments
+///
+/// * `cli_path` - The path given with the `--libcudaimg` command line flag, if any.
+fn candidates(cli_path: Option<&Path>) -> Vec<(String, PathBuf)> {
+    let file_name = libcudaimg_file_name();
+    let mut candidates = Vec::new();
+
+    if let Some(path) = cli_path {
+        let path = resolve_user_path(path);
+        candidates.push((format!("{} {}", LIBCUDAIMG_CLI_FLAG, path.display()), path));
+    }
+
+    if let Some(path) = std::env::var_os(LIBCUDAIMG_ENV_VAR) {
+        let path = resolve_user_path(Path::new(&path));
+        candidates.push((format!("${} {}", LIBCUDAIMG_ENV_VAR, path.display()), path));
+    }
+
+    if let Some(exe_dir) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+    {
+        for path in [
+            exe_dir.join(&file_name),
+            exe_dir.join("data").join(&file_name),
+        ] {
+            candidates.push((format!("executable directory {}", path.display()), path));
+        }
+    }
+
+    // The location used by libcudaimg_build.bat when running from the repository root
+    let path = Path::new("data").join(&file_name);
+    candidates.push((format!("working directory {}", path.display()), path));
+
+    // A bare file name lets the system loader search its own paths
+    candidates.push((
+        format!("system loader path {}", file_name),
+        PathBuf::from(&file_name),
+    ));
+
+    candidates
+}
+
+/// Search for libcudaimg and load the first library found.
+///
+/// The locations are searched in the following order:
+///
+/// 1. The path given with the `--libcudaimg` command line flag.
+/// 2. The path in the `IMG_UTILS_LIBCUDAIMG` environment variable.
+/// 3. The directory of the executable and its `data` subdirectory.
+/// 4. The `data` directory in the working directory.
+/// 5. The search path of the system loader.
+///
+/// # Arguments
+///
+/// * `cli_path` - The path given with the `--libcudaimg` command line flag, if any.
+///
+/// # Returns
+///
+/// * The loaded library and the location it was loaded from, or every location that was tried.
+pub fn load_libcudaimg(cli_path: Option<&Path>) -> Result<(Library, PathBuf), LibcudaimgNotFound> {
+    let mut attempts = Vec::new();
+
+    for (location, path) in candidates(cli_path) {
+        debug!("Trying to load libcudaimg from {}", location);
+
+        match unsafe { Library::new(&path) } {
+            Ok(library) => {
+                info!("Loaded libcudaimg from {}", location);
+                return Ok((library, path));
+            }
+            Err(e) => attempts.push(LoadAttempt {
+                location,
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    Err(LibcudaimgNotFound { attempts })
+}
+
+/// Get the value of the `--libcudaimg` flag from the command line arguments.
+/// Both `--libcudaimg <path>` and `--libcudaimg=<path>` are accepted.
+///
+/// # Arguments
+///
+/// * `args` - The command line arguments, without the program name.
+pub fn libcudaimg_cli_path(args: impl IntoIterator<Item = String>) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if arg == LIBCUDAIMG_CLI_FLAG {
+            return args.next().map(PathBuf::from);
+        }
+
+        if let Some(path) = arg
+            .strip_prefix(LIBCUDAIMG_CLI_FLAG)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    None
+}
@@ -1,15 +1,15 @@
 #![windows_subsystem = "windows"]
 
-use libloading::Library;
-use std::path::Path;
+use img_utils::backend;
+use img_utils::loader;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
 
-    // Load the libcudaimg library
-    let lib_path = Path::new("data/libcudaimg.dll");
-    let libcudaimg = unsafe { Library::new(lib_path)? };
+    // Load the libcudaimg library, fall back to the CPU implementation if it is missing
+    let cli_path = loader::libcudaimg_cli_path(std::env::args().skip(1));
+    let backend = backend::load_backend(cli_path.as_deref());
 
     let options = eframe::NativeOptions {
         vsync: true,
@@ -20,7 +20,7 @@ async fn main() -> anyhow::Result<()> {
     if let Err(e) = eframe::run_native(
         "Image Processing Utility",
         options,
-        Box::new(|_cc| Ok(Box::new(img_utils::app::MyApp::new(libcudaimg)))),
+        Box::new(|_cc| Ok(Box::new(img_utils::app::MyApp::new(backend)))),
     ) {
         eprintln!("Failed to run eframe native: {:?}", e);
     }
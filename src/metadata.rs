@@ -0,0 +1,799 @@
+use anyhow::Context;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use image::{DynamicImage, ImageFormat};
+use log::warn;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+
+/// The EXIF tag of the orientation of the image.
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// The signature of the EXIF segment of JPEG files.
+const JPEG_EXIF_SIGNATURE: &[u8] = b"Exif\0\0";
+
+/// The signature of the XMP segment of JPEG files.
+const JPEG_XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// The signature of the ICC profile segments of JPEG files.
+const JPEG_ICC_SIGNATURE: &[u8] = b"ICC_PROFILE\0";
+
+/// The largest payload of a JPEG segment, its length field counts itself too.
+const JPEG_MAX_SEGMENT: usize = 65533;
+
+/// The keyword of the text chunk holding the XMP packet of PNG files.
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+/// The metadata of an image file that is kept when the image is saved again.
+///
+/// # Fields
+///
+/// * `icc_profile` - The ICC color profile.
+/// * `exif` - The EXIF data, as a TIFF structure without the `Exif` signature of JPEG files.
+/// * `xmp` - The XMP packet.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImageMetadata {
+    pub icc_profile: Option<Vec<u8>>,
+    pub exif: Option<Vec<u8>>,
+    pub xmp: Option<Vec<u8>>,
+}
+
+impl ImageMetadata {
+    /// Check whether the file had no metadata.
+    pub fn is_empty(&self) -> bool {
+        self.icc_profile.is_none() && self.exif.is_none() && self.xmp.is_none()
+    }
+
+    /// Read the metadata of an image file.
+    /// Metadata that can not be parsed is skipped, it never fails opening the image.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The contents of the file.
+    /// * `format` - The format of the file.
+    pub fn read(bytes: &[u8], format: ImageFormat) -> Self {
+        match format {
+            ImageFormat::Jpeg => read_jpeg(bytes),
+            ImageFormat::Png => read_png(bytes),
+            ImageFormat::WebP => read_webp(bytes),
+            _ => Self::default(),
+        }
+    }
+
+    /// Get the EXIF orientation, from 1 (upright) to 8.
+    pub fn orientation(&self) -> Option<u16> {
+        let exif = self.exif.as_deref()?;
+        let (offset, big_endian) = find_orientation(exif)?;
+
+        Some(read_u16(exif, offset, big_endian)).filter(|value| (1..=8).contains(value))
+    }
+
+    /// Mark the image as upright in the EXIF data, once the orientation was applied to the pixels.
+    fn reset_orientation(&mut self) {
+        let Some(exif) = self.exif.as_mut() else {
+            return;
+        };
+
+        if let Some((offset, big_endian)) = find_orientation(exif) {
+            let value = if big_endian {
+                1u16.to_be_bytes()
+            } else {
+                1u16.to_le_bytes()
+            };
+            exif[offset..offset + 2].copy_from_slice(&value);
+        }
+    }
+}
+
+/// Check whether the metadata is written back when saving in a format.
+pub fn is_supported(format: ImageFormat) -> bool {
+    matches!(
+        format,
+        ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP
+    )
+}
+
+/// Decode an image file, rotate it upright according to its EXIF orientation and read its metadata.
+///
+/// # Arguments
+///
+/// * `bytes` - The contents of the file.
+/// * `format` - The format of the file.
+pub fn decode(
+    bytes: &[u8],
+    format: ImageFormat,
+) -> image::ImageResult<(DynamicImage, ImageMetadata)> {
+    decode_with_limits(bytes, format, image::Limits::default())
+}
+
+/// Decode an image file like [`decode`], rejecting images that exceed the limits
+/// before their pixels are allocated.
+///
+/// # Arguments
+///
+/// * `bytes` - The contents of the file.
+/// * `format` - The format of the file.
+/// * `limits` - The largest dimensions and allocation the decoder accepts.
+pub fn decode_with_limits(
+    bytes: &[u8],
+    format: ImageFormat,
+    limits: image::Limits,
+) -> image::ImageResult<(DynamicImage, ImageMetadata)> {
+    let mut reader = image::ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    let image = reader.decode()?;
+    let mut metadata = ImageMetadata::read(bytes, format);
+
+    // TIFF files keep the orientation in their own tags, they have the same layout as EXIF
+    let orientation = match format {
+        ImageFormat::Tiff => {
+            find_orientation(bytes).map(|(offset, big_endian)| read_u16(bytes, offset, big_endian))
+        }
+        _ => metadata.orientation(),
+    };
+
+    let image = match orientation {
+        Some(orientation) => apply_orientation(image, orientation),
+        None => image,
+    };
+    metadata.reset_orientation();
+
+    Ok((image, metadata))
+}
+
+/// Open an image file, rotate it upright according to its EXIF orientation and read its metadata.
+///
+/// # Arguments
+///
+/// * `path` - The image file to open, its format is guessed from the contents and the extension.
+pub fn open(path: &Path) -> anyhow::Result<(DynamicImage, ImageMetadata)> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let format = image::guess_format(&bytes)
+        .or_else(|_| ImageFormat::from_path(path))
+        .with_context(|| format!("Unknown image format of {}", path.display()))?;
+
+    decode(&bytes, format).with_context(|| format!("Failed to decode {}", path.display()))
+}
+
+/// Transform an image so it is upright.
+///
+/// # Arguments
+///
+/// * `image` - The image as it is stored.
+/// * `orientation` - The EXIF orientation, from 1 (upright) to 8.
+pub fn apply_orientation(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Write the metadata into an encoded image file.
+/// Formats without metadata support, see [`is_supported`], are returned unchanged.
+///
+/// # Arguments
+///
+/// * `bytes` - The encoded image file, as written by the `image` crate.
+/// * `format` - The format of the file.
+/// * `image` - The image that was encoded.
+/// * `metadata` - The metadata to write.
+pub fn embed(
+    bytes: Vec<u8>,
+    format: ImageFormat,
+    image: &DynamicImage,
+    metadata: &ImageMetadata,
+) -> anyhow::Result<Vec<u8>> {
+    let mut metadata = metadata.clone();
+
+    // A profile of another color space would make viewers misread the colors
+    if let Some(profile) = &metadata.icc_profile {
+        if !icc_matches(profile, image) {
+            metadata.icc_profile = None;
+        }
+    }
+
+    if metadata.is_empty() {
+        return Ok(bytes);
+    }
+
+    match format {
+        ImageFormat::Jpeg => embed_jpeg(bytes, &metadata),
+        ImageFormat::Png => embed_png(bytes, &metadata),
+        ImageFormat::WebP => embed_webp(bytes, image, &metadata),
+        _ => Ok(bytes),
+    }
+}
+
+/// Check whether the color space of an ICC profile matches the channels of an image.
+fn icc_matches(profile: &[u8], image: &DynamicImage) -> bool {
+    match profile.get(16..20) {
+        Some(b"RGB ") => image.color().has_color(),
+        Some(b"GRAY") => !image.color().has_color(),
+        _ => false,
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize, big_endian: bool) -> u16 {
+    let value = [bytes[offset], bytes[offset + 1]];
+
+    if big_endian {
+        u16::from_be_bytes(value)
+    } else {
+        u16::from_le_bytes(value)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize, big_endian: bool) -> u32 {
+    let value = [
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ];
+
+    if big_endian {
+        u32::from_be_bytes(value)
+    } else {
+        u32::from_le_bytes(value)
+    }
+}
+
+/// Find the orientation value in the first directory of a TIFF structure, as used by EXIF.
+///
+/// # Returns
+///
+/// * The offset of the value and whether the structure is big endian.
+fn find_orientation(tiff: &[u8]) -> Option<(usize, bool)> {
+    let big_endian = match tiff.get(0..4)? {
+        b"II*\0" => false,
+        b"MM\0*" => true,
+        _ => return None,
+    };
+
+    if tiff.len() < 8 {
+        return None;
+    }
+
+    let directory = read_u32(tiff, 4, big_endian) as usize;
+    if tiff.len() < directory + 2 {
+        return None;
+    }
+
+    let count = read_u16(tiff, directory, big_endian);
+
+    for i in 0..usize::from(count) {
+        let entry = directory + 2 + i * 12;
+        if tiff.len() < entry + 12 {
+            return None;
+        }
+
+        // A single SHORT stored in the value field of the entry
+        if read_u16(tiff, entry, big_endian) == ORIENTATION_TAG
+            && read_u16(tiff, entry + 2, big_endian) == 3
+        {
+            return Some((entry + 8, big_endian));
+        }
+    }
+
+    None
+}
+
+/// Read the metadata segments of a JPEG file, up to the start of the image data.
+fn read_jpeg(bytes: &[u8]) -> ImageMetadata {
+    let mut metadata = ImageMetadata::default();
+    let mut icc_chunks: Vec<(u8, &[u8])> = Vec::new();
+    let mut pos = 2;
+
+    while pos + 4 <= bytes.len() && bytes[pos] == 0xFF {
+        let marker = bytes[pos + 1];
+
+        // Markers without a length
+        if marker == 0x01 || (0xD0..=0xD8).contains(&marker) {
+            pos += 2;
+            continue;
+        }
+
+        // The image data starts, no metadata follows
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+
+        let length = usize::from(u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]));
+        let Some(data) = bytes.get(pos + 4..pos + 2 + length) else {
+            break;
+        };
+
+        match marker {
+            0xE1 if data.starts_with(JPEG_EXIF_SIGNATURE) => {
+                metadata.exif = Some(data[JPEG_EXIF_SIGNATURE.len()..].to_vec());
+            }
+            0xE1 if data.starts_with(JPEG_XMP_SIGNATURE) => {
+                metadata.xmp = Some(data[JPEG_XMP_SIGNATURE.len()..].to_vec());
+            }
+            0xE2 if data.starts_with(JPEG_ICC_SIGNATURE) && data.len() > 14 => {
+                icc_chunks.push((data[12], &data[14..]));
+            }
+            _ => {}
+        }
+
+        pos += 2 + length;
+    }
+
+    // The profile is split over numbered segments
+    if !icc_chunks.is_empty() {
+        icc_chunks.sort_by_key(|(sequence, _)| *sequence);
+        metadata.icc_profile = Some(
+            icc_chunks
+                .iter()
+                .flat_map(|(_, data)| data.iter().copied())
+                .collect(),
+        );
+    }
+
+    metadata
+}
+
+/// Build a JPEG segment.
+fn jpeg_segment(marker: u8, signature: &[u8], data: &[u8]) -> Vec<u8> {
+    let length = (2 + signature.len() + data.len()) as u16;
+
+    let mut segment = vec![0xFF, marker];
+    segment.extend_from_slice(&length.to_be_bytes());
+    segment.extend_from_slice(signature);
+    segment.extend_from_slice(data);
+    segment
+}
+
+/// Insert the metadata segments after the header segment of a JPEG file.
+fn embed_jpeg(bytes: Vec<u8>, metadata: &ImageMetadata) -> anyhow::Result<Vec<u8>> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        anyhow::bail!("Not a JPEG file");
+    }
+
+    let mut segments = Vec::new();
+
+    if let Some(exif) = &metadata.exif {
+        if JPEG_EXIF_SIGNATURE.len() + exif.len() <= JPEG_MAX_SEGMENT {
+            segments.extend(jpeg_segment(0xE1, JPEG_EXIF_SIGNATURE, exif));
+        } else {
+            warn!("The EXIF data is too large for a JPEG segment, it is not saved");
+        }
+    }
+
+    if let Some(xmp) = &metadata.xmp {
+        if JPEG_XMP_SIGNATURE.len() + xmp.len() <= JPEG_MAX_SEGMENT {
+            segments.extend(jpeg_segment(0xE1, JPEG_XMP_SIGNATURE, xmp));
+        } else {
+            warn!("The XMP packet is too large for a JPEG segment, it is not saved");
+        }
+    }
+
+    if let Some(profile) = &metadata.icc_profile {
+        let chunk_size = JPEG_MAX_SEGMENT - JPEG_ICC_SIGNATURE.len() - 2;
+        let chunks: Vec<_> = profile.chunks(chunk_size).collect();
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut signature = JPEG_ICC_SIGNATURE.to_vec();
+            signature.extend_from_slice(&[(i + 1) as u8, chunks.len() as u8]);
+            segments.extend(jpeg_segment(0xE2, &signature, chunk));
+        }
+    }
+
+    // Keep the JFIF header first
+    let mut pos = 2;
+    if bytes.get(2..4) == Some(&[0xFF, 0xE0]) && bytes.len() >= 6 {
+        pos += 2 + usize::from(u16::from_be_bytes([bytes[4], bytes[5]]));
+    }
+
+    let mut output = Vec::with_capacity(bytes.len() + segments.len());
+    output.extend_from_slice(&bytes[..pos]);
+    output.extend_from_slice(&segments);
+    output.extend_from_slice(&bytes[pos..]);
+
+    Ok(output)
+}
+
+/// Read the metadata chunks of a PNG file.
+fn read_png(bytes: &[u8]) -> ImageMetadata {
+    let mut metadata = ImageMetadata::default();
+    let mut pos = 8;
+
+    while pos + 8 <= bytes.len() {
+        let length =
+            u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+                as usize;
+        let kind = &bytes[pos + 4..pos + 8];
+        let Some(data) = bytes.get(pos + 8..pos + 8 + length) else {
+            break;
+        };
+
+        match kind {
+            b"eXIf" => metadata.exif = Some(data.to_vec()),
+            b"iCCP" => metadata.icc_profile = read_png_icc(data),
+            b"iTXt" => {
+                if let Some(xmp) = read_png_xmp(data) {
+                    metadata.xmp = Some(xmp);
+                }
+            }
+            b"IEND" => break,
+            _ => {}
+        }
+
+        // The data is followed by its CRC
+        pos += 12 + length;
+    }
+
+    metadata
+}
+
+/// Read the compressed profile of an `iCCP` chunk, after its name and compression method.
+fn read_png_icc(data: &[u8]) -> Option<Vec<u8>> {
+    let name_end = data.iter().position(|&b| b == 0)?;
+    let compressed = data.get(name_end + 2..)?;
+
+    let mut profile = Vec::new();
+    ZlibDecoder::new(compressed)
+        .read_to_end(&mut profile)
+        .ok()?;
+
+    Some(profile)
+}
+
+/// Read the XMP packet of an `iTXt` chunk, if it holds one.
+fn read_png_xmp(data: &[u8]) -> Option<Vec<u8>> {
+    let keyword_end = data.iter().position(|&b| b == 0)?;
+    if &data[..keyword_end] != PNG_XMP_KEYWORD {
+        return None;
+    }
+
+    let compressed = *data.get(keyword_end + 1)? == 1;
+
+    // Skip the language tag and the translated keyword
+    let rest = data.get(keyword_end + 3..)?;
+    let language_end = rest.iter().position(|&b| b == 0)?;
+    let rest = &rest[language_end + 1..];
+    let translated_end = rest.iter().position(|&b| b == 0)?;
+    let text = &rest[translated_end + 1..];
+
+    if compressed {
+        let mut xmp = Vec::new();
+        ZlibDecoder::new(text).read_to_end(&mut xmp).ok()?;
+        Some(xmp)
+    } else {
+        Some(text.to_vec())
+    }
+}
+
+/// Build a PNG chunk.
+fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    chunk.extend_from_slice(&crc.finalize().to_be_bytes());
+
+    chunk
+}
+
+/// Insert the metadata chunks after the header chunk of a PNG file.
+fn embed_png(bytes: Vec<u8>, metadata: &ImageMetadata) -> anyhow::Result<Vec<u8>> {
+    // The signature and the IHDR chunk with its 13 bytes of data
+    const HEADER_END: usize = 8 + 12 + 13;
+
+    if bytes.get(12..16) != Some(b"IHDR") || bytes.len() < HEADER_END {
+        anyhow::bail!("Not a PNG file");
+    }
+
+    let mut chunks = Vec::new();
+
+    if let Some(profile) = &metadata.icc_profile {
+        let mut data = b"ICC Profile\0\0".to_vec();
+        let mut encoder = ZlibEncoder::new(&mut data, flate2::Compression::default());
+        encoder.write_all(profile)?;
+        encoder.finish()?;
+
+        chunks.extend(png_chunk(b"iCCP", &data));
+    }
+
+    if let Some(exif) = &metadata.exif {
+        chunks.extend(png_chunk(b"eXIf", exif));
+    }
+
+    if let Some(xmp) = &metadata.xmp {
+        // Uncompressed, without a language tag or translated keyword
+        let mut data = PNG_XMP_KEYWORD.to_vec();
+        data.extend_from_slice(&[0, 0, 0, 0, 0]);
+        data.extend_from_slice(xmp);
+
+        chunks.extend(png_chunk(b"iTXt", &data));
+    }
+
+    let mut output = Vec::with_capacity(bytes.len() + chunks.len());
+    output.extend_from_slice(&bytes[..HEADER_END]);
+    output.extend_from_slice(&chunks);
+    output.extend_from_slice(&bytes[HEADER_END..]);
+
+    Ok(output)
+}
+
+/// Split the contents of a WebP file into its chunks.
+fn webp_chunks(bytes: &[u8]) -> Option<Vec<([u8; 4], &[u8])>> {
+    if bytes.get(0..4)? != b"RIFF" || bytes.get(8..12)? != b"WEBP" {
+        return None;
+    }
+
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+
+    while pos + 8 <= bytes.len() {
+        let kind = [bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]];
+        let length = read_u32(bytes, pos + 4, false) as usize;
+        let data = bytes.get(pos + 8..pos + 8 + length)?;
+
+        chunks.push((kind, data));
+
+        // Chunks are padded to an even length
+        pos += 8 + length + length % 2;
+    }
+
+    Some(chunks)
+}
+
+/// Read the metadata chunks of a WebP file.
+fn read_webp(bytes: &[u8]) -> ImageMetadata {
+    let mut metadata = ImageMetadata::default();
+
+    for (kind, data) in webp_chunks(bytes).unwrap_or_default() {
+        match &kind {
+            b"ICCP" => metadata.icc_profile = Some(data.to_vec()),
+            // Some writers keep the signature of JPEG files
+            b"EXIF" => {
+                let data = data.strip_prefix(JPEG_EXIF_SIGNATURE).unwrap_or(data);
+                metadata.exif = Some(data.to_vec());
+            }
+            b"XMP " => metadata.xmp = Some(data.to_vec()),
+            _ => {}
+        }
+    }
+
+    metadata
+}
+
+/// Rebuild a WebP file in the extended format, which can hold metadata.
+fn embed_webp(
+    bytes: Vec<u8>,
+    image: &DynamicImage,
+    metadata: &ImageMetadata,
+) -> anyhow::Result<Vec<u8>> {
+    let chunks = webp_chunks(&bytes).context("Not a WebP file")?;
+
+    let mut flags = 0u8;
+    if metadata.icc_profile.is_some() {
+        flags |= 0x20;
+    }
+    if image.color().has_alpha() {
+        flags |= 0x10;
+    }
+    if metadata.exif.is_some() {
+        flags |= 0x08;
+    }
+    if metadata.xmp.is_some() {
+        flags |= 0x04;
+    }
+
+    // The flags, 3 reserved bytes and the canvas size minus one in 24 bits
+    let mut header = vec![flags, 0, 0, 0];
+    header.extend_from_slice(&(image.width() - 1).to_le_bytes()[..3]);
+    header.extend_from_slice(&(image.height() - 1).to_le_bytes()[..3]);
+
+    let mut output = Vec::new();
+    let mut push_chunk = |kind: &[u8; 4], data: &[u8]| {
+        output.extend_from_slice(kind);
+        output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        output.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            output.push(0);
+        }
+    };
+
+    push_chunk(b"VP8X", &header);
+    if let Some(profile) = &metadata.icc_profile {
+        push_chunk(b"ICCP", profile);
+    }
+
+    // The image data, without the metadata of the encoder
+    for (kind, data) in &chunks {
+        if !matches!(kind, b"VP8X" | b"ICCP" | b"EXIF" | b"XMP ") {
+            push_chunk(kind, data);
+        }
+    }
+
+    if let Some(exif) = &metadata.exif {
+        push_chunk(b"EXIF", exif);
+    }
+    if let Some(xmp) = &metadata.xmp {
+        push_chunk(b"XMP ", xmp);
+    }
+
+    let mut file = b"RIFF".to_vec();
+    file.extend_from_slice(&(4 + output.len() as u32).to_le_bytes());
+    file.extend_from_slice(b"WEBP");
+    file.extend_from_slice(&output);
+
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{encode, rgb};
+    use image::{ImageDecoder, ImageReader, Rgb};
+
+    /// A little endian EXIF structure with a single orientation entry and an odd length.
+    fn exif(orientation: u16) -> Vec<u8> {
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend_from_slice(&ORIENTATION_TAG.to_le_bytes());
+        tiff.extend_from_slice(&3u16.to_le_bytes());
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+        tiff.extend_from_slice(&orientation.to_le_bytes());
+        tiff.extend_from_slice(&[0, 0]);
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff.push(0);
+        tiff
+    }
+
+    /// An RGB profile of an odd length, only its header is looked at.
+    fn icc_profile(len: usize) -> Vec<u8> {
+        let mut profile: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        profile[16..20].copy_from_slice(b"RGB ");
+        profile
+    }
+
+    fn metadata(orientation: u16) -> ImageMetadata {
+        ImageMetadata {
+            icc_profile: Some(icc_profile(301)),
+            exif: Some(exif(orientation)),
+            xmp: Some(b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>".to_vec()),
+        }
+    }
+
+    /// A 3x2 image with a distinct color in every pixel.
+    fn image() -> DynamicImage {
+        #[rustfmt::skip]
+        let pixels = [
+            0, 0, 50, 100, 0, 50, 200, 0, 50,
+            0, 200, 50, 100, 200, 50, 200, 200, 50,
+        ];
+        rgb(3, 2, &pixels)
+    }
+
+    #[test]
+    fn metadata_round_trips() {
+        for format in [ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::WebP] {
+            let image = image();
+            let written = metadata(6);
+            let bytes = embed(encode(&image, format), format, &image, &written).unwrap();
+
+            assert_eq!(ImageMetadata::read(&bytes, format), written, "{:?}", format);
+
+            // Both readers see the profile and the orientation is applied
+            let (decoded, read) = decode(&bytes, format).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (2, 3));
+            assert_eq!(read.orientation(), Some(1));
+            assert_eq!(read.icc_profile, written.icc_profile);
+            assert_eq!(read.xmp, written.xmp);
+
+            let mut decoder = ImageReader::with_format(Cursor::new(&bytes), format)
+                .into_decoder()
+                .unwrap();
+            assert_eq!(decoder.icc_profile().unwrap(), written.icc_profile);
+        }
+    }
+
+    #[test]
+    fn jpeg_profile_is_split_over_segments() {
+        let image = image();
+        let written = ImageMetadata {
+            icc_profile: Some(icc_profile(2 * JPEG_MAX_SEGMENT)),
+            ..Default::default()
+        };
+        let bytes = embed(
+            encode(&image, ImageFormat::Jpeg),
+            ImageFormat::Jpeg,
+            &image,
+            &written,
+        )
+        .unwrap();
+
+        let segments = bytes
+            .windows(2 + 2 + JPEG_ICC_SIGNATURE.len())
+            .filter(|window| window[..2] == [0xFF, 0xE2] && window[4..] == *JPEG_ICC_SIGNATURE)
+            .count();
+        assert_eq!(segments, 3);
+
+        assert_eq!(ImageMetadata::read(&bytes, ImageFormat::Jpeg), written);
+        let mut decoder = image::codecs::jpeg::JpegDecoder::new(Cursor::new(&bytes)).unwrap();
+        assert_eq!(decoder.icc_profile().unwrap(), written.icc_profile);
+    }
+
+    #[test]
+    fn webp_chunks_have_their_sizes_and_padding() {
+        let image = image();
+        let written = metadata(1);
+        let bytes = embed(
+            encode(&image, ImageFormat::WebP),
+            ImageFormat::WebP,
+            &image,
+            &written,
+        )
+        .unwrap();
+
+        assert_eq!(read_u32(&bytes, 4, false) as usize, bytes.len() - 8);
+        assert_eq!(bytes.len() % 2, 0);
+
+        // Every chunk starts at an even offset and the last one ends the file
+        let mut pos = 12;
+        let mut kinds = Vec::new();
+        while pos < bytes.len() {
+            assert_eq!(pos % 2, 0);
+            let length = read_u32(&bytes, pos + 4, false) as usize;
+            kinds.push(bytes[pos..pos + 4].to_vec());
+            if length % 2 == 1 {
+                assert_eq!(bytes[pos + 8 + length], 0);
+            }
+            pos += 8 + length + length % 2;
+        }
+        assert_eq!(pos, bytes.len());
+
+        assert_eq!(kinds.first().map(Vec::as_slice), Some(&b"VP8X"[..]));
+        assert_eq!(kinds[1], b"ICCP");
+        assert_eq!(kinds[kinds.len() - 2], b"EXIF");
+        assert_eq!(kinds[kinds.len() - 1], b"XMP ");
+
+        // The flags of the profile, EXIF and XMP, and the canvas size minus one
+        let header = &bytes[20..30];
+        assert_eq!(header[0], 0x20 | 0x08 | 0x04);
+        assert_eq!(&header[4..10], &[2, 0, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn orientation_is_read_and_applied() {
+        for orientation in 1..=8 {
+            let metadata = ImageMetadata {
+                exif: Some(exif(orientation)),
+                ..Default::default()
+            };
+            assert_eq!(metadata.orientation(), Some(orientation));
+        }
+
+        let invalid = ImageMetadata {
+            exif: Some(exif(9)),
+            ..Default::default()
+        };
+        assert_eq!(invalid.orientation(), None);
+
+        // The stored left column becomes the top row when rotated clockwise
+        let rotated = apply_orientation(image(), 6).to_rgb8();
+        assert_eq!(rotated.dimensions(), (2, 3));
+        assert_eq!(rotated.get_pixel(0, 0), &Rgb([0, 200, 50]));
+        assert_eq!(rotated.get_pixel(1, 0), &Rgb([0, 0, 50]));
+        assert_eq!(rotated.get_pixel(1, 2), &Rgb([200, 0, 50]));
+
+        let mirrored = apply_orientation(image(), 2).to_rgb8();
+        assert_eq!(mirrored.get_pixel(0, 0), &Rgb([200, 0, 50]));
+    }
+}
@@ -0,0 +1,228 @@
+use crate::cudaimg::{ImageOperation, ImageProcessingFunction};
+use std::ops::RangeInclusive;
+
+/// Description of a parameter of an operation.
+///
+/// # Fields
+///
+/// * `name` - The label of the parameter's slider.
+/// * `range` - The range of values the slider allows.
+/// * `default` - The initial value of the parameter.
+/// * `integer` - Whether the parameter only takes whole numbers.
+pub struct ParamSpec {
+    pub name: &'static str,
+    pub range: RangeInclusive<f32>,
+    pub default: f32,
+    pub integer: bool,
+}
+
+impl ParamSpec {
+    /// Get the key of the parameter in command line usage and query strings, e.g. `filter_size`.
+    pub fn key(&self) -> String {
+        self.name.to_lowercase().replace(' ', "_")
+    }
+
+    /// Parse a value of the parameter from text.
+    ///
+    /// # Returns
+    ///
+    /// * `None` if the text is not a number, or not a whole non-negative number for integers.
+    pub fn parse(&self, value: &str) -> Option<f32> {
+        let parsed: f32 = value.trim().parse().ok()?;
+
+        if self.integer && (parsed < 0.0 || parsed.fract() != 0.0) {
+            return None;
+        }
+
+        Some(parsed)
+    }
+}
+
+/// Description of an operation listed in the Tools menu.
+///
+/// # Fields
+///
+/// * `name` - The label of the operation in the menu.
+/// * `flag` - The name of the command line flag running the operation, without the leading `--`.
+/// * `description` - A short description shown when hovering the menu entry.
+/// * `operation` - The backend operation the entry runs.
+/// * `params` - The parameters of the operation, in the order `build` expects them.
+/// * `build` - Build the image processing function from the parameter values.
+pub struct OperationSpec {
+    pub name: &'static str,
+    pub flag: &'static str,
+    pub description: &'static str,
+    pub operation: ImageOperation,
+    pub params: &'static [ParamSpec],
+    pub build: fn(&[f32]) -> ImageProcessingFunction,
+}
+
+impl OperationSpec {
+    /// Get the default values of the operation's parameters.
+    pub fn default_values(&self) -> Vec<f32> {
+        self.params.iter().map(|param| param.default).collect()
+    }
+}
+
+/// Every operation, in the order they are listed in the Tools menu.
+/// Adding an operation to the menu only requires adding an entry here.
+pub const OPERATIONS: &[OperationSpec] = &[
+    OperationSpec {
+        name: "Invert image",
+        flag: "invert",
+        description: "Invert every channel of the image.",
+        operation: ImageOperation::Invert,
+        params: &[],
+        build: |_| ImageProcessingFunction::Invert,
+    },
+    OperationSpec {
+        name: "Gamma transformation",
+        flag: "gamma",
+        description: "Raise the normalized pixel values to the power of gamma.",
+        operation: ImageOperation::GammaTransform,
+        params: &[ParamSpec {
+            name: "Gamma",
+            range: 0.1..=5.0,
+            default: 2.2,
+            integer: false,
+        }],
+        build: |values| ImageProcessingFunction::GammaTransform(values[0]),
+    },
+    OperationSpec {
+        name: "Logarithmic transformation",
+        flag: "log",
+        description: "Map the pixel values through a logarithmic curve.",
+        operation: ImageOperation::LogarithmicTransform,
+        params: &[ParamSpec {
+            name: "Base",
+            range: 0.1..=10.0,
+            default: 2.0,
+            integer: false,
+        }],
+        build: |values| ImageProcessingFunction::LogarithmicTransform(values[0]),
+    },
+    OperationSpec {
+        name: "Grayscale conversion",
+        flag: "grayscale",
+        description: "Replace every pixel with its luminance.",
+        operation: ImageOperation::Grayscale,
+        params: &[],
+        build: |_| ImageProcessingFunction::Grayscale,
+    },
+    OperationSpec {
+        name: "Generate histogram",
+        flag: "histogram",
+        description: "Plot the histogram of the pixel values.",
+        operation: ImageOperation::ComputeHistogram,
+        params: &[],
+        build: |_| ImageProcessingFunction::ComputeHistogram,
+    },
+    OperationSpec {
+        name: "Balance histogram",
+        flag: "balance-histogram",
+        description: "Spread the pixel values over the full range (histogram equalization).",
+        operation: ImageOperation::BalanceHistogram,
+        params: &[],
+        build: |_| ImageProcessingFunction::BalanceHistogram,
+    },
+    OperationSpec {
+        name: "Box filter",
+        flag: "box-filter",
+        description: "Average every pixel with its neighbours.",
+        operation: ImageOperation::BoxFilter,
+        params: &[ParamSpec {
+            name: "Filter size",
+            range: 1.0..=80.0,
+            default: 3.0,
+            integer: true,
+        }],
+        build: |values| ImageProcessingFunction::BoxFilter(values[0] as u32),
+    },
+    OperationSpec {
+        name: "Gaussian blur",
+        flag: "gaussian",
+        description: "Blur the image with a Gaussian kernel.",
+        operation: ImageOperation::GaussianBlur,
+        params: &[ParamSpec {
+            name: "Sigma",
+            range: 0.1..=5.0,
+            default: 1.0,
+            integer: false,
+        }],
+        build: |values| ImageProcessingFunction::GaussianBlur(values[0]),
+    },
+    OperationSpec {
+        name: "Sobel edge detection",
+        flag: "sobel",
+        description: "Show the gradient magnitude of the luminance.",
+        operation: ImageOperation::SobelEdgeDetection,
+        params: &[],
+        build: |_| ImageProcessingFunction::SobelEdgeDetection,
+    },
+    OperationSpec {
+        name: "Laplace edge detection",
+        flag: "laplace",
+        description: "Show the Laplacian of the luminance.",
+        operation: ImageOperation::LaplaceEdgeDetection,
+        params: &[],
+        build: |_| ImageProcessingFunction::LaplaceEdgeDetection,
+    },
+    OperationSpec {
+        name: "Harris corner detection",
+        flag: "harris",
+        description: "Convert to grayscale, blur with sigma 1 and mark the Harris corners.",
+        operation: ImageOperation::HarrisCornerDetection,
+        params: &[],
+        build: |_| ImageProcessingFunction::HarrisCornerDetection,
+    },
+];
+
+/// Get the registry entry of an operation.
+///
+/// # Arguments
+///
+/// * `operation` - The operation to look up, every operation has an entry.
+pub fn spec(operation: ImageOperation) -> &'static OperationSpec {
+    OPERATIONS
+        .iter()
+        .find(|spec| spec.operation == operation)
+        .expect("every operation is listed in the registry")
+}
+
+/// Describe an image processing function with its parameters,
+/// e.g. `Gaussian blur (Sigma = 1.50)`.
+///
+/// # Arguments
+///
+/// * `function` - The image processing function to describe.
+pub fn describe(function: &ImageProcessingFunction) -> String {
+    let spec = spec(function.operation());
+
+    if spec.params.is_empty() {
+        return spec.name.to_string();
+    }
+
+    let params = spec
+        .params
+        .iter()
+        .zip(function.params())
+        .map(|(param, value)| {
+            if param.integer {
+                format!("{} = {}", param.name, value as u32)
+            } else {
+                format!("{} = {:.2}", param.name, value)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    format!("{} ({})", spec.name, params.join(", "))
+}
+
+/// Get the registry entry with a command line flag.
+///
+/// # Arguments
+///
+/// * `flag` - The name of the flag, without the leading `--`.
+pub fn find_by_flag(flag: &str) -> Option<&'static OperationSpec> {
+    OPERATIONS.iter().find(|spec| spec.flag == flag)
+}
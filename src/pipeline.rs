@@ -0,0 +1,105 @@
+use crate::backend::ImageBackend;
+use crate::cudaimg::{process_image, ImageProcessingFunction};
+use crate::operations;
+use anyhow::Context;
+use image::DynamicImage;
+
+/// A step of the processing pipeline.
+///
+/// # Fields
+///
+/// * `function` - The image processing function of the step, including its parameters.
+/// * `enabled` - Whether the step is applied when the pipeline runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineStep {
+    pub function: ImageProcessingFunction,
+    pub enabled: bool,
+}
+
+impl PipelineStep {
+    /// Get the name of the step's operation as listed in the Tools menu.
+    pub fn name(&self) -> &'static str {
+        operations::spec(self.function.operation()).name
+    }
+}
+
+/// A non-destructive stack of operations.
+/// The pipeline is always executed from the source image, so steps can be edited,
+/// reordered, disabled or removed without losing the original.
+///
+/// # Fields
+///
+/// * `steps` - The steps of the pipeline, in the order they are applied.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pipeline {
+    pub steps: Vec<PipelineStep>,
+}
+
+impl Pipeline {
+    /// Append an enabled step to the end of the pipeline.
+    ///
+    /// # Arguments
+    ///
+    /// * `function` - The image processing function of the step.
+    pub fn push(&mut self, function: ImageProcessingFunction) {
+        self.steps.push(PipelineStep {
+            function,
+            enabled: true,
+        });
+    }
+
+    /// Move a step one position towards the start of the pipeline.
+    pub fn move_up(&mut self, index: usize) {
+        if index > 0 && index < self.steps.len() {
+            self.steps.swap(index - 1, index);
+        }
+    }
+
+    /// Move a step one position towards the end of the pipeline.
+    pub fn move_down(&mut self, index: usize) {
+        if index + 1 < self.steps.len() {
+            self.steps.swap(index, index + 1);
+        }
+    }
+
+    /// Remove a step from the pipeline.
+    pub fn remove(&mut self, index: usize) {
+        if index < self.steps.len() {
+            self.steps.remove(index);
+        }
+    }
+
+    /// Check whether running the pipeline would change the image.
+    pub fn has_enabled_steps(&self) -> bool {
+        self.steps.iter().any(|step| step.enabled)
+    }
+
+    /// Run the enabled steps on the source image, in order.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The backend to use for image processing.
+    /// * `source` - The image the pipeline starts from, it is not modified.
+    ///
+    /// # Returns
+    ///
+    /// * The image produced by the last enabled step, or a copy of the source without any.
+    pub fn run(
+        &self,
+        backend: &dyn ImageBackend,
+        source: &DynamicImage,
+    ) -> anyhow::Result<DynamicImage> {
+        let mut image = source.clone();
+
+        for (i, step) in self.steps.iter().enumerate() {
+            if !step.enabled {
+                continue;
+            }
+
+            image = process_image(backend, &image, step.function)
+                .with_context(|| format!("Step {} ({}) failed", i + 1, step.name()))?;
+        }
+
+        Ok(image)
+    }
+}
@@ -0,0 +1,353 @@
+use crate::cudaimg::ImageProcessingFunction;
+use crate::pipeline::{Pipeline, PipelineStep};
+use anyhow::Context;
+use serde::{Deserialize, Serialize, Serializer};
+use std::path::Path;
+
+/// The version of the recipe schema written by this build.
+/// Bump it when an existing operation or parameter changes its meaning,
+/// adding a new operation does not require a new version.
+pub const RECIPE_VERSION: u32 = 1;
+
+/// The file formats recipes can be stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecipeFormat {
+    Json,
+    Toml,
+}
+
+impl RecipeFormat {
+    /// Pick the format from the extension of a file, recipes default to JSON.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("toml") => RecipeFormat::Toml,
+            _ => RecipeFormat::Json,
+        }
+    }
+}
+
+/// An operation of a recipe with its named parameters.
+///
+/// The names are part of the recipe schema and must not change,
+/// so this is kept separate from [`ImageProcessingFunction`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum RecipeOperation {
+    Invert,
+    GammaTransform {
+        #[serde(serialize_with = "serialize_param")]
+        gamma: f32,
+    },
+    LogarithmicTransform {
+        #[serde(serialize_with = "serialize_param")]
+        base: f32,
+    },
+    Grayscale,
+    BalanceHistogram,
+    BoxFilter {
+        filter_size: u32,
+    },
+    GaussianBlur {
+        #[serde(serialize_with = "serialize_param")]
+        sigma: f32,
+    },
+    SobelEdgeDetection,
+    LaplaceEdgeDetection,
+    HarrisCornerDetection,
+}
+
+/// Serialize a parameter as the shortest decimal that reads back as the same `f32`,
+/// so `2.2` is not written as `2.200000047683716` by formats that only know `f64`.
+fn serialize_param<S: Serializer>(value: &f32, serializer: S) -> Result<S::Ok, S::Error> {
+    let value = value.to_string().parse().unwrap_or(f64::from(*value));
+    serializer.serialize_f64(value)
+}
+
+impl RecipeOperation {
+    /// Convert an image processing function to a recipe operation.
+    ///
+    /// # Returns
+    ///
+    /// * `None` if the function does not modify the image, so it cannot be part of a recipe.
+    pub fn from_function(function: ImageProcessingFunction) -> Option<Self> {
+        let operation = match function {
+            ImageProcessingFunction::Invert => RecipeOperation::Invert,
+            ImageProcessingFunction::GammaTransform(gamma) => {
+                RecipeOperation::GammaTransform { gamma }
+            }
+            ImageProcessingFunction::LogarithmicTransform(base) => {
+                RecipeOperation::LogarithmicTransform { base }
+            }
+            ImageProcessingFunction::Grayscale => RecipeOperation::Grayscale,
+            ImageProcessingFunction::ComputeHistogram => return None,
+            ImageProcessingFunction::BalanceHistogram => RecipeOperation::BalanceHistogram,
+            ImageProcessingFunction::BoxFilter(filter_size) => {
+                RecipeOperation::BoxFilter { filter_size }
+            }
+            ImageProcessingFunction::GaussianBlur(sigma) => RecipeOperation::GaussianBlur { sigma },
+            ImageProcessingFunction::SobelEdgeDetection => RecipeOperation::SobelEdgeDetection,
+            ImageProcessingFunction::LaplaceEdgeDetection => RecipeOperation::LaplaceEdgeDetection,
+            ImageProcessingFunction::HarrisCornerDetection => {
+                RecipeOperation::HarrisCornerDetection
+            }
+        };
+
+        Some(operation)
+    }
+
+    /// Convert the recipe operation to the image processing function it runs.
+    pub fn to_function(self) -> ImageProcessingFunction {
+        match self {
+            RecipeOperation::Invert => ImageProcessingFunction::Invert,
+            RecipeOperation::GammaTransform { gamma } => {
+                ImageProcessingFunction::GammaTransform(gamma)
+            }
+            RecipeOperation::LogarithmicTransform { base } => {
+                ImageProcessingFunction::LogarithmicTransform(base)
+            }
+            RecipeOperation::Grayscale => ImageProcessingFunction::Grayscale,
+            RecipeOperation::BalanceHistogram => ImageProcessingFunction::BalanceHistogram,
+            RecipeOperation::BoxFilter { filter_size } => {
+                ImageProcessingFunction::BoxFilter(filter_size)
+            }
+            RecipeOperation::GaussianBlur { sigma } => ImageProcessingFunction::GaussianBlur(sigma),
+            RecipeOperation::SobelEdgeDetection => ImageProcessingFunction::SobelEdgeDetection,
+            RecipeOperation::LaplaceEdgeDetection => ImageProcessingFunction::LaplaceEdgeDetection,
+            RecipeOperation::HarrisCornerDetection => {
+                ImageProcessingFunction::HarrisCornerDetection
+            }
+        }
+    }
+}
+
+/// A step of a recipe.
+///
+/// # Fields
+///
+/// * `operation` - The operation of the step and its parameters.
+/// * `enabled` - Whether the step is applied, steps are enabled unless stated otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RecipeStep {
+    #[serde(flatten)]
+    pub operation: RecipeOperation,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// A serializable list of processing steps that can be shared and re-applied to other images.
+///
+/// # Fields
+///
+/// * `version` - The version of the recipe schema.
+/// * `steps` - The steps of the recipe, in the order they are applied.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Recipe {
+    pub version: u32,
+    #[serde(default)]
+    pub steps: Vec<RecipeStep>,
+}
+
+impl Recipe {
+    /// Create a recipe from the steps of a pipeline.
+    pub fn from_pipeline(pipeline: &Pipeline) -> Self {
+        let steps = pipeline
+            .steps
+            .iter()
+            .filter_map(|step| {
+                RecipeOperation::from_function(step.function).map(|operation| RecipeStep {
+                    operation,
+                    enabled: step.enabled,
+                })
+            })
+            .collect();
+
+        Self {
+            version: RECIPE_VERSION,
+            steps,
+        }
+    }
+
+    /// Create a pipeline running the steps of the recipe.
+    pub fn to_pipeline(&self) -> Pipeline {
+        let steps = self
+            .steps
+            .iter()
+            .map(|step| PipelineStep {
+                function: step.operation.to_function(),
+                enabled: step.enabled,
+            })
+            .collect();
+
+        Pipeline { steps }
+    }
+
+    /// Parse a recipe and check that its version is supported.
+    ///
+    /// # Arguments
+    ///
+    /// * `contents` - The serialized recipe.
+    /// * `format` - The format of the serialized recipe.
+    pub fn parse(contents: &str, format: RecipeFormat) -> anyhow::Result<Self> {
+        let recipe: Recipe = match format {
+            RecipeFormat::Json => serde_json::from_str(contents)?,
+            RecipeFormat::Toml => toml::from_str(contents)?,
+        };
+
+        if recipe.version == 0 || recipe.version > RECIPE_VERSION {
+            anyhow::bail!(
+                "Unsupported recipe version {}, this build supports versions 1 to {}",
+                recipe.version,
+                RECIPE_VERSION
+            );
+        }
+
+        Ok(recipe)
+    }
+
+    /// Serialize the recipe.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format to serialize the recipe to.
+    pub fn serialize(&self, format: RecipeFormat) -> anyhow::Result<String> {
+        let contents = match format {
+            RecipeFormat::Json => serde_json::to_string_pretty(self)?,
+            RecipeFormat::Toml => toml::to_string_pretty(self)?,
+        };
+
+        Ok(contents)
+    }
+
+    /// Load a recipe from a file, the format is picked from the file's extension.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        Self::parse(&contents, RecipeFormat::from_path(path))
+            .with_context(|| format!("Invalid recipe {}", path.display()))
+    }
+
+    /// Save the recipe to a file, the format is picked from the file's extension.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = self.serialize(RecipeFormat::from_path(path))?;
+
+        std::fs::write(path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A recipe with every operation, the last one disabled.
+    fn recipe() -> Recipe {
+        let operations = [
+            RecipeOperation::Invert,
+            RecipeOperation::GammaTransform { gamma: 2.2 },
+            RecipeOperation::LogarithmicTransform { base: 10.0 },
+            RecipeOperation::Grayscale,
+            RecipeOperation::BalanceHistogram,
+            RecipeOperation::BoxFilter { filter_size: 4 },
+            RecipeOperation::GaussianBlur { sigma: 1.5 },
+            RecipeOperation::SobelEdgeDetection,
+            RecipeOperation::LaplaceEdgeDetection,
+            RecipeOperation::HarrisCornerDetection,
+        ];
+        let count = operations.len();
+
+        Recipe {
+            version: RECIPE_VERSION,
+            steps: operations
+                .into_iter()
+                .enumerate()
+                .map(|(i, operation)| RecipeStep {
+                    operation,
+                    enabled: i + 1 < count,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn recipes_round_trip() {
+        // The format and how it writes the gamma of the recipe
+        let cases = [
+            (RecipeFormat::Json, "\"gamma\": 2.2,"),
+            (RecipeFormat::Toml, "gamma = 2.2\n"),
+        ];
+
+        for (format, gamma) in cases {
+            let contents = recipe().serialize(format).unwrap();
+
+            assert!(contents.contains(gamma), "{:?}", format);
+            assert_eq!(Recipe::parse(&contents, format).unwrap(), recipe());
+        }
+    }
+
+    #[test]
+    fn pipeline_round_trips() {
+        let pipeline = recipe().to_pipeline();
+
+        assert_eq!(Recipe::from_pipeline(&pipeline), recipe());
+    }
+
+    #[test]
+    fn steps_are_enabled_by_default() {
+        let cases = [
+            (
+                RecipeFormat::Json,
+                r#"{ "version": 1, "steps": [{ "operation": "box_filter", "filter_size": 3 }] }"#,
+                RecipeOperation::BoxFilter { filter_size: 3 },
+            ),
+            (
+                RecipeFormat::Toml,
+                "version = 1\n[[steps]]\noperation = \"gaussian_blur\"\nsigma = 2\n",
+                RecipeOperation::GaussianBlur { sigma: 2.0 },
+            ),
+        ];
+
+        for (format, contents, operation) in cases {
+            let recipe = Recipe::parse(contents, format).unwrap();
+            assert_eq!(
+                recipe.steps,
+                [RecipeStep {
+                    operation,
+                    enabled: true,
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        for version in [0, RECIPE_VERSION + 1] {
+            let cases = [
+                (
+                    RecipeFormat::Json,
+                    format!(r#"{{ "version": {version}, "steps": [] }}"#),
+                ),
+                (
+                    RecipeFormat::Toml,
+                    format!("version = {version}\nsteps = []\n"),
+                ),
+            ];
+
+            for (format, contents) in cases {
+                let error = Recipe::parse(&contents, format).unwrap_err();
+                assert!(error.to_string().contains("Unsupported recipe version"));
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_operations_are_rejected() {
+        let json = r#"{ "version": 1, "steps": [{ "operation": "sharpen" }] }"#;
+
+        assert!(Recipe::parse(json, RecipeFormat::Json).is_err());
+    }
+}
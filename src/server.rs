@@ -0,0 +1,448 @@
+use crate::backend::ImageBackend;
+use crate::cudaimg::{process_image, CudaImgError, ImageProcessingFunction};
+use crate::encoding::{self, EncoderOptions};
+use crate::formats;
+use crate::histogram;
+use crate::metadata::{self, ImageMetadata};
+use crate::operations;
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use image::{DynamicImage, ImageError, ImageFormat};
+use log::info;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// The default address the server listens on, only reachable from the local machine.
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8080";
+
+/// The default maximum size of a request body, in bytes.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 32 * 1024 * 1024;
+
+/// The default maximum width and height of an uploaded image, in pixels.
+pub const DEFAULT_MAX_IMAGE_SIZE: u32 = 16384;
+
+/// The default maximum memory the decoder may allocate for an uploaded image, in bytes.
+pub const DEFAULT_MAX_ALLOC: u64 = 512 * 1024 * 1024;
+
+/// The options of the HTTP server.
+///
+/// # Fields
+///
+/// * `addr` - The address the server listens on.
+/// * `max_body_size` - The maximum size of a request body in bytes, larger requests are rejected.
+/// * `max_image_size` - The maximum width and height of an uploaded image in pixels.
+/// * `max_alloc` - The maximum memory the decoder may allocate for an uploaded image in bytes.
+/// * `max_concurrency` - The number of images processed at a time, other requests wait.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerOptions {
+    pub addr: SocketAddr,
+    pub max_body_size: usize,
+    pub max_image_size: u32,
+    pub max_alloc: u64,
+    pub max_concurrency: usize,
+}
+
+/// The state shared between the request handlers.
+///
+/// # Fields
+///
+/// * `backend` - The backend to use for image processing.
+/// * `permits` - Limits the number of images processed at a time.
+/// * `limits` - The largest images the decoder accepts.
+#[derive(Clone)]
+struct ServerState {
+    backend: Arc<dyn ImageBackend>,
+    permits: Arc<Semaphore>,
+    limits: image::Limits,
+}
+
+/// An error returned to the client as a JSON body, e.g. `{"error": "..."}`.
+///
+/// # Fields
+///
+/// * `status` - The HTTP status of the response.
+/// * `message` - The description of the error.
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    /// Create an error caused by the request.
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+        }
+    }
+
+    /// Create an error from a failed operation.
+    /// Invalid parameters are reported as bad requests, anything else as a server error.
+    fn from_processing(error: anyhow::Error) -> Self {
+        let status = match error.downcast_ref::<CudaImgError>() {
+            Some(CudaImgError::InvalidArgument(_)) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        Self {
+            status,
+            message: format!("{:#}", error),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error: self.message,
+        };
+
+        (self.status, Json(body)).into_response()
+    }
+}
+
+#[derive(Serialize)]
+struct HealthBody {
+    status: &'static str,
+    backend: String,
+}
+
+/// The histograms of an image, with 256 bins each.
+///
+/// # Fields
+///
+/// * `counts` - The counts of the red, green and blue channels added together.
+/// * `red`, `green`, `blue`, `luma` - The counts of each channel.
+#[derive(Serialize)]
+struct HistogramBody {
+    counts: Vec<u32>,
+    red: Vec<u32>,
+    green: Vec<u32>,
+    blue: Vec<u32>,
+    luma: Vec<u32>,
+}
+
+/// Decode the image of a request body, rotated upright according to its EXIF orientation.
+/// Images exceeding the limits are rejected with `413 Payload Too Large` before their pixels
+/// are allocated, so small files declaring huge dimensions can not exhaust the memory.
+fn decode(
+    body: &[u8],
+    limits: image::Limits,
+) -> Result<(DynamicImage, ImageMetadata, ImageFormat), ApiError> {
+    let format = image::guess_format(body)
+        .map_err(|e| ApiError::bad_request(format!("Unknown image format: {}", e)))?;
+    let (image, metadata) =
+        metadata::decode_with_limits(body, format, limits).map_err(|e| match e {
+            ImageError::Limits(e) => ApiError {
+                status: StatusCode::PAYLOAD_TOO_LARGE,
+                message: format!("The image is too large: {}", e),
+            },
+            e => ApiError::bad_request(format!("Failed to decode the image: {}", e)),
+        })?;
+
+    Ok((image, metadata, format))
+}
+
+/// Build the image processing function of a `/process` request from its query.
+///
+/// The operation is selected with `op`, named like the flags of the command line tool,
+/// and its parameters are given by their keys, e.g. `?op=gaussian&sigma=1.5`.
+/// Missing parameters take their default value.
+fn function_from_query(
+    query: &HashMap<String, String>,
+) -> Result<ImageProcessingFunction, ApiError> {
+    let op = query
+        .get("op")
+        .ok_or_else(|| ApiError::bad_request("Missing the op query parameter"))?;
+    let spec = operations::find_by_flag(op)
+        .ok_or_else(|| ApiError::bad_request(format!("Unknown operation {}", op)))?;
+
+    if !spec.operation.modifies_image() {
+        return Err(ApiError::bad_request(format!(
+            "{} does not produce an image, use its own endpoint",
+            op
+        )));
+    }
+
+    let values = spec
+        .params
+        .iter()
+        .map(|param| match query.get(&param.key()) {
+            Some(value) => param.parse(value).ok_or_else(|| {
+                ApiError::bad_request(format!("Invalid {}: {}", param.key(), value))
+            }),
+            None => Ok(param.default),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((spec.build)(&values))
+}
+
+/// Wait for a processing permit before the body of a request is read,
+/// so the requests waiting for their turn do not hold their images in memory.
+async fn limit_concurrency(
+    State(state): State<ServerState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let _permit = state
+        .permits
+        .acquire()
+        .await
+        .expect("the semaphore is never closed");
+
+    next.run(request).await
+}
+
+/// Run a job on the blocking thread pool.
+async fn run_blocking<T: Send + 'static>(
+    job: impl FnOnce() -> Result<T, ApiError> + Send + 'static,
+) -> Result<T, ApiError> {
+    tokio::task::spawn_blocking(job)
+        .await
+        .map_err(|e| ApiError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: format!("Processing panicked: {}", e),
+        })?
+}
+
+/// `GET /health` - Report that the server is running and which backend it uses.
+async fn health(State(state): State<ServerState>) -> Json<HealthBody> {
+    Json(HealthBody {
+        status: "ok",
+        backend: state.backend.name().to_string(),
+    })
+}
+
+/// `POST /process?op=<operation>&<param>=<value>` - Process the image of the body.
+///
+/// The result is encoded in the format of the input, or in the one given with `format`,
+/// e.g. `&format=png`. Inputs in formats that can not be written are returned as PNG.
+/// The metadata of the input is kept unless `strip_metadata=true` is given.
+async fn process(
+    State(state): State<ServerState>,
+    Query(query): Query<HashMap<String, String>>,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let function = function_from_query(&query)?;
+    let output_format = match query.get("format") {
+        Some(ext) => Some(
+            ImageFormat::from_extension(ext)
+                .filter(|format| formats::is_writable(*format))
+                .ok_or_else(|| ApiError::bad_request(format!("Unsupported format {}", ext)))?,
+        ),
+        None => None,
+    };
+    let strip_metadata = query
+        .get("strip_metadata")
+        .is_some_and(|value| value == "true" || value == "1");
+
+    let backend = Arc::clone(&state.backend);
+    let limits = state.limits.clone();
+    let (bytes, format) = run_blocking(move || {
+        let (image, mut metadata, input_format) = decode(&body, limits)?;
+        if strip_metadata {
+            metadata = ImageMetadata::default();
+        }
+
+        let format = match output_format {
+            Some(format) => format,
+            None if formats::is_writable(input_format) => input_format,
+            None => ImageFormat::Png,
+        };
+
+        let start = std::time::Instant::now();
+        let image =
+            process_image(backend.as_ref(), &image, function).map_err(ApiError::from_processing)?;
+        info!(
+            "Processed {} in {:?}",
+            operations::describe(&function),
+            start.elapsed()
+        );
+
+        let bytes = encoding::encode(&image, format, &EncoderOptions::default(), &metadata)
+            .map_err(ApiError::from_processing)?;
+
+        Ok((bytes, format))
+    })
+    .await?;
+
+    Ok(([(header::CONTENT_TYPE, format.to_mime_type())], bytes).into_response())
+}
+
+/// `POST /histogram` - Compute the histograms of the channels of the image of the body.
+async fn histogram(
+    State(state): State<ServerState>,
+    body: Bytes,
+) -> Result<Json<HistogramBody>, ApiError> {
+    let backend = Arc::clone(&state.backend);
+    let limits = state.limits.clone();
+
+    let histograms = run_blocking(move || {
+        let (image, _, _) = decode(&body, limits)?;
+
+        histogram::image_histograms(backend.as_ref(), &image).map_err(ApiError::from_processing)
+    })
+    .await?;
+
+    Ok(Json(HistogramBody {
+        counts: histograms.combined(),
+        red: histograms.red.data,
+        green: histograms.green.data,
+        blue: histograms.blue.data,
+        luma: histograms.luma.data,
+    }))
+}
+
+/// Build the routes of the server.
+fn router(backend: Arc<dyn ImageBackend>, options: &ServerOptions) -> Router {
+    let mut limits = image::Limits::default();
+    limits.max_image_width = Some(options.max_image_size);
+    limits.max_image_height = Some(options.max_image_size);
+    limits.max_alloc = Some(options.max_alloc);
+
+    let state = ServerState {
+        backend,
+        permits: Arc::new(Semaphore::new(options.max_concurrency.max(1))),
+        limits,
+    };
+
+    routes(state, options.max_body_size)
+}
+
+/// Build the routes of the server with its state.
+/// The requests carrying an image wait for a permit before their body is read.
+fn routes(state: ServerState, max_body_size: usize) -> Router {
+    let processing = Router::new()
+        .route("/process", post(process))
+        .route("/histogram", post(histogram))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            limit_concurrency,
+        ));
+
+    Router::new()
+        .route("/health", get(health))
+        .merge(processing)
+        .layer(DefaultBodyLimit::max(max_body_size))
+        .with_state(state)
+}
+
+/// Serve the image processing operations over HTTP until Ctrl+C is pressed.
+///
+/// # Arguments
+///
+/// * `backend` - The backend to use for image processing.
+/// * `options` - The options of the server.
+pub async fn serve(backend: Arc<dyn ImageBackend>, options: &ServerOptions) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind(options.addr).await?;
+    info!("Listening on http://{}", listener.local_addr()?);
+
+    axum::serve(listener, router(backend, options))
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tower::ServiceExt;
+
+    /// Encode a black grayscale PNG image.
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = DynamicImage::ImageLuma8(image::GrayImage::new(width, height));
+        encoding::encode(
+            &image,
+            ImageFormat::Png,
+            &EncoderOptions::default(),
+            &ImageMetadata::default(),
+        )
+        .unwrap()
+    }
+
+    /// Build the limits of the decoder.
+    fn limits(max_image_size: u32, max_alloc: u64) -> image::Limits {
+        let mut limits = image::Limits::default();
+        limits.max_image_width = Some(max_image_size);
+        limits.max_image_height = Some(max_image_size);
+        limits.max_alloc = Some(max_alloc);
+        limits
+    }
+
+    #[test]
+    fn decode_accepts_images_within_the_limits() {
+        let (image, _, format) = decode(&png(16, 8), limits(16, 1024)).ok().unwrap();
+        assert_eq!(
+            (image.width(), image.height(), format),
+            (16, 8, ImageFormat::Png)
+        );
+    }
+
+    #[test]
+    fn decode_rejects_images_exceeding_the_limits() {
+        let body = png(DEFAULT_MAX_IMAGE_SIZE + 1, 1);
+        assert!(body.len() < 1024);
+
+        let error = decode(&body, limits(DEFAULT_MAX_IMAGE_SIZE, DEFAULT_MAX_ALLOC)).err();
+        assert_eq!(error.map(|e| e.status), Some(StatusCode::PAYLOAD_TOO_LARGE));
+
+        let error = decode(&png(64, 64), limits(64, 1024)).err();
+        assert_eq!(error.map(|e| e.status), Some(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    #[test]
+    fn decode_rejects_invalid_images() {
+        let error = decode(b"not an image", limits(64, 1024)).err();
+        assert_eq!(error.map(|e| e.status), Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn requests_wait_for_a_permit_before_reading_the_body() {
+        let state = ServerState {
+            backend: Arc::new(crate::cpuimg::CpuBackend),
+            permits: Arc::new(Semaphore::new(1)),
+            limits: limits(64, 1024 * 1024),
+        };
+        let permit = Arc::clone(&state.permits).acquire_owned().await.unwrap();
+
+        // The body records when it is first read
+        let read = Arc::new(AtomicBool::new(false));
+        let body = {
+            let read = Arc::clone(&read);
+            axum::body::Body::from_stream(stream::once(async move {
+                read.store(true, Ordering::SeqCst);
+                Ok::<_, std::convert::Infallible>(Bytes::from(png(4, 4)))
+            }))
+        };
+        let request = Request::post("/process?op=invert").body(body).unwrap();
+        let response = tokio::spawn(routes(state, DEFAULT_MAX_BODY_SIZE).oneshot(request));
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!read.load(Ordering::SeqCst));
+        assert!(!response.is_finished());
+
+        drop(permit);
+        let response = response.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(read.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,51 @@
+//! Builders of the images the tests of the modules share.
+
+use image::{DynamicImage, GrayImage, ImageFormat, Rgb, RgbImage};
+use std::io::Cursor;
+
+/// Build a grayscale image from its rows.
+///
+/// # Arguments
+///
+/// * `width` - The width of the image.
+/// * `height` - The height of the image.
+/// * `pixels` - One sample per pixel, by rows.
+pub fn gray(width: u32, height: u32, pixels: &[u8]) -> DynamicImage {
+    DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, pixels.to_vec()).unwrap())
+}
+
+/// Build an RGB image from its rows.
+///
+/// # Arguments
+///
+/// * `width` - The width of the image.
+/// * `height` - The height of the image.
+/// * `pixels` - Three samples per pixel, by rows.
+pub fn rgb(width: u32, height: u32, pixels: &[u8]) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, pixels.to_vec()).unwrap())
+}
+
+/// Build an RGB image with the same color in every pixel.
+///
+/// # Arguments
+///
+/// * `width` - The width of the image.
+/// * `height` - The height of the image.
+/// * `color` - The color of every pixel.
+pub fn constant_rgb(width: u32, height: u32, color: [u8; 3]) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb(color)))
+}
+
+/// Encode an image without any metadata.
+///
+/// # Arguments
+///
+/// * `image` - The image to encode.
+/// * `format` - The format to encode the image in.
+pub fn encode(image: &DynamicImage, format: ImageFormat) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut bytes), format)
+        .unwrap();
+    bytes
+}
@@ -0,0 +1,354 @@
+use crate::backend::ImageBackend;
+use crate::batch::{self, BatchFileResult};
+use crate::pipeline::Pipeline;
+use anyhow::Context;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// The name of the file in the output directory recording the images that were handled.
+pub const STATE_FILE_NAME: &str = ".img-utils-watch.json";
+
+/// The name of the default log file in the output directory.
+pub const LOG_FILE_NAME: &str = "img-utils-watch.log";
+
+/// The default time between two scans of the watched directory.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The size and modification time of a file, used to notice when it changes.
+///
+/// # Fields
+///
+/// * `len` - The size of the file in bytes.
+/// * `modified_secs` - The seconds of the modification time since the Unix epoch.
+/// * `modified_nanos` - The nanoseconds of the modification time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub len: u64,
+    pub modified_secs: u64,
+    pub modified_nanos: u32,
+}
+
+impl FileStamp {
+    /// Read the stamp of a file.
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Ok(Self {
+            len: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+        })
+    }
+}
+
+/// The images of the watched directory that were already handled, successfully or not.
+/// It is stored in the output directory, so restarting the watch does not process them again.
+///
+/// # Fields
+///
+/// * `handled` - The stamp of every handled image when it was processed, by file name.
+/// * `processed` - The number of images handled so far, including changed images handled again.
+///   The `{index}` of the next image is one more, so results are never overwritten.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WatchState {
+    #[serde(default)]
+    pub handled: BTreeMap<String, FileStamp>,
+    #[serde(default)]
+    pub processed: usize,
+}
+
+impl WatchState {
+    /// Load the state from a file, a missing file is an empty state.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut state: Self = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid watch state {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+
+        // States written before the counter was recorded handled at least these images
+        state.processed = state.processed.max(state.handled.len());
+
+        Ok(state)
+    }
+
+    /// Save the state to a file.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+
+        std::fs::write(path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// The options of a watched directory.
+///
+/// # Fields
+///
+/// * `input_dir` - The directory watched for new or changed images.
+/// * `output_dir` - The directory the results, the state and the default log are written to.
+/// * `name_template` - The template of the output file names, see [`batch::output_name`].
+/// * `jobs` - The number of images processed at a time.
+/// * `poll_interval` - The time between two scans of the watched directory.
+/// * `log_file` - The file every handled image is logged to.
+/// * `strip_metadata` - Whether to drop the EXIF, XMP and ICC metadata of the images.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchOptions {
+    pub input_dir: PathBuf,
+    pub output_dir: PathBuf,
+    pub name_template: String,
+    pub jobs: usize,
+    pub poll_interval: Duration,
+    pub log_file: PathBuf,
+    pub strip_metadata: bool,
+}
+
+/// An append-only log of the handled images.
+struct WatchLog {
+    file: File,
+}
+
+impl WatchLog {
+    /// Open the log file for appending, creating it if it is missing.
+    fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open the log {}", path.display()))?;
+
+        Ok(Self { file })
+    }
+
+    /// Write a timestamped line to the log and to the logger.
+    fn write(&mut self, message: &str) {
+        let timestamp = humantime::format_rfc3339_seconds(SystemTime::now());
+
+        if let Err(e) = writeln!(self.file, "{} {}", timestamp, message) {
+            error!("Failed to write the watch log: {}", e);
+        }
+    }
+
+    /// Log the result of an image.
+    fn write_result(&mut self, result: &BatchFileResult) {
+        match &result.outcome {
+            Ok(duration) => {
+                info!("Processed {}", result.input.display());
+                self.write(&format!(
+                    "ok {} -> {} ({:.3?})",
+                    result.input.display(),
+                    result.output.display(),
+                    duration
+                ));
+            }
+            Err(e) => {
+                warn!("Failed to process {}: {}", result.input.display(), e);
+                self.write(&format!("FAILED {}: {}", result.input.display(), e));
+            }
+        }
+    }
+}
+
+/// Watch a directory and run the pipeline on every new or changed image until Ctrl+C is pressed,
+/// which also interrupts a running scan.
+///
+/// The directory is scanned every `poll_interval`, which also works on network shares where
+/// change notifications are not delivered. An image is only processed once its size and
+/// modification time stayed the same between two scans, so files still being written are
+/// not picked up. Images that failed are not retried until they change.
+///
+/// # Arguments
+///
+/// * `backend` - The backend to use for image processing.
+/// * `pipeline` - The pipeline to run on every image.
+/// * `options` - The options of the watched directory.
+/// * `on_result` - Called with the result of every image as soon as it finishes.
+pub async fn watch(
+    backend: Arc<dyn ImageBackend>,
+    pipeline: Pipeline,
+    options: &WatchOptions,
+    mut on_result: impl FnMut(&BatchFileResult),
+) -> anyhow::Result<()> {
+    if !options.input_dir.is_dir() {
+        anyhow::bail!("{} is not a directory", options.input_dir.display());
+    }
+
+    std::fs::create_dir_all(&options.output_dir).with_context(|| {
+        format!(
+            "Failed to create the directory {}",
+            options.output_dir.display()
+        )
+    })?;
+
+    // Check the template before waiting for the first image
+    batch::output_name(&options.name_template, Path::new("image.png"), 1)?;
+
+    // The results would be picked up as new images otherwise
+    if options.input_dir.canonicalize()? == options.output_dir.canonicalize()? {
+        anyhow::bail!("The output directory can not be the watched directory");
+    }
+
+    let state_path = options.output_dir.join(STATE_FILE_NAME);
+    let mut state = WatchState::load(&state_path)?;
+    let mut log = WatchLog::open(&options.log_file)?;
+    let mut pending: BTreeMap<String, FileStamp> = BTreeMap::new();
+
+    log.write(&format!(
+        "Watching {} ({} images handled before)",
+        options.input_dir.display(),
+        state.handled.len()
+    ));
+
+    let mut interval = tokio::time::interval(options.poll_interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+
+        let ready = match scan(&options.input_dir, &state, &mut pending) {
+            Ok(ready) => ready,
+            Err(e) => {
+                // The share may be temporarily unavailable, try again on the next scan
+                log.write(&format!("Failed to scan: {:#}", e));
+                continue;
+            }
+        };
+
+        if ready.is_empty() {
+            continue;
+        }
+
+        // Failed images are recorded too, they are retried once they change
+        let mut files = Vec::with_capacity(ready.len());
+        let mut stamps = HashMap::with_capacity(ready.len());
+        for (name, stamp) in ready {
+            let input = options.input_dir.join(&name);
+            state.processed += 1;
+
+            match batch::output_name(&options.name_template, &input, state.processed) {
+                Ok(output) => {
+                    files.push((input.clone(), options.output_dir.join(output)));
+                    stamps.insert(input, (name, stamp));
+                }
+                Err(e) => {
+                    warn!("Failed to name the result of {}: {:#}", input.display(), e);
+                    log.write(&format!("FAILED {}: {:#}", input.display(), e));
+                    state.handled.insert(name, stamp);
+                }
+            }
+        }
+        save_state(&state, &state_path, &mut log);
+
+        // The state is saved after every image, so an interrupted scan only redoes
+        // the images that did not finish
+        let batch = batch::run_batch(
+            Arc::clone(&backend),
+            pipeline.clone(),
+            files,
+            options.jobs,
+            options.strip_metadata,
+            |result| {
+                log.write_result(result);
+                on_result(result);
+
+                if let Some((name, stamp)) = stamps.remove(&result.input) {
+                    state.handled.insert(name, stamp);
+                    save_state(&state, &state_path, &mut log);
+                }
+            },
+        );
+
+        let report = tokio::select! {
+            report = batch => report,
+            _ = tokio::signal::ctrl_c() => {
+                log.write("Interrupted, the unfinished images are processed again on restart");
+                break;
+            }
+        };
+
+        log.write(&format!(
+            "{} succeeded, {} failed in {:.3?}",
+            report.succeeded(),
+            report.failed(),
+            report.duration
+        ));
+    }
+
+    log.write("Stopped watching");
+
+    Ok(())
+}
+
+/// Save the state, logging a failure instead of stopping the watch.
+/// The state is kept in memory, so only a restart would handle the images again.
+fn save_state(state: &WatchState, path: &Path, log: &mut WatchLog) {
+    if let Err(e) = state.save(path) {
+        log.write(&format!("Failed to save the state: {:#}", e));
+    }
+}
+
+/// Scan the watched directory for images that are ready to be processed.
+///
+/// # Arguments
+///
+/// * `input_dir` - The watched directory.
+/// * `state` - The images that were already handled.
+/// * `pending` - The new or changed images seen on the previous scan, updated in place.
+///
+/// # Returns
+///
+/// * The file names and stamps of the images that did not change since the previous scan.
+fn scan(
+    input_dir: &Path,
+    state: &WatchState,
+    pending: &mut BTreeMap<String, FileStamp>,
+) -> anyhow::Result<Vec<(String, FileStamp)>> {
+    let mut ready = Vec::new();
+    let mut seen = BTreeMap::new();
+
+    let entries = std::fs::read_dir(input_dir)
+        .with_context(|| format!("Failed to read the directory {}", input_dir.display()))?;
+
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if !batch::is_image_file(&path) {
+            continue;
+        }
+
+        let (Some(name), Ok(stamp)) = (
+            path.file_name().and_then(|name| name.to_str()),
+            FileStamp::read(&path),
+        ) else {
+            continue;
+        };
+
+        if state.handled.get(name) == Some(&stamp) {
+            continue;
+        }
+
+        if pending.get(name) == Some(&stamp) {
+            ready.push((name.to_string(), stamp));
+        } else {
+            seen.insert(name.to_string(), stamp);
+        }
+    }
+
+    // Only the images still changing are kept for the next scan
+    *pending = seen;
+
+    Ok(ready)
+}